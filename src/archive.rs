@@ -1,37 +1,132 @@
+use std::collections::BTreeMap;
 use std::io::{Cursor, Read, Seek};
 
 use bytes::Bytes;
-use color_eyre::Result;
+use itertools::Itertools;
+use color_eyre::{eyre::eyre, Result};
 use url::Url;
 use zip::ZipArchive;
 
-use crate::client::client;
+use crate::client::{download_client, is_allowed_download_host};
+use crate::opts::{opts, TagNormalization};
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Tag {
     pub path: String,
     pub name: String,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Normalize a tag name so that tags differing only in case or spacing are
+/// treated as the same tag, according to `--tag-normalization`
+pub fn normalize_tag(name: &str) -> String {
+    match opts().tag_normalization {
+        TagNormalization::None => name.to_owned(),
+        TagNormalization::Lowercase => name.to_lowercase(),
+        TagNormalization::Slug => name
+            .to_lowercase()
+            .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+            .filter(|part| !part.is_empty())
+            .join("-"),
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Archive {
     pub id: u32,
     pub name: String,
-    pub artist: String,
+    /// Every credited artist, records from before multiple artists were kept
+    /// have a single `artist` string instead
+    #[serde(alias = "artist", deserialize_with = "one_or_many")]
+    pub artists: Vec<String>,
     pub parody: String,
     pub tags: Vec<Tag>,
     pub num_pages: u16,
     pub base_url: Url,
     pub download_url: Url,
+    /// Where the download actually ended up after following redirects
+    #[serde(default)]
+    pub final_download_url: Option<Url>,
+    /// Whether the downloaded zip matched the size the site gave for it
+    #[serde(default)]
+    pub verified: bool,
+    /// When the archive was first added, in unix seconds
+    #[serde(default)]
+    pub added_at: Option<u64>,
+    /// When the archive was last downloaded, in unix seconds
+    #[serde(default)]
+    pub last_fetched_at: Option<u64>,
+    /// The base url the archive was scraped from
+    #[serde(default)]
+    pub source_site: Option<Url>,
+    /// Name of the site the archive is from, ids are only unique within a site
+    #[serde(default)]
+    pub site: String,
+    /// User defined key/value metadata, set with `kscrpr meta set`
+    #[serde(default)]
+    pub custom: BTreeMap<String, String>,
+    /// The metadata JSON the site gave for the archive, stored separately
+    #[serde(skip)]
+    pub raw_metadata: Option<String>,
+}
+
+fn one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(
+        match <OneOrMany as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMany::One(artist) => vec![artist],
+            OneOrMany::Many(artists) => artists,
+        },
+    )
 }
 
+/// The downloaded zip wasn't the size the site said it would be
+#[derive(Debug)]
+pub struct SizeMismatch {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl std::fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Downloaded {} bytes but expected {} bytes",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for SizeMismatch {}
+
 impl Archive {
-    pub async fn download(&self, inspector: impl Fn(Option<u64>, &Bytes)) -> Result<ZipArchive<impl Read + Seek>> {
-        let mut body = client()
+    /// Download the archive's zip, checking it is `expected_size` bytes if given
+    pub async fn download(
+        &self,
+        expected_size: Option<u64>,
+        inspector: impl Fn(Option<u64>, &Bytes),
+    ) -> Result<(ZipArchive<impl Read + Seek>, Url)> {
+        if !is_allowed_download_host(&self.download_url) {
+            return Err(eyre!(
+                "Download url {} is not on an allowed host",
+                self.download_url
+            ));
+        }
+
+        let mut body = download_client()
             .get(self.download_url.as_str())
             .send()
             .await?;
 
+        let final_url = body.url().clone();
+
         let mut v = Vec::new();
 
         if let Some(size_hint) = body.content_length() {
@@ -43,12 +138,41 @@ impl Archive {
             inspector(body.content_length(), &buf);
         }
 
+        if let Some(expected) = expected_size {
+            if v.len() as u64 != expected {
+                return Err(SizeMismatch {
+                    expected,
+                    actual: v.len() as u64,
+                }
+                .into());
+            }
+        }
+
         let zip = zip::ZipArchive::new(Cursor::new(v))?;
 
-        Ok(zip)
+        Ok((zip, final_url))
+    }
+
+    pub fn effective_download_url(&self) -> &Url {
+        self.final_download_url.as_ref().unwrap_or(&self.download_url)
+    }
+
+    /// The site the archive is from, falling back to the host of its url for
+    /// archives fetched before sites were recorded
+    pub fn site(&self) -> &str {
+        if self.site.is_empty() {
+            self.base_url.host_str().unwrap_or_default()
+        } else {
+            &self.site
+        }
+    }
+
+    /// The credited artists, comma separated
+    pub fn artist(&self) -> String {
+        self.artists.join(", ")
     }
 
     pub fn pretty_single_line(&self) -> String {
-        format!("[{}] {}", self.artist, self.name)
+        format!("[{}] {}", self.artist(), self.name)
     }
 }
@@ -0,0 +1,551 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use color_eyre::Result;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use itertools::Itertools;
+use url::Url;
+
+use crate::archive::Archive;
+use crate::epub;
+use crate::filesystem::{open_page, page_files, FileSystem};
+use crate::opts::opts;
+use crate::pdf::{self, PdfWriter};
+use crate::utils::{fuck_error, user_has_quit};
+
+const THUMBNAIL_SIZE: u32 = 400;
+
+/// Contact sheets are laid out on A4
+const CONTACT_SHEET_PAGE: (f64, f64) = (595.28, 841.89);
+
+/// The tallest a PDF page can be in points, which viewers stop at
+const MAX_PDF_PAGE: f64 = 14400.0;
+
+pub fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Downscale the first page of an archive into `meta/thumbs/<id>.jpg`
+pub fn generate_thumbnail(fs: &FileSystem, archive: &Archive) -> Result<()> {
+    let first_page = match page_files(&fs.data_dir_of_id(archive.id)).into_iter().next() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    let thumb = open_page(&first_page)?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8();
+    thumb.save(fs.thumb_file_of_id(archive.id))?;
+
+    Ok(())
+}
+
+/// An archive's thumbnail ready for a contact sheet, generating it first if
+/// it's missing
+fn contact_sheet_thumbnail(fs: &FileSystem, archive: &Archive) -> Result<Option<pdf::Image>> {
+    let path = fs.thumb_file_of_id(archive.id);
+
+    if !path.exists() {
+        generate_thumbnail(fs, archive)?;
+
+        // archives without pages, i.e. evicted ones, don't get one
+        if !path.exists() {
+            return Ok(None);
+        }
+    }
+
+    let thumb = image::open(&path)?;
+    Ok(Some(pdf::Image::jpeg(&thumb, 85)?))
+}
+
+/// Write a PDF of a grid of the archives' thumbnails, each captioned with its
+/// name and id, calling `progress` with how many archives are done
+pub fn generate_contact_sheet(
+    fs: &FileSystem,
+    archives: &[Archive],
+    title: &str,
+    columns: usize,
+    destination: &Path,
+    progress: impl Fn(usize, usize),
+) -> Result<()> {
+    let (width, height) = CONTACT_SHEET_PAGE;
+    let margin = 28.0;
+    let cell_width = (width - 2.0 * margin) / columns as f64;
+    let size = (cell_width / 16.0).clamp(5.0, 10.0);
+    let leading = size * 1.2;
+    let padding = size / 2.0;
+    // two lines of name and one of id under thumbnails that are mostly portrait
+    let caption_height = 3.0 * leading;
+    let cell_height = cell_width * 1.4 + caption_height;
+    let rows = (((height - 2.0 * margin) / cell_height) as usize).max(1);
+    let image_box = (
+        cell_width - 2.0 * padding,
+        cell_height - caption_height - 2.0 * padding,
+    );
+
+    let mut writer = PdfWriter::new(BufWriter::new(File::create(destination)?))?;
+    let mut done = 0;
+
+    for (page, archives_on_page) in archives.chunks(columns * rows).enumerate() {
+        let mut images = vec![];
+        let mut contents = String::new();
+
+        for (i, archive) in archives_on_page.iter().enumerate() {
+            let x = margin + (i % columns) as f64 * cell_width + padding;
+            let top = height - margin - (i / columns) as f64 * cell_height - padding;
+
+            let thumb = contact_sheet_thumbnail(fs, archive).unwrap_or_else(|e| {
+                tracing::error!(
+                    error = fuck_error(&e),
+                    id = archive.id,
+                    "Failed to get thumbnail for the contact sheet, treating this as a non-fatal error though"
+                );
+                None
+            });
+            if let Some(thumb) = thumb {
+                let image = writer.add_image(&thumb)?;
+                let _ = writeln!(
+                    contents,
+                    "{}",
+                    image.draw_in(images.len(), (x, top - image_box.1), image_box)
+                );
+                images.push(image);
+            }
+
+            let mut lines = pdf::wrap(&archive.name, pdf::chars_in(image_box.0, size));
+            if lines.len() > 2 {
+                lines.truncate(2);
+                lines[1].push_str("...");
+            }
+            lines.push(format!("({})", archive.id));
+            for (j, line) in lines.iter().enumerate() {
+                let baseline = top - image_box.1 - (j + 1) as f64 * leading;
+                let _ = writeln!(contents, "{}", pdf::text((x, baseline), size, line));
+            }
+
+            done += 1;
+            progress(done, archives.len());
+        }
+
+        writer.add_drawn_page(
+            width,
+            height,
+            &images,
+            &contents,
+            Some(format!("Page {}", page + 1)),
+        )?;
+    }
+
+    let info = pdf::Info {
+        title: title.to_owned(),
+        author: String::new(),
+        subject: String::new(),
+        keywords: vec![],
+        identifier: String::new(),
+    };
+    writer.finish(&info, false)?.flush()?;
+
+    Ok(())
+}
+
+/// Where long strips are written, either pages of one PDF or numbered images
+/// in a directory
+#[derive(Clone, Copy)]
+pub enum StripOutput<'a> {
+    Pdf(&'a Path),
+    Images(&'a Path),
+}
+
+enum StripSink<'a> {
+    Pdf(PdfWriter<BufWriter<File>>),
+    Images(&'a Path),
+}
+
+impl StripSink<'_> {
+    /// Join `pages` top to bottom and write them out as the `number`th strip,
+    /// which starts at page `first_page` of the archive
+    fn write(&mut self, pages: Vec<RgbImage>, number: usize, first_page: usize) -> Result<()> {
+        let width = pages.iter().map(RgbImage::width).max().unwrap_or(1);
+        let height = pages.iter().map(RgbImage::height).sum();
+
+        let mut strip = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+        let mut y = 0;
+        for page in pages {
+            imageops::overlay(&mut strip, &page, 0, y);
+            y += page.height();
+        }
+        let strip = DynamicImage::ImageRgb8(strip);
+
+        match self {
+            StripSink::Pdf(writer) => {
+                let image = match opts().render_quality {
+                    Some(quality) => pdf::Image::jpeg(&strip, quality)?,
+                    None => pdf::Image::rgb(&strip)?,
+                };
+                let image = writer.add_image(&image)?;
+                writer.add_page(
+                    image,
+                    width as f64 * 72.0 / opts().render_dpi,
+                    height as f64 * 72.0 / opts().render_dpi,
+                    Some(format!("Page {first_page}")),
+                )?;
+            }
+            StripSink::Images(dir) => match opts().render_quality {
+                Some(quality) => {
+                    let path = dir.join(format!("{number:04}.jpg"));
+                    let mut file = BufWriter::new(File::create(path)?);
+                    strip.write_to(&mut file, ImageOutputFormat::Jpeg(quality))?;
+                }
+                None => strip.save(dir.join(format!("{number:04}.png")))?,
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Join an archive's pages top to bottom into strips no taller than
+/// `max_height` pixels, for vertical scrolling comics split into screenshots,
+/// calling `progress` with the pages done and the total pages
+///
+/// Pages are scaled to the width of the first and only split between, so a
+/// page taller than `max_height` gets a strip to itself. Returns `false` if
+/// the user quit part way through.
+pub fn generate_strips(
+    fs: &FileSystem,
+    archive: &Archive,
+    output: StripOutput,
+    max_height: u32,
+    progress: impl Fn(usize, usize),
+) -> Result<bool> {
+    let pages = page_files(&fs.data_dir_of_id(archive.id));
+
+    let (mut sink, max_height) = match output {
+        StripOutput::Pdf(destination) => {
+            let writer = PdfWriter::new(BufWriter::new(File::create(destination)?))?;
+            let max_pixels = (MAX_PDF_PAGE * opts().render_dpi / 72.0) as u32;
+            (StripSink::Pdf(writer), max_height.min(max_pixels))
+        }
+        StripOutput::Images(dir) => {
+            std::fs::create_dir_all(dir)?;
+            (StripSink::Images(dir), max_height)
+        }
+    };
+
+    let mut width = None;
+    let mut strip: Vec<RgbImage> = vec![];
+    let mut strip_height = 0;
+    let mut strips = 0;
+    // the number of the page the current strip starts at
+    let mut first_page = 1;
+
+    for (i, path) in pages.iter().enumerate() {
+        if user_has_quit() {
+            if let StripOutput::Pdf(destination) = output {
+                drop(sink);
+                let _ = std::fs::remove_file(destination);
+            }
+            return Ok(false);
+        }
+
+        let page = match open_page(path) {
+            Ok(page) => page,
+            Err(e) => {
+                tracing::error!(
+                    error = fuck_error(&e),
+                    id = archive.id,
+                    page = %path.display(),
+                    "Failed to decode page, leaving it out of the strip"
+                );
+                progress(i + 1, pages.len());
+                continue;
+            }
+        };
+        let width = *width.get_or_insert(page.width());
+        let page = if page.width() == width {
+            page.to_rgb8()
+        } else {
+            let height = page.height() as u64 * width as u64 / page.width() as u64;
+            imageops::resize(
+                &page.to_rgb8(),
+                width,
+                (height as u32).max(1),
+                FilterType::Lanczos3,
+            )
+        };
+
+        if !strip.is_empty() && strip_height + page.height() > max_height {
+            strips += 1;
+            sink.write(std::mem::take(&mut strip), strips, first_page)?;
+            strip_height = 0;
+            first_page = i + 1;
+        }
+        strip_height += page.height();
+        strip.push(page);
+
+        progress(i + 1, pages.len());
+    }
+    if !strip.is_empty() {
+        strips += 1;
+        sink.write(strip, strips, first_page)?;
+    }
+
+    if let StripSink::Pdf(writer) = sink {
+        let info = pdf::Info {
+            title: archive.name.clone(),
+            author: archive.artist(),
+            subject: String::new(),
+            keywords: archive.tags.iter().map(|t| t.name.clone()).collect(),
+            identifier: archive.base_url.to_string(),
+        };
+        writer.finish(&info, false)?.flush()?;
+    }
+
+    Ok(true)
+}
+
+/// A link to `path` from the gallery and catalog in `meta/`, relative to it as
+/// `--state-dir`, `--data-dir`, and `--xdg` can put the meta and rendered
+/// trees under different roots. Paths that can't be made relative, like
+/// those on another drive, are linked as `file://` urls
+fn href(fs: &FileSystem, path: &Path) -> String {
+    let absolute = |p: &Path| {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(p))
+            .unwrap_or_else(|_| p.to_owned())
+    };
+
+    let target = match Url::from_file_path(absolute(path)) {
+        Ok(target) => target,
+        Err(()) => return escape_xml(&path.display().to_string()),
+    };
+    let href = Url::from_directory_path(absolute(&fs.meta_dir()))
+        .ok()
+        .and_then(|base| base.make_relative(&target))
+        .unwrap_or_else(|| target.to_string());
+
+    escape_xml(&href)
+}
+
+fn html_fragment(fs: &FileSystem, archive: &Archive) -> String {
+    let mut html = String::new();
+
+    let _ = writeln!(html, r#"<figure class="archive" id="archive-{}">"#, archive.id);
+    let _ = writeln!(
+        html,
+        r#"  <a href="{rendered}"><img src="{thumb}" loading="lazy" alt="{name}"></a>"#,
+        // a split PDF is linked at its first part
+        rendered = href(fs, &fs.rendered_file_of_id(archive.id)),
+        thumb = href(fs, &fs.thumb_file_of_id(archive.id)),
+        name = escape_xml(&archive.name)
+    );
+    let _ = writeln!(
+        html,
+        r#"  <figcaption><b>{}</b> by {}<br><small>{}</small></figcaption>"#,
+        escape_xml(&archive.name),
+        escape_xml(&archive.artist()),
+        escape_xml(&archive.tags.iter().map(|t| t.name.as_str()).join(", "))
+    );
+    let _ = writeln!(html, "</figure>");
+
+    html
+}
+
+fn opds_entry(fs: &FileSystem, archive: &Archive) -> String {
+    let mut xml = String::new();
+
+    let _ = writeln!(xml, "  <entry>");
+    let _ = writeln!(xml, "    <title>{}</title>", escape_xml(&archive.name));
+    let _ = writeln!(xml, "    <id>urn:kscrpr:{}</id>", archive.id);
+    for artist in &archive.artists {
+        let _ = writeln!(
+            xml,
+            "    <author><name>{}</name></author>",
+            escape_xml(artist)
+        );
+    }
+    for tag in &archive.tags {
+        let _ = writeln!(xml, r#"    <category term="{}"/>"#, escape_xml(&tag.name));
+    }
+    let _ = writeln!(
+        xml,
+        r#"    <link rel="http://opds-spec.org/image/thumbnail" href="{}" type="image/jpeg"/>"#,
+        href(fs, &fs.thumb_file_of_id(archive.id))
+    );
+    // each part of a split PDF is a download of its own
+    for path in fs.rendered_files_of_id(archive.id) {
+        let mime = match path.extension().and_then(|e| e.to_str()) {
+            Some("cbz") => "application/vnd.comicbook+zip",
+            Some("epub") => epub::MIMETYPE,
+            _ => "application/pdf",
+        };
+        let _ = writeln!(
+            xml,
+            r#"    <link rel="http://opds-spec.org/acquisition" href="{}" type="{mime}"/>"#,
+            href(fs, &path)
+        );
+    }
+    let _ = writeln!(xml, "  </entry>");
+
+    xml
+}
+
+/// The ComicInfo.xml put in rendered CBZs, which comic readers take titles,
+/// credits, tags, and reading direction from
+pub fn comic_info(archive: &Archive, rtl: bool) -> String {
+    let mut xml = String::new();
+
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    let _ = writeln!(xml, "<ComicInfo>");
+    let _ = writeln!(xml, "  <Title>{}</Title>", escape_xml(&archive.name));
+    // "original" is what archives without a parody are given
+    if archive.parody != "original" {
+        let _ = writeln!(xml, "  <Series>{}</Series>", escape_xml(&archive.parody));
+    }
+    if !archive.artists.is_empty() {
+        let _ = writeln!(xml, "  <Writer>{}</Writer>", escape_xml(&archive.artist()));
+    }
+    let _ = writeln!(
+        xml,
+        "  <Tags>{}</Tags>",
+        escape_xml(&archive.tags.iter().map(|t| t.name.as_str()).join(","))
+    );
+    let _ = writeln!(xml, "  <PageCount>{}</PageCount>", archive.num_pages);
+    let _ = writeln!(xml, "  <Web>{}</Web>", escape_xml(archive.base_url.as_str()));
+    if rtl {
+        let _ = writeln!(xml, "  <Manga>YesAndRightToLeft</Manga>");
+    }
+    let _ = writeln!(xml, "</ComicInfo>");
+
+    xml
+}
+
+/// Concatenate every fragment in `dir`, in id order
+fn collect_fragments(dir: &Path) -> Result<String> {
+    let mut fragments = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let id = e.path().file_stem()?.to_str()?.parse::<u32>().ok()?;
+            Some((id, e.path()))
+        })
+        .collect_vec();
+    fragments.sort_by_key(|(id, _)| *id);
+
+    let mut out = String::new();
+    for (_, path) in fragments {
+        out.push_str(&std::fs::read_to_string(path)?);
+    }
+
+    Ok(out)
+}
+
+fn write_gallery(fs: &FileSystem) -> Result<()> {
+    let body = collect_fragments(&fs.html_fragment_dir())?;
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>kscrpr</title>
+<style>
+body {{ display: flex; flex-wrap: wrap; font-family: sans-serif; }}
+figure.archive {{ width: 220px; }}
+figure.archive img {{ max-width: 200px; }}
+</style>
+</head>
+<body>
+{body}</body>
+</html>
+"#
+    );
+
+    std::fs::write(fs.gallery_file(), html)?;
+
+    Ok(())
+}
+
+fn write_catalog(fs: &FileSystem) -> Result<()> {
+    let entries = collect_fragments(&fs.opds_fragment_dir())?;
+
+    let xml = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:opds="http://opds-spec.org/2010/catalog">
+  <id>urn:kscrpr:catalog</id>
+  <title>kscrpr</title>
+{entries}</feed>
+"#
+    );
+
+    std::fs::write(fs.opds_file(), xml)?;
+
+    Ok(())
+}
+
+/// Regenerate the thumbnail, HTML gallery fragment, and OPDS entry of every
+/// archive flagged in the `dirty` tree, then stitch the fragments back
+/// together into `meta/gallery.html` and `meta/opds.xml`
+///
+/// Returns the number of archives that were regenerated.
+pub fn regenerate(fs: &FileSystem) -> Result<usize> {
+    let mut regenerated = 0;
+
+    for key in fs.dirty.iter().keys() {
+        let key = key?;
+        let id = u32::from_be_bytes(key.as_ref().try_into()?);
+
+        let thumb = fs.thumb_file_of_id(id);
+        let html = fs.html_fragment_of_id(id);
+        let opds = fs.opds_fragment_of_id(id);
+
+        match fs.fetch_doc(id) {
+            Ok(archive) => {
+                let result = if thumb.exists() {
+                    Ok(())
+                } else {
+                    generate_thumbnail(fs, &archive)
+                };
+
+                if let Err(e) = result {
+                    tracing::error!(
+                        error = fuck_error(&e),
+                        id,
+                        "Failed to generate thumbnail, treating this as a non-fatal error though"
+                    );
+                }
+                std::fs::write(&html, html_fragment(fs, &archive))?;
+                std::fs::write(&opds, opds_entry(fs, &archive))?;
+            }
+            Err(_) => {
+                // the archive was removed
+                let _ = std::fs::remove_file(&thumb);
+                let _ = std::fs::remove_file(&html);
+                let _ = std::fs::remove_file(&opds);
+            }
+        }
+
+        fs.dirty.remove(&key)?;
+        regenerated += 1;
+    }
+
+    if regenerated > 0 || !fs.gallery_file().exists() || !fs.opds_file().exists() {
+        write_gallery(fs)?;
+        write_catalog(fs)?;
+    }
+
+    Ok(regenerated)
+}
@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+
+use color_eyre::{eyre::eyre, Help, Result};
+use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use itertools::Itertools;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, List, ListItem};
+use tui::{Frame, Terminal};
+
+use crate::archive::Archive;
+use crate::filesystem::FileSystem;
+use crate::keymap::{keymap, Action, Keys};
+use crate::pick::render_archive;
+use crate::pick::statefullist::StatefulList;
+use crate::theme::theme;
+use crate::utils::{fuck_error, natural_cmp};
+
+/// What the left pane lists
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Facet {
+    Tags,
+    Artists,
+}
+
+/// Which pane key presses go to
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Groups,
+    Archives,
+}
+
+/// Every tag or artist with the indices of the archives under it, the ones
+/// with the most archives first
+fn group_archives(archives: &[Archive], facet: Facet) -> Vec<(String, Vec<usize>)> {
+    let mut groups = BTreeMap::<&str, Vec<usize>>::new();
+
+    for (i, archive) in archives.iter().enumerate() {
+        match facet {
+            Facet::Tags => {
+                for tag in &archive.tags {
+                    groups.entry(&tag.name).or_default().push(i);
+                }
+            }
+            Facet::Artists => {
+                for artist in &archive.artists {
+                    groups.entry(artist).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .sorted_by(|(n0, m0), (n1, m1)| m1.len().cmp(&m0.len()).then_with(|| n0.cmp(n1)))
+        .map(|(name, members)| (name.to_owned(), members))
+        .collect()
+}
+
+/// Browse the library by tag or artist, opening archives picked from under
+/// them
+pub async fn do_browse(fs: &FileSystem) -> Result<()> {
+    let mut archives = fs.fetch_all().collect::<Result<Vec<_>>>()?;
+    if archives.is_empty() {
+        return Err(eyre!("There are no archives to browse"))
+            .suggestion("Fetch some first with `kscrpr fetch`");
+    }
+    archives.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    // read before taking over the terminal, so mistakes in it are shown
+    keymap()?;
+
+    crossterm::terminal::enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+    terminal.clear()?;
+
+    let result = run_app(&mut terminal, fs, &archives);
+
+    crossterm::terminal::disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    fs: &FileSystem,
+    archives: &[Archive],
+) -> Result<()> {
+    let mut facet = Facet::Tags;
+    let mut focus = Focus::Groups;
+    let mut groups = StatefulList::with_items(group_archives(archives, facet));
+    groups.next();
+    let mut members = StatefulList::with_items(vec![]);
+    // the group the right pane is listing the archives of
+    let mut listed = None;
+    let mut keys = Keys::new()?;
+
+    loop {
+        if listed != Some((facet, groups.selected())) {
+            let selected = groups.selected().map(|i| groups.items()[i].1.clone());
+            members.set_items(selected.unwrap_or_default());
+            listed = Some((facet, groups.selected()));
+        }
+
+        terminal.draw(|f| ui(f, archives, (facet, focus), &mut groups, &mut members))?;
+
+        let (code, action) = match event::read()? {
+            Event::Key(key) => (key.code, keys.press(key)),
+            Event::Mouse(evt) => match evt.kind {
+                event::MouseEventKind::ScrollDown => (KeyCode::Null, Some(Action::Down)),
+                event::MouseEventKind::ScrollUp => (KeyCode::Null, Some(Action::Up)),
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        // paging is just moving ten times
+        let (action, times) = match action {
+            Some(Action::PageDown) => (Some(Action::Down), 10),
+            Some(Action::PageUp) => (Some(Action::Up), 10),
+            Some(Action::HalfPageDown) => (Some(Action::Down), 5),
+            Some(Action::HalfPageUp) => (Some(Action::Up), 5),
+            action => (action, 1),
+        };
+
+        // there's nothing to type into, so the browser's own keys are plain
+        // letters and arrows which come before the keymap
+        for _ in 0..times {
+            match (code, action, focus) {
+                (KeyCode::Char('q'), _, _) | (_, Some(Action::Quit), _) => return Ok(()),
+                (KeyCode::Tab, _, _) => {
+                    facet = match facet {
+                        Facet::Tags => Facet::Artists,
+                        Facet::Artists => Facet::Tags,
+                    };
+                    groups.set_items(group_archives(archives, facet));
+                    focus = Focus::Groups;
+                }
+                (KeyCode::Left, _, _) => focus = Focus::Groups,
+                (KeyCode::Right, _, _) | (_, Some(Action::Select), Focus::Groups) => {
+                    if !members.items().is_empty() {
+                        focus = Focus::Archives;
+                    }
+                }
+                (_, Some(Action::Down), Focus::Groups) => groups.next(),
+                (_, Some(Action::Up), Focus::Groups) => groups.previous(),
+                (_, Some(Action::Down), Focus::Archives) => members.next(),
+                (_, Some(Action::Up), Focus::Archives) => members.previous(),
+                (_, Some(Action::Top), Focus::Groups) => groups.select(0),
+                (_, Some(Action::Bottom), Focus::Groups) => groups.select(usize::MAX),
+                (_, Some(Action::Top), Focus::Archives) => members.select(0),
+                (_, Some(Action::Bottom), Focus::Archives) => members.select(usize::MAX),
+                (_, Some(Action::Select), Focus::Archives) => {
+                    if let Some(i) = members.selected() {
+                        let archive = &archives[members.items()[i]];
+                        if let Err(e) = fs.open_archive(archive.id) {
+                            tracing::error!(
+                                error = fuck_error(&e),
+                                id = archive.id,
+                                "Failed to open archive, treating this as a non-fatal error though"
+                            );
+                        }
+                        // archives that aren't rendered yet are rendered
+                        // first, which writes over the screen
+                        terminal.clear()?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn pane(title: &str, focused: bool) -> Block {
+    let style = if focused {
+        Style::default()
+            .fg(theme().accent.tui())
+            .add_modifier(Modifier::BOLD)
+    } else {
+        Style::default().fg(theme().value.tui())
+    };
+
+    Block::default()
+        .title(Span::styled(title, style))
+        .borders(Borders::ALL)
+        .border_style(style)
+}
+
+fn ui<B: Backend>(
+    f: &mut Frame<B>,
+    archives: &[Archive],
+    (facet, focus): (Facet, Focus),
+    groups: &mut StatefulList<(String, Vec<usize>)>,
+    members: &mut StatefulList<usize>,
+) {
+    let chunks = Layout::default()
+        .margin(1)
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+        .split(f.size());
+
+    let group_items = groups
+        .items()
+        .iter()
+        .map(|(name, members)| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(name.as_str(), Style::default().fg(theme().label.tui())),
+                Span::styled(
+                    format!(" {}", members.len()),
+                    Style::default()
+                        .fg(theme().dim.tui())
+                        .add_modifier(Modifier::DIM),
+                ),
+            ]))
+        })
+        .collect_vec();
+    let title = match facet {
+        Facet::Tags => "Tags (tab for artists)",
+        Facet::Artists => "Artists (tab for tags)",
+    };
+    let group_list = List::new(group_items)
+        .block(pane(title, focus == Focus::Groups))
+        .highlight_style(
+            Style::default()
+                .fg(theme().highlight.tui())
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+        );
+
+    let member_items = members
+        .items()
+        .iter()
+        .map(|i| ListItem::new(render_archive(&archives[*i])))
+        .collect_vec();
+    let member_list = List::new(member_items)
+        .block(pane("Archives (enter to open)", focus == Focus::Archives))
+        .highlight_style(
+            Style::default()
+                .fg(theme().highlight.tui())
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol("｜")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(group_list, chunks[0], groups.state());
+    f.render_stateful_widget(member_list, chunks[1], members.state());
+}
@@ -1,4 +1,8 @@
 use once_cell::sync::OnceCell;
+use reqwest::redirect::Policy;
+use url::Url;
+
+use crate::opts::opts;
 
 pub fn client() -> &'static reqwest::Client {
     static INSTANCE: OnceCell<reqwest::Client> = OnceCell::new();
@@ -9,3 +13,39 @@ pub fn client() -> &'static reqwest::Client {
             .unwrap()
     })
 }
+
+/// Whether archive downloads may be fetched from this url
+pub fn is_allowed_download_host(url: &Url) -> bool {
+    let allowed = &opts().allowed_download_hosts;
+
+    allowed.is_empty()
+        || url
+            .host_str()
+            .map_or(false, |host| allowed.iter().any(|a| a == host))
+}
+
+/// Client used for archive downloads, which enforces the redirect limit and
+/// download host allowlist
+pub fn download_client() -> &'static reqwest::Client {
+    static INSTANCE: OnceCell<reqwest::Client> = OnceCell::new();
+    INSTANCE.get_or_init(|| {
+        let max_redirects = opts().max_redirects;
+
+        let policy = Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error(format!("Exceeded the limit of {max_redirects} redirects"))
+            } else if !is_allowed_download_host(attempt.url()) {
+                let msg = format!("Redirected to disallowed host: {}", attempt.url());
+                attempt.error(msg)
+            } else {
+                attempt.follow()
+            }
+        });
+
+        reqwest::ClientBuilder::new()
+            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.110 Safari/537.36")
+            .redirect(policy)
+            .build()
+            .unwrap()
+    })
+}
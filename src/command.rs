@@ -1,23 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use clap::IntoApp;
-use color_eyre::Result;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use color_eyre::{eyre::eyre, Help, Result};
+use crossterm::tty::IsTty;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget};
 use itertools::Itertools;
 
 use crate::archive::Archive;
+use crate::artifacts;
+use crate::config;
+use crate::dedupe;
+use crate::doctor;
+use crate::downloads::{DownloadState, DownloadView};
+use crate::evict;
 use crate::filesystem::{self, FileSystem};
+use crate::filter::Filter;
+use crate::import;
+use crate::plan;
 use crate::opts::{
-    opts, Command, DirCommand, FetchCommand, GetCommand, IndexType, Opts, OutputAsType,
+    opts, BlacklistCommand, Command, ConfigCommand, DedupeAction, DedupeMode, DirCommand,
+    DuplicateTitles, FetchCommand, GetCommand, IndexType, Opts, OutputAsType, ListSort, MetaCommand,
+    PlanCommand, ReadingDirection, RenderCommand, RenderProfile, SortKey, TagMode,
 };
-use crate::pick;
-use crate::scrape::{by_id, fetch_tag_page};
+use crate::{browse, pager, pick};
+use crate::scrape::{by_id, fetch_tag_page, DownloadSize};
+use crate::search::PageRange;
+use crate::serve;
+use crate::stats;
+use crate::template::Template;
+use crate::theme::{self, theme, ThemeRole};
 use crate::utils::{self, user_has_quit};
 
 pub async fn do_stuff() -> Result<()> {
     let opts = opts();
 
+    theme::load()?;
+
     opts.command.go().await?;
 
     Ok(())
@@ -26,109 +47,1554 @@ pub async fn do_stuff() -> Result<()> {
 impl Command {
     pub async fn go(&self) -> Result<()> {
         match self {
-            Command::Get { command, output_as } => command.go(*output_as).await,
+            Command::Get {
+                command,
+                output_as,
+                format,
+                sort,
+                reverse,
+            } => {
+                let output = match format {
+                    Some(template) => Output::Template(template),
+                    None => Output::As(*output_as),
+                };
+                command.go(output, *sort, *reverse).await
+            }
             Command::Dir { command } => command.go(),
             Command::Fetch { command } => command.go().await,
-            Command::Reindex => do_reindex().await,
+            Command::Plan { command } => command.go().await,
+            Command::Reindex => do_reindex(None).await,
+            Command::Resume => do_resume().await,
+            Command::Direction { direction, ids } => do_direction(*direction, ids),
+            Command::Render {
+                command: Some(command),
+                ..
+            } => command.go().await,
+            Command::Render {
+                command: None,
+                ids,
+                filter,
+                force,
+                out,
+                profile,
+            } => do_render(ids, filter.as_ref(), *force, out.as_deref(), *profile).await,
             Command::Completion { shell } => {
                 shell.generate(&mut Opts::command(), &mut std::io::stdout());
                 Ok(())
             }
-            Command::Stats => do_stats().await,
+            Command::Stats { json } => do_stats(*json).await,
+            Command::Info { id, pages } => do_info(*id, *pages),
+            Command::Tags { min_count, sort } => do_tags(*min_count, *sort),
+            Command::Artists { min_count, sort } => do_artists(*min_count, *sort),
+            Command::Browse => do_browse().await,
+            Command::Ui { output_as } => do_ui(*output_as).await,
+            Command::Serve { bind } => do_serve(*bind).await,
+            Command::Export { path, ids, filter } => do_export(path, ids, filter.as_ref()),
+            Command::Import { path, force } => do_import(path, *force).await,
+            Command::Verify => do_verify(),
+            Command::Doctor { fix } => do_doctor(*fix).await,
+            Command::Dedupe { mode, action, yes } => do_dedupe(*mode, *action, *yes).await,
+            Command::Remove { ids, filter, yes } => do_remove(ids, filter.as_ref(), *yes).await,
+            Command::Favorite { remove, ids } => do_favorite(ids, *remove),
+            Command::History {
+                run,
+                pick,
+                open,
+                output_as,
+            } => do_history(*run, *pick || *open, *open, *output_as).await,
+            Command::Blacklist { command } => command.go(),
+            Command::Config { command } => command.go(),
+            Command::Meta { command } => command.go().await,
+            Command::Evict { max_size, dry_run } => do_evict(*max_size, *dry_run),
+        }
+    }
+}
+
+/// Bring the thumbnails, gallery, and OPDS catalog up to date with whatever
+/// was just changed
+fn regenerate_artifacts(fs: &FileSystem) {
+    if let Err(e) = artifacts::regenerate(fs) {
+        tracing::error!(
+            error = utils::fuck_error(&e),
+            "Failed to regenerate the gallery, treating this as a non-fatal error though"
+        );
+    }
+}
+
+fn report_render_pending(fs: &FileSystem) -> Result<()> {
+    // with --no-render these are expected, and rendered when they're opened
+    if opts().no_render {
+        return Ok(());
+    }
+
+    let pending = fs.render_pending_ids()?;
+
+    if !pending.is_empty() {
+        eprintln!(
+            "Rendering was cancelled for {} archives ({}), run `kscrpr render` to finish rendering them",
+            pending.len(),
+            pending.iter().join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Evict archives if the library has grown past `--max-library-size`
+fn enforce_library_size(fs: &FileSystem) {
+    let max_size = match opts().max_library_size {
+        Some(max_size) => max_size,
+        None => return,
+    };
+
+    match evict::enforce(fs, max_size) {
+        Ok(evicted) if !evicted.is_empty() => {
+            let freed = evicted.iter().map(|(_, size)| size).sum::<u64>();
+            eprintln!(
+                "Evicted {} archives to stay under {}, freeing {}",
+                evicted.len(),
+                HumanBytes(max_size),
+                HumanBytes(freed)
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!(
+                error = utils::fuck_error(&e),
+                "Failed to evict archives, treating this as a non-fatal error though"
+            );
+        }
+    }
+}
+
+async fn do_stats(json: bool) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    let stats = stats::collect(&fs)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        Ok(())
+    } else {
+        pager::output_lines(&stats.lines())
+    }
+}
+
+fn do_tags(min_count: u64, sort: ListSort) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    let counts = fs.searcher.tag_counts()?;
+
+    let tags = counts
+        .into_iter()
+        .filter(|(_, count)| *count >= min_count)
+        .sorted_by(|(n0, c0), (n1, c1)| match sort {
+            ListSort::Count => c1.cmp(c0).then_with(|| n0.cmp(n1)),
+            ListSort::Name => n0.cmp(n1),
+        })
+        .map(|(name, count)| {
+            format!(
+                "{}\t{}",
+                theme().paint(ThemeRole::Value, &name),
+                theme().paint(ThemeRole::Dim, &count.to_string())
+            )
+        })
+        .collect_vec();
+
+    pager::output_lines(&tags)
+}
+
+/// Count how many of `docs` have each value of the given fields, most common
+/// first
+fn facet_lines(docs: &[Archive], facets: &[IndexType]) -> Vec<String> {
+    let mut lines = vec![];
+
+    for facet in facets {
+        let mut counts = HashMap::<&str, u64>::new();
+
+        for doc in docs {
+            match facet {
+                IndexType::Name => *counts.entry(&doc.name).or_default() += 1,
+                IndexType::Artist => {
+                    for artist in &doc.artists {
+                        *counts.entry(artist).or_default() += 1;
+                    }
+                }
+                IndexType::Parody => *counts.entry(&doc.parody).or_default() += 1,
+                IndexType::Site => *counts.entry(doc.site()).or_default() += 1,
+                IndexType::Tag => {
+                    for tag in &doc.tags {
+                        *counts.entry(&tag.name).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        lines.push(theme().paint(ThemeRole::Label, &format!("{}:", facet.str())));
+        lines.extend(
+            counts
+                .into_iter()
+                .sorted_by(|(n0, c0), (n1, c1)| c1.cmp(c0).then_with(|| n0.cmp(n1)))
+                .map(|(name, count)| {
+                    format!(
+                        "  {}\t{}",
+                        theme().paint(ThemeRole::Value, name),
+                        theme().paint(ThemeRole::Dim, &count.to_string())
+                    )
+                }),
+        );
+    }
+
+    lines
+}
+
+async fn do_browse() -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    browse::do_browse(&fs).await
+}
+
+async fn do_ui(output_as: Option<OutputAsType>) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    for archive in pick::do_ui(&fs).await? {
+        match output_as {
+            Some(output_as) => output_as.print(&archive, &fs),
+            None => fs.open_archive(archive.id)?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn do_serve(bind: SocketAddr) -> Result<()> {
+    let fs = FileSystem::open()?;
+    regenerate_artifacts(&fs);
+    let server = serve::Server::new(&fs)?;
+    // the library stays usable by other commands while serving
+    drop(fs);
+
+    server.run(bind).await
+}
+
+fn do_artists(min_count: u64, sort: ListSort) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    let mut counts = HashMap::<String, (u64, u64)>::new();
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+        for artist in archive.artists {
+            let (count, pages) = counts.entry(artist).or_default();
+            *count += 1;
+            *pages += archive.num_pages as u64;
+        }
+    }
+
+    let artists = counts
+        .into_iter()
+        .filter(|(_, (count, _))| *count >= min_count)
+        .sorted_by(|(n0, (c0, _)), (n1, (c1, _))| match sort {
+            ListSort::Count => c1.cmp(c0).then_with(|| n0.cmp(n1)),
+            ListSort::Name => n0.cmp(n1),
+        })
+        .map(|(name, (count, pages))| {
+            format!(
+                "{}\t{}\t{}",
+                theme().paint(ThemeRole::Label, &name),
+                theme().paint(ThemeRole::Dim, &count.to_string()),
+                theme().paint(ThemeRole::Dim, &pages.to_string())
+            )
+        })
+        .collect_vec();
+
+    pager::output_lines(&artists)
+}
+
+/// Pages smaller than this in either dimension are likely placeholders
+const TINY_PAGE_PX: u32 = 100;
+
+fn page_lines(fs: &FileSystem, archive: &Archive) -> Vec<String> {
+    let pages = filesystem::page_files(&fs.data_dir_of_id(archive.id));
+
+    let probed = pages
+        .iter()
+        .map(|page| {
+            let size = std::fs::metadata(page).map_or(0, |m| m.len());
+            (page, size, filesystem::probe_page(page))
+        })
+        .collect_vec();
+
+    let median_aspect = probed
+        .iter()
+        .filter_map(|(_, _, p)| p.as_ref().ok())
+        .map(|(_, (w, h))| *w as f64 / (*h).max(1) as f64)
+        .sorted_by(|a, b| a.total_cmp(b))
+        .collect_vec();
+    let median_aspect = median_aspect.get(median_aspect.len() / 2).copied();
+
+    let mut lines = vec![];
+
+    if pages.len() != archive.num_pages as usize {
+        lines.push(format!(
+            "  ! expected {} pages but found {}",
+            archive.num_pages,
+            pages.len()
+        ));
+    }
+
+    for (page, size, probe) in probed {
+        let name = page.file_name().unwrap_or_default().to_string_lossy();
+
+        let (format, (w, h)) = match probe {
+            Ok(p) => p,
+            Err(e) => {
+                lines.push(format!("  {name:<24} ! unreadable: {e}"));
+                continue;
+            }
+        };
+
+        let mut flags = vec![];
+
+        if w < TINY_PAGE_PX || h < TINY_PAGE_PX {
+            flags.push("tiny, likely a placeholder");
+        }
+
+        // double page spreads are about twice as wide as a normal page
+        if let Some(median) = median_aspect {
+            let ratio = (w as f64 / h.max(1) as f64) / median;
+            if !(0.6..=2.4).contains(&ratio) {
+                flags.push("unusual aspect ratio");
+            }
+        }
+
+        let flags = if flags.is_empty() {
+            String::new()
+        } else {
+            format!(" ! {}", flags.join(", "))
+        };
+
+        lines.push(format!(
+            "  {name:<24} {:<5} {:>11} {:>10}{flags}",
+            format!("{format:?}").to_lowercase(),
+            format!("{w}x{h}"),
+            HumanBytes(size).to_string()
+        ));
+    }
+
+    lines
+}
+
+fn do_info(id: u32, pages: bool) -> Result<()> {
+    let fs = FileSystem::open()?;
+    let archive = fs.fetch_doc(id)?;
+
+    let mut lines = vec![
+        format!("({}) {}", archive.id, archive.pretty_single_line()),
+        format!("  parody: {}", archive.parody),
+        format!(
+            "  tags:   {}",
+            archive.tags.iter().map(|t| &t.name).join(", ")
+        ),
+        format!("  pages:  {}", archive.num_pages),
+        format!("  url:    {}", archive.base_url),
+    ];
+
+    lines.push(format!("  site:   {}", archive.site()));
+
+    if let Some(source_site) = &archive.source_site {
+        lines.push(format!("  source: {source_site}"));
+    }
+
+    for (key, value) in &archive.custom {
+        lines.push(format!("  {key}: {value}"));
+    }
+
+    if fs.is_evicted(id) {
+        lines.push("  size:   evicted".to_owned());
+    } else {
+        lines.push(format!("  size:   {}", HumanBytes(fs.content_size_of(id))));
+    }
+
+    if pages && !fs.is_evicted(id) {
+        lines.extend(page_lines(&fs, &archive));
+    }
+
+    pager::output_lines(&lines)
+}
+
+async fn do_dedupe(mode: DedupeMode, action: Option<DedupeAction>, yes: bool) -> Result<()> {
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let fs = FileSystem::open()?;
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    let prog_bar = bar.add(ProgressBar::new(1).with_style(
+        theme().progress_style("[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}"),
+    ));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    let groups = dedupe::find_duplicates(&fs, mode, &msg_bar, &prog_bar)?;
+
+    msg_bar.finish_and_clear();
+    prog_bar.finish_and_clear();
+
+    if groups.is_empty() {
+        eprintln!("No duplicates found");
+        return Ok(());
+    }
+
+    for group in groups {
+        let (keep, dupes) = group.split_first().unwrap();
+
+        println!("keep:   ({}) {}", keep.id, keep.pretty_single_line());
+        for dupe in dupes {
+            println!("  dupe: ({}) {}", dupe.id, dupe.pretty_single_line());
+        }
+
+        let action = match action {
+            Some(action) => action,
+            None => continue,
+        };
+
+        if !yes && !utils::confirm("Replace the duplicates?")? {
+            continue;
+        }
+
+        for dupe in dupes {
+            dedupe::resolve(&fs, keep, dupe, action).await?;
+        }
+    }
+
+    fs.searcher.commit().await?;
+    regenerate_artifacts(&fs);
+
+    Ok(())
+}
+
+/// The archives a bulk command applies to: the given ids if there are any,
+/// otherwise the whole library, narrowed down by the filter if given
+fn select_archives(fs: &FileSystem, ids: &[u32], filter: Option<&Filter>) -> Result<Vec<Archive>> {
+    let archives = if ids.is_empty() {
+        fs.fetch_all().collect::<Result<Vec<_>>>()?
+    } else {
+        ids.iter()
+            .map(|id| fs.fetch_doc(*id))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    Ok(archives
+        .into_iter()
+        .filter(|archive| filter.map_or(true, |f| f.matches(archive, fs)))
+        .collect())
+}
+
+async fn do_remove(ids: &[u32], filter: Option<&Filter>, yes: bool) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    let archives = select_archives(&fs, ids, filter)?;
+
+    if archives.is_empty() {
+        eprintln!("Nothing found :(");
+        return Ok(());
+    }
+
+    for archive in archives {
+        if !yes && !utils::confirm(&format!("Delete {}?", archive.pretty_single_line()))? {
+            continue;
+        }
+
+        fs.remove_archive(&archive).await?;
+        eprintln!("Deleted {}", archive.pretty_single_line());
+    }
+
+    fs.searcher.commit().await?;
+    regenerate_artifacts(&fs);
+
+    Ok(())
+}
+
+fn do_favorite(ids: &[u32], remove: bool) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    for id in ids {
+        fs.fetch_doc(*id)?;
+        fs.set_favorite(*id, !remove)?;
+    }
+
+    Ok(())
+}
+
+async fn do_history(
+    run: Option<usize>,
+    pick: bool,
+    open: bool,
+    output_as: OutputAsType,
+) -> Result<()> {
+    let fs = FileSystem::open()?;
+    let history = fs.recent_searches()?;
+
+    let n = match run {
+        Some(n) => n,
+        None => {
+            for (n, query) in history.iter().rev().enumerate() {
+                let n = theme().paint(ThemeRole::Dim, &format!("{:>4}", n + 1));
+                println!("{n}  {query}");
+            }
+            return Ok(());
+        }
+    };
+
+    let query = n
+        .checked_sub(1)
+        .and_then(|i| history.iter().rev().nth(i))
+        .ok_or_else(|| eyre!("There's no search {n} back, only {}", history.len()))
+        .suggestion("List them with `kscrpr history`")?;
+    fs.push_search_history(query)?;
+
+    let docs = fs
+        .search(query, &pick::SEARCH_INDEXES, None, 0, PageRange::default())
+        .await?;
+
+    if docs.is_empty() {
+        eprintln!("Nothing found :(");
+    } else if pick {
+        do_pick(query, &docs, open, Output::As(output_as), &fs).await?;
+    } else {
+        output_as.print_all(&docs, &fs)?;
+    }
+
+    Ok(())
+}
+
+impl ConfigCommand {
+    pub fn go(&self) -> Result<()> {
+        match self {
+            ConfigCommand::Init { force } => config::init(*force),
+            ConfigCommand::HashPassword { user } => config::hash_password(user),
+        }
+    }
+}
+
+impl BlacklistCommand {
+    pub fn go(&self) -> Result<()> {
+        let fs = FileSystem::open()?;
+
+        match self {
+            BlacklistCommand::Add { tag } => fs.blacklist_tag(tag)?,
+            BlacklistCommand::Remove { tag } => fs.unblacklist_tag(tag)?,
+            BlacklistCommand::List => {
+                for tag in fs.blacklisted_tags()? {
+                    println!("{tag}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RenderCommand {
+    pub async fn go(&self) -> Result<()> {
+        match self {
+            RenderCommand::ContactSheet {
+                filter,
+                title,
+                columns,
+                out,
+            } => {
+                if *columns == 0 {
+                    return Err(eyre!("A contact sheet needs at least one column"));
+                }
+
+                let fs = FileSystem::open()?;
+
+                let mut archives = select_archives(&fs, &[], Some(filter))?;
+                if archives.is_empty() {
+                    return Err(eyre!("No archives match the filter"));
+                }
+                archives.sort_by(|a, b| utils::natural_cmp(&a.name, &b.name));
+
+                let out = out.clone().unwrap_or_else(|| match title {
+                    Some(title) => PathBuf::from(format!(
+                        "{}-contact-sheet.pdf",
+                        filesystem::sanitize_component(title)
+                    )),
+                    None => PathBuf::from("contact-sheet.pdf"),
+                });
+
+                let prog_bar =
+                    ProgressBar::new(archives.len() as u64).with_style(theme().progress_style(
+                        "[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}",
+                    ));
+                artifacts::generate_contact_sheet(
+                    &fs,
+                    &archives,
+                    &match title {
+                        Some(title) => format!("{title} contact sheet"),
+                        None => "Contact sheet".to_owned(),
+                    },
+                    *columns,
+                    &out,
+                    |done, _| prog_bar.set_position(done as u64),
+                )?;
+                prog_bar.finish_and_clear();
+
+                eprintln!("Wrote the contact sheet to {}", out.display());
+            }
+            RenderCommand::Merge { ids, filter, out } => {
+                if ids.is_empty() && filter.is_none() {
+                    return Err(eyre!("No archives to merge"))
+                        .suggestion("Give the ids of the archives, or use `--filter`");
+                }
+
+                let fs = FileSystem::open()?;
+
+                let mut archives = select_archives(&fs, ids, filter.as_ref())?;
+                // given ids are kept in their order, the parts of a series
+                // found by a search usually sort into order by name
+                if ids.is_empty() {
+                    archives.sort_by(|a, b| utils::natural_cmp(&a.name, &b.name));
+                }
+                archives.retain(|archive| {
+                    let evicted = fs.is_evicted(archive.id);
+                    if evicted {
+                        tracing::warn!(
+                            id = archive.id,
+                            "Leaving archive out of the merge as its content was evicted"
+                        );
+                    }
+                    !evicted
+                });
+                if archives.is_empty() {
+                    return Err(eyre!("No archives to merge"))
+                        .suggestion("Give the ids of the archives, or use `--filter`");
+                }
+
+                ctrlc::set_handler(move || {
+                    utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+                })
+                .unwrap();
+
+                let bar = MultiProgress::new();
+                let msg_bar = bar.add(ProgressBar::new(1).with_style(
+                    theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}"),
+                ));
+                msg_bar.enable_steady_tick(Duration::from_millis(200));
+                let page_bar = bar.add(ProgressBar::new(1).with_style(
+                    theme().progress_style("{wide_bar:{label/value}} {pos:>}/{len} pages"),
+                ));
+                bar.set_move_cursor(true);
+
+                msg_bar.set_prefix("Merging");
+
+                let completed = fs.generate_merged_pdf(&archives, out, &msg_bar, &page_bar)?;
+
+                msg_bar.finish_with_message(if completed { "Done" } else { "Cancelled" });
+                page_bar.finish_and_clear();
+
+                if completed {
+                    eprintln!("Merged {} archives into {}", archives.len(), out.display());
+                }
+            }
+            RenderCommand::Strip {
+                id,
+                out,
+                images,
+                max_height,
+            } => {
+                let fs = FileSystem::open()?;
+                let archive = fs.fetch_doc(*id)?;
+
+                if fs.is_evicted(*id) {
+                    return Err(eyre!("The content of archive {id} was evicted"))
+                        .with_suggestion(|| format!("Fetch it again with `kscrpr fetch id {id}`"));
+                }
+
+                ctrlc::set_handler(move || {
+                    utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+                })
+                .unwrap();
+
+                let output = if *images {
+                    artifacts::StripOutput::Images(out)
+                } else {
+                    artifacts::StripOutput::Pdf(out)
+                };
+                let prog_bar = ProgressBar::new(archive.num_pages as u64).with_style(
+                    theme().progress_style("{wide_bar:{label/value}} {pos:>}/{len} pages"),
+                );
+                let completed = artifacts::generate_strips(
+                    &fs,
+                    &archive,
+                    output,
+                    *max_height,
+                    |done, total| {
+                        prog_bar.set_length(total as u64);
+                        prog_bar.set_position(done as u64);
+                    },
+                )?;
+                prog_bar.finish_and_clear();
+
+                if completed {
+                    eprintln!("Wrote the strips to {}", out.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl MetaCommand {
+    pub async fn go(&self) -> Result<()> {
+        let fs = FileSystem::open()?;
+
+        match self {
+            MetaCommand::Set { id, pairs } => {
+                fs.update_custom_meta(*id, pairs, &[]).await?;
+            }
+            MetaCommand::Unset { id, keys } => {
+                fs.update_custom_meta(*id, &[], keys).await?;
+            }
+            MetaCommand::List { id } => {
+                for (key, value) in &fs.fetch_doc(*id)?.custom {
+                    println!("{key}={value}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn do_evict(max_size: Option<u64>, dry_run: bool) -> Result<()> {
+    let max_size = max_size
+        .or(opts().max_library_size)
+        .ok_or_else(|| eyre!("No size to evict down to"))
+        .suggestion("Pass --max-size or set --max-library-size")?;
+
+    let fs = FileSystem::open()?;
+
+    let (total, to_evict) = evict::plan(&fs, max_size)?;
+
+    if to_evict.is_empty() {
+        eprintln!(
+            "The library is {}, which is within {}",
+            HumanBytes(total),
+            HumanBytes(max_size)
+        );
+        return Ok(());
+    }
+
+    for (archive, size) in &to_evict {
+        if !dry_run {
+            fs.evict_archive(archive)?;
+        }
+
+        println!(
+            "({}) {} [{}]",
+            archive.id,
+            archive.pretty_single_line(),
+            HumanBytes(*size)
+        );
+    }
+
+    let freed = to_evict.iter().map(|(_, size)| size).sum::<u64>();
+    eprintln!(
+        "{} {} archives, freeing {} of {}",
+        if dry_run { "Would evict" } else { "Evicted" },
+        to_evict.len(),
+        HumanBytes(freed),
+        HumanBytes(total)
+    );
+
+    Ok(())
+}
+
+fn do_verify() -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    // Archives whose names collide share a link, which `symlink_unique`
+    // disambiguates with a ` (n)` suffix, so the targets are grouped by the
+    // link they want and checked against all of its suffixed names
+    let mut wanted = HashMap::<PathBuf, (bool, Vec<PathBuf>)>::new();
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+        let data_dir = fs.data_dir_of_id(archive.id);
+
+        // evicted archives have no links, and ones left to be rendered when
+        // opened have no rendered links yet
+        if fs.is_evicted(archive.id) {
+            continue;
+        }
+        let pending = fs.render_pending.contains_key(archive.id.to_be_bytes())?;
+
+        for (link, target) in fs.symlink_paths_for(&archive) {
+            let dir = target == data_dir;
+            if pending && !dir {
+                continue;
+            }
+
+            let entry = wanted.entry(link).or_insert((dir, vec![]));
+            entry.1.push(target);
+        }
+    }
+
+    let mut problems = 0;
+
+    for (link, (dir, targets)) in wanted.iter().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+        let mut found = vec![];
+
+        for candidate in filesystem::existing_link_names(link, *dir) {
+            let existing = match std::fs::read_link(&candidate) {
+                Ok(existing) => existing,
+                Err(_) => continue,
+            };
+
+            // Dangling links are reported with the rest below
+            if targets.contains(&existing) {
+                found.push(existing);
+            } else if existing.exists() {
+                problems += 1;
+                println!(
+                    "wrong target: {} points at {}",
+                    candidate.display(),
+                    existing.display()
+                );
+            }
+        }
+
+        for target in targets.iter().filter(|t| !found.contains(t)) {
+            problems += 1;
+            println!("missing: {} -> {}", link.display(), target.display());
+        }
+    }
+
+    let trees = [
+        fs.data_tag_dir(),
+        fs.data_artist_dir(),
+        fs.data_parody_dir(),
+        fs.rendered_tag_dir(),
+        fs.rendered_artist_dir(),
+        fs.rendered_parody_dir(),
+    ];
+
+    for tree in trees {
+        for entry in walkdir::WalkDir::new(tree).into_iter().filter_map(|e| e.ok()) {
+            if !entry.path_is_symlink() {
+                continue;
+            }
+
+            if !entry.path().exists() {
+                problems += 1;
+                println!("broken: {}", entry.path().display());
+            }
+        }
+    }
+
+    let mut corrupt = 0;
+
+    for (id, pages) in fs.all_corrupt_pages()? {
+        corrupt += 1;
+        println!(
+            "corrupt: ({id}) pages {} couldn't be decoded when rendering",
+            pages.iter().join(", ")
+        );
+    }
+
+    if problems == 0 && corrupt == 0 {
+        eprintln!("No problems found");
+    }
+    if problems > 0 {
+        eprintln!("Found {problems} problems, `kscrpr reindex` will rebuild the symlink trees");
+    }
+    if corrupt > 0 {
+        eprintln!(
+            "Found {corrupt} archives with corrupt pages, removing and fetching them again may fix them"
+        );
+    }
+
+    Ok(())
+}
+
+async fn do_doctor(fix: bool) -> Result<()> {
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let fs = FileSystem::open()?;
+
+    let findings = doctor::run(&fs, fix).await?;
+
+    if fix {
+        regenerate_artifacts(&fs);
+    }
+
+    if findings.is_empty() {
+        eprintln!("No problems found");
+        return Ok(());
+    }
+
+    for finding in &findings {
+        if finding.fixed {
+            println!("fixed: {}", finding.problem);
+        } else {
+            println!("problem: {}", finding.problem);
+            println!("  fix: {}", finding.fix);
+        }
+    }
+
+    let remaining = findings.iter().filter(|f| !f.fixed).count();
+    if remaining > 0 && !fix {
+        eprintln!("Found {remaining} problems, `kscrpr doctor --fix` can fix some of them");
+    } else if remaining > 0 {
+        eprintln!("{remaining} problems need fixing by hand");
+    }
+
+    Ok(())
+}
+
+const PAUSED_FETCH: &str = "fetch";
+const PAUSED_REINDEX: &str = "reindex";
+
+async fn do_resume() -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    if let Some(last_id) = fs.paused::<u32>(PAUSED_REINDEX)? {
+        drop(fs);
+        return do_reindex(Some(last_id)).await;
+    }
+
+    if let Some(queue) = fs.paused::<FetchQueue>(PAUSED_FETCH)? {
+        ctrlc::set_handler(move || {
+            println!("C-c acknowleged, pausing soon!");
+            utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+        })
+        .unwrap();
+
+        eprintln!("Resuming fetching {}", queue.tags.join(", "));
+        return run_fetch_queue(&fs, queue, true, false).await;
+    }
+
+    eprintln!("Nothing to resume");
+
+    Ok(())
+}
+
+fn do_direction(direction: ReadingDirection, ids: &[u32]) -> Result<()> {
+    let fs = FileSystem::open()?;
+
+    for id in ids {
+        fs.fetch_doc(*id)?;
+        fs.set_reading_direction(*id, direction)?;
+    }
+
+    eprintln!("They'll be rendered again when next opened, or run `kscrpr render` to do it now");
+
+    Ok(())
+}
+
+async fn do_render(
+    ids: &[u32],
+    filter: Option<&Filter>,
+    force: bool,
+    out: Option<&Path>,
+    profile: RenderProfile,
+) -> Result<()> {
+    // exporting the archives still waiting to be rendered would be a surprise
+    if out.is_some() && ids.is_empty() && filter.is_none() {
+        return Err(eyre!("No archives given to render"))
+            .suggestion("Give the ids of the archives to render, or use `--filter`");
+    }
+
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let fs = FileSystem::open()?;
+
+    let archives = if ids.is_empty() && filter.is_none() {
+        fs.render_pending_ids()?
+            .into_iter()
+            .map(|id| fs.fetch_doc(id))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        select_archives(&fs, ids, filter)?
+    };
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    let page_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{wide_bar:{label/value}} {pos:>}/{len} pages")),
+    );
+    let prog_bar = bar.add(ProgressBar::new(archives.len() as u64).with_style(
+        theme().progress_style("[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}"),
+    ));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    msg_bar.set_prefix("Rendering");
+
+    for archive in archives {
+        if user_has_quit() {
+            break;
+        }
+
+        let id = archive.id;
+
+        if fs.is_evicted(id) {
+            tracing::warn!(id, "Not rendering archive as its content was evicted");
+            prog_bar.inc(1);
+            continue;
+        }
+
+        msg_bar.set_message(format!("Archive ({})[{}]", archive.id, archive.name));
+        if let Some(out) = out {
+            fs.export_archive(&archive, out, profile, force, &msg_bar, &page_bar)?;
+        } else if profile == RenderProfile::Eink {
+            if force {
+                fs.remove_eink_file_of(id);
+            }
+            fs.render_eink(&archive, &msg_bar, &page_bar)?;
+        } else {
+            if force {
+                fs.remove_rendered_files_of(id);
+            }
+            fs.render_archive(&archive, &msg_bar, &page_bar)?;
+        }
+        prog_bar.inc(1);
+    }
+
+    msg_bar.finish_with_message(if user_has_quit() { "Cancelled" } else { "Done" });
+    page_bar.finish_and_clear();
+    prog_bar.finish();
+
+    if let Some(out) = out {
+        eprintln!("Rendered files are in {}", out.display());
+        return Ok(());
+    }
+    if profile == RenderProfile::Eink {
+        eprintln!("E-ink PDFs are in {}", fs.rendered_eink_dir().display());
+        return Ok(());
+    }
+    regenerate_artifacts(&fs);
+
+    // the pending archives are worth pointing out even with --no-render here
+    let pending = fs.render_pending_ids()?;
+    if !pending.is_empty() {
+        eprintln!(
+            "{} archives still need rendering, run `kscrpr render` again to carry on",
+            pending.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Rebuild the symlink trees and search index, carrying on after the archive
+/// with id `resume_after` if resuming a paused reindex
+async fn do_reindex(resume_after: Option<u32>) -> Result<()> {
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    let page_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{wide_bar:{label/value}} {pos:>}/{len} pages")),
+    );
+    let prog_bar = bar.add(ProgressBar::new(1).with_style(
+        theme().progress_style("[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}"),
+    ));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    // clearing the tag trees and index means any change to --tag-normalization
+    // or --rendered-name-template is migrated by rebuilding them below
+    if resume_after.is_none() {
+        msg_bar.set_prefix("Clearing directories");
+        msg_bar.tick();
+
+        FileSystem::reset_tantivy_dir();
+        FileSystem::reset_artists_dir();
+        FileSystem::reset_parodies_dir();
+        FileSystem::reset_rendered_dir();
+        FileSystem::reset_tags_dir();
+    }
+    let fs = FileSystem::open()?;
+
+    let remaining = match resume_after {
+        Some(id) => fs.sled_db.range((id + 1).to_be_bytes()..),
+        None => fs.sled_db.range::<&[u8], _>(..),
+    };
+
+    prog_bar.set_length(fs.sled_db.len() as u64);
+    prog_bar.set_position(match resume_after {
+        Some(id) => fs.sled_db.range(..=id.to_be_bytes()).count() as u64,
+        None => 0,
+    });
+
+    let mut paused = false;
+
+    for v in remaining.values() {
+        prog_bar.tick();
+        let v = v?;
+        let archive = serde_cbor::from_slice::<Archive>(&v)?;
+        msg_bar.set_message(format!("Archive ({})[{}]", archive.id, archive.name));
+        msg_bar.set_prefix("Indexing");
+        fs.searcher.add_archive(&archive).await?;
+        if !fs.is_evicted(archive.id) {
+            msg_bar.set_prefix("Building symlinks");
+            fs.build_data_symlinks_for(&archive)?;
+            msg_bar.set_prefix("Rendering");
+            fs.render_or_defer(&archive, &msg_bar, &page_bar)?;
+        }
+        fs.mark_dirty(archive.id)?;
+        prog_bar.inc(1);
+
+        if user_has_quit() {
+            fs.save_paused(PAUSED_REINDEX, &archive.id)?;
+            paused = true;
+            break;
+        }
+    }
+    if !paused {
+        fs.clear_paused(PAUSED_REINDEX)?;
+    }
+
+    msg_bar.set_message("");
+    msg_bar.set_prefix("Committing searcher");
+    fs.searcher.commit().await?;
+
+    msg_bar.set_prefix("Updating gallery");
+    regenerate_artifacts(&fs);
+
+    msg_bar.finish_with_message(if paused { "Paused" } else { "Done" });
+    page_bar.finish_and_clear();
+    prog_bar.finish();
+
+    report_render_pending(&fs)?;
+
+    if paused {
+        eprintln!("Reindexing was paused, run `kscrpr resume` to carry on");
+    }
+
+    Ok(())
+}
+
+fn do_export(path: &Path, ids: &[u32], filter: Option<&Filter>) -> Result<()> {
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let fs = FileSystem::open()?;
+
+    let archives = select_archives(&fs, ids, filter)?;
+
+    if archives.is_empty() {
+        eprintln!("Nothing found :(");
+        return Ok(());
+    }
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    let prog_bar = bar.add(ProgressBar::new(1).with_style(
+        theme().progress_style("[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}"),
+    ));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    let exported = import::export(&fs, &archives, path, &msg_bar, &prog_bar)?;
+
+    msg_bar.finish_with_message("Done");
+    prog_bar.finish();
+
+    eprintln!("Exported {exported} archives to {}", path.display());
+
+    Ok(())
+}
+
+async fn do_import(path: &Path, force: bool) -> Result<()> {
+    ctrlc::set_handler(move || {
+        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+    })
+    .unwrap();
+
+    let fs = FileSystem::open()?;
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    let prog_bar = bar.add(ProgressBar::new(1).with_style(
+        theme().progress_style("[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len}"),
+    ));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    let imported = import::import(&fs, path, force, &msg_bar, &prog_bar).await?;
+
+    msg_bar.set_prefix("Updating gallery");
+    regenerate_artifacts(&fs);
+
+    msg_bar.finish_with_message("Done");
+    prog_bar.finish();
+
+    if imported.is_empty() {
+        eprintln!("Imported no new archives");
+    } else {
+        eprintln!("Imported the following archives:");
+        for archive in imported {
+            println!("{}", archive.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// A fetched archive with the same title and artist as a local archive, and
+/// what was done about it
+struct DuplicateTitle {
+    new: Archive,
+    existing: Archive,
+    action: DuplicateTitles,
+}
+
+impl DuplicateTitle {
+    fn describe(&self) -> String {
+        let new = format!("({}) {}", self.new.id, self.new.pretty_single_line());
+        let existing = format!("({})", self.existing.id);
+
+        match self.action {
+            DuplicateTitles::Skip => format!("skipped {new}, the same title as {existing}"),
+            DuplicateTitles::Replace => format!("replaced {existing} with {new}"),
+            DuplicateTitles::Keep | DuplicateTitles::Ask => {
+                format!("kept {new} alongside {existing}")
+            }
+        }
+    }
+}
+
+fn ask_duplicate_title(new: &Archive, existing: &Archive) -> Result<DuplicateTitles> {
+    use std::io::Write;
+
+    eprintln!(
+        "({}) {} has the same title as the local archive ({})",
+        new.id,
+        new.pretty_single_line(),
+        existing.id
+    );
+
+    loop {
+        eprint!("[s]kip, [r]eplace, or [k]eep both? ");
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+
+        match answer.trim() {
+            "s" | "skip" => return Ok(DuplicateTitles::Skip),
+            "r" | "replace" => return Ok(DuplicateTitles::Replace),
+            "k" | "keep" => return Ok(DuplicateTitles::Keep),
+            _ => continue,
+        }
+    }
+}
+
+/// What fetching a single tag did
+struct TagFetch {
+    new_archives: Vec<Archive>,
+    duplicates: Vec<DuplicateTitle>,
+    /// The listing page to carry on from, if the user quit part way through
+    paused_at: Option<u32>,
+}
+
+async fn fetch_tag(
+    fs: &FileSystem,
+    tag: &str,
+    start_page: u32,
+    max: Option<usize>,
+    view: Option<&DownloadView>,
+) -> Result<TagFetch> {
+    let mut new_archives = vec![];
+    let mut duplicates = vec![];
+    let mut paused_at = None;
+
+    // the table shows what the bars would
+    let bar = match view {
+        Some(_) => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        None => MultiProgress::new(),
+    };
+    let total_bar = bar.add(ProgressBar::new(0).with_style(
+        theme().progress_style("[{elapsed_precise:{accent}}] {prefix:{label}} {wide_msg}"),
+    ));
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    let prog_bar = bar.add(ProgressBar::new(1));
+    total_bar.enable_steady_tick(Duration::from_millis(200));
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    prog_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    total_bar.set_prefix(tag.to_owned());
+
+    'outer: for page in start_page.. {
+        if user_has_quit() {
+            paused_at = Some(page);
+            break;
+        }
+
+        total_bar.set_message(format!(
+            "[page {}] [newly downloaded {}]",
+            page,
+            new_archives.len()
+        ));
+        prog_bar.set_style(theme().progress_style("{pos:{value}}/{len}"));
+        if let Some(view) = view {
+            view.set_status(format!("{tag}, fetching page {page}"));
+        }
+
+        if let Some(a) = fetch_tag_page(fs, tag, page, &msg_bar, &prog_bar).await? {
+            if let Some(view) = view {
+                view.set_status(format!("{tag}, page {page}"));
+                view.queue(a.iter().map(|(archive, size)| (archive, *size)));
+            }
+
+            let mut queue = a.into_iter().collect::<VecDeque<_>>();
+            while let Some((archive, size)) = queue.pop_front() {
+                if let Some(view) = view {
+                    match view.state(archive.id) {
+                        DownloadState::Skipped => continue,
+                        DownloadState::Held => {
+                            // come back to held archives once the rest are
+                            // done, waiting on the user if only they are left
+                            if queue
+                                .iter()
+                                .all(|(a, _)| view.state(a.id) == DownloadState::Held)
+                            {
+                                if user_has_quit() {
+                                    paused_at = Some(page);
+                                    break 'outer;
+                                }
+                                tokio::time::sleep(Duration::from_millis(200)).await;
+                            }
+                            queue.push_back((archive, size));
+                            continue;
+                        }
+                        _ => {
+                            view.set_state(archive.id, DownloadState::Downloading, Some(&prog_bar))
+                        }
+                    }
+                }
+
+                // adding the last archive left the bar counting rendered pages
+                prog_bar.set_style(
+                    theme().progress_style("{wide_bar:{label/value}} {bytes:>}/{total_bytes}"),
+                );
+
+                let duplicate = match fs.same_title_as(&archive).await? {
+                    Some(existing) => {
+                        let action = match opts().duplicate_titles {
+                            DuplicateTitles::Ask if std::io::stdin().is_tty() && view.is_none() => {
+                                bar.suspend(|| ask_duplicate_title(&archive, &existing))?
+                            }
+                            DuplicateTitles::Ask => DuplicateTitles::Keep,
+                            action => action,
+                        };
+                        Some((existing, action))
+                    }
+                    None => None,
+                };
+
+                let skip = matches!(duplicate, Some((_, DuplicateTitles::Skip)));
+
+                let added = !skip
+                    && fs
+                        .add_archive(&archive, size, false, &msg_bar, &prog_bar)
+                        .await?;
+                if added {
+                    if let Some((existing, DuplicateTitles::Replace)) = &duplicate {
+                        fs.remove_archive(existing).await?;
+                    }
+                    new_archives.push(archive.clone());
+                }
+                if let Some(view) = view {
+                    let state = if added {
+                        DownloadState::Added
+                    } else {
+                        DownloadState::Skipped
+                    };
+                    view.set_state(archive.id, state, None);
+                }
+
+                if let Some((existing, action)) = duplicate {
+                    duplicates.push(DuplicateTitle {
+                        new: archive,
+                        existing,
+                        action,
+                    });
+                }
+
+                total_bar.set_message(format!(
+                    "[page {}] [newly downloaded {}]",
+                    page,
+                    new_archives.len()
+                ));
+
+                let reached_max = max.map_or(false, |max| new_archives.len() >= max);
+
+                if user_has_quit() || reached_max {
+                    fs.searcher.commit().await?;
+                    if !reached_max {
+                        // archives already downloaded are skipped, so carry on
+                        // from the start of this page
+                        paused_at = Some(page);
+                    }
+                    break 'outer;
+                }
+            }
+
+            fs.searcher.commit().await?;
+        } else {
+            break;
+        }
+    }
+
+    total_bar.finish();
+    msg_bar.finish_and_clear();
+    prog_bar.finish_and_clear();
+
+    Ok(TagFetch {
+        new_archives,
+        duplicates,
+        paused_at,
+    })
+}
+
+/// Tags left to fetch, saved when a fetch is paused so it can be resumed
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FetchQueue {
+    /// The tag being fetched followed by the tags still to fetch
+    tags: Vec<String>,
+    /// The listing page of the first tag to carry on from
+    page: u32,
+    max_per_tag: Option<usize>,
+}
+
+async fn run_fetch_queue(
+    fs: &FileSystem,
+    mut queue: FetchQueue,
+    resumed: bool,
+    tui: bool,
+) -> Result<()> {
+    let mut new_archives = vec![];
+    let mut duplicates = vec![];
+    let mut paused = false;
+
+    let view = if tui {
+        Some(DownloadView::start()?)
+    } else {
+        None
+    };
+
+    while let Some(tag) = queue.tags.first().cloned() {
+        let fetched = fetch_tag(fs, &tag, queue.page, queue.max_per_tag, view.as_ref()).await?;
+        new_archives.extend(fetched.new_archives);
+        duplicates.extend(fetched.duplicates);
+
+        if let Some(page) = fetched.paused_at {
+            queue.page = page;
+            fs.save_paused(PAUSED_FETCH, &queue)?;
+            paused = true;
+            break;
         }
+
+        queue.tags.remove(0);
+        queue.page = 1;
     }
-}
 
-async fn do_stats() -> Result<()> {
-    let mut total = 0;
-    let mut tag_counter = HashMap::new();
+    if let Some(view) = view {
+        view.finish()?;
+    }
 
-    let filesystem = filesystem::FileSystem::open()?;
+    if resumed && !paused {
+        fs.clear_paused(PAUSED_FETCH)?;
+    }
 
-    for archive in filesystem.fetch_all() {
-        let archive = archive?;
+    regenerate_artifacts(fs);
+    report_render_pending(fs)?;
+    enforce_library_size(fs);
 
-        total += 1;
-        for tag in &archive.tags {
-            *tag_counter
-                .raw_entry_mut()
-                .from_key(&tag.name)
-                .or_insert_with(|| (tag.name.clone(), 0))
-                .1 += 1;
+    if !duplicates.is_empty() {
+        eprintln!("Found archives with the same title as local archives:");
+        for duplicate in &duplicates {
+            eprintln!("  {}", duplicate.describe());
         }
     }
 
-    println!("Total: {total}");
-
-    let tags = tag_counter
-        .into_iter()
-        .sorted_by(|(k0, _), (k1, _)| std::cmp::Ord::cmp(k0, k1))
-        .collect_vec();
+    if new_archives.is_empty() {
+        eprintln!("Added no new archives");
+    } else {
+        eprintln!("Added the following new archives:");
+        for archive in new_archives {
+            println!("{}", archive.name);
+        }
+    }
 
-    for (k, v) in tags {
-        println!("{k}: {v}");
+    if paused {
+        eprintln!(
+            "Paused with {} tags left to fetch, run `kscrpr resume` to carry on",
+            queue.tags.len()
+        );
     }
 
     Ok(())
 }
 
-async fn do_reindex() -> Result<()> {
-    ctrlc::set_handler(move || {
-        utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
-    })
-    .unwrap();
-
-    let bar = MultiProgress::new();
-    let msg_bar = bar.add(ProgressBar::new(1).with_style(
-        ProgressStyle::with_template("{spinner:.green} {prefix:.cyan} {wide_msg}").unwrap(),
-    ));
-    msg_bar.enable_steady_tick(Duration::from_millis(200));
-    let prog_bar = bar.add(
-        ProgressBar::new(1).with_style(
-            ProgressStyle::with_template("[{elapsed_precise}] {wide_bar:.cyan/blue} {pos:>}/{len}")
-                .unwrap(),
-        ),
-    );
-    prog_bar.enable_steady_tick(Duration::from_millis(200));
-    bar.set_move_cursor(true);
-
-    msg_bar.set_prefix("Clearing directories");
-    msg_bar.tick();
-
-    FileSystem::reset_tantivy_dir();
-    FileSystem::reset_artists_dir();
-    FileSystem::reset_rendered_dir();
-    FileSystem::reset_tags_dir();
-    let fs = FileSystem::open()?;
-
-    prog_bar.set_length(fs.sled_db.len() as u64);
-    prog_bar.set_position(0);
-
-    for v in fs.sled_db.iter().values() {
-        prog_bar.tick();
-        let v = v?;
-        let archive = serde_cbor::from_slice::<Archive>(&v)?;
-        msg_bar.set_message(format!("Archive ({})[{}]", archive.id, archive.name));
-        msg_bar.set_prefix("Indexing");
-        fs.searcher.add_archive(&archive).await?;
-        msg_bar.set_prefix("Building symlinks");
-        fs.build_data_symlinks_for(&archive)?;
-        msg_bar.set_prefix("Rendering");
-        fs.render_archive(&archive)?;
-        prog_bar.inc(1);
+/// The tags under `[follow]` in the config file, for `fetch tag --followed`
+fn followed_tags() -> Result<Vec<String>> {
+    let tags = config::config().followed_tags();
 
-        if user_has_quit() {
-            break;
-        }
+    if tags.is_empty() {
+        return Err(eyre!("No tags are followed")).suggestion(
+            "List them in the config file, i.e. `tags = [\"some-tag\"]` under `[follow]`",
+        );
     }
-    msg_bar.set_message("");
-    msg_bar.set_prefix("Committing searcher");
-    fs.searcher.commit().await?;
-
-    msg_bar.finish_with_message("Done");
-    prog_bar.finish();
 
-    Ok(())
+    Ok(tags)
 }
 
 impl FetchCommand {
@@ -142,92 +1608,58 @@ impl FetchCommand {
         let fs = FileSystem::open()?;
 
         match self {
-            FetchCommand::Tag { tag } => {
-                let mut new_archives = vec![];
-
-                let bar = MultiProgress::new();
-                let total_bar = bar.add(ProgressBar::new(0).with_style(
-                    ProgressStyle::with_template("[{elapsed_precise:.yellow}] {wide_msg}").unwrap(),
-                ));
-                let msg_bar = bar.add(
-                    ProgressBar::new(1).with_style(
-                        ProgressStyle::with_template("{spinner:.green} {prefix:.cyan} {wide_msg}")
-                            .unwrap(),
-                    ),
-                );
-                let prog_bar = bar.add(ProgressBar::new(1));
-                total_bar.enable_steady_tick(Duration::from_millis(200));
-                msg_bar.enable_steady_tick(Duration::from_millis(200));
-                prog_bar.enable_steady_tick(Duration::from_millis(200));
-                bar.set_move_cursor(true);
-
-                'outer: for page in 1.. {
-                    total_bar.set_message(format!(
-                        "[page {}] [newly downloaded {}]",
-                        page,
-                        new_archives.len()
-                    ));
-                    prog_bar.set_style(ProgressStyle::with_template("{pos:>}/{len}").unwrap());
+            FetchCommand::Tag {
+                tag,
+                all_tags_of,
+                followed,
+                max_per_tag,
+                yes,
+                tui,
+            } => {
+                let tags = match (tag, all_tags_of) {
+                    (Some(tag), _) => utils::expand_stdin_args(std::slice::from_ref(tag))?,
+                    (None, Some(id)) => {
+                        let archive = fs.fetch_doc(*id)?;
+                        let tags = archive.tags.iter().map(|t| t.path.clone()).collect_vec();
 
-                    if let Some(a) = fetch_tag_page(&fs, tag, page, &msg_bar, &prog_bar).await? {
-                        prog_bar.set_style(
-                            ProgressStyle::with_template("{wide_bar} {bytes:>}/{total_bytes}")
-                                .unwrap(),
+                        eprintln!(
+                            "Fetching {} tags of {}: {}",
+                            tags.len(),
+                            archive.pretty_single_line(),
+                            tags.join(", ")
                         );
 
-                        for (archive, size) in a {
-                            if fs
-                                .add_archive(&archive, size, false, &msg_bar, &prog_bar)
-                                .await?
-                            {
-                                new_archives.push(archive);
-                            }
-
-                            total_bar.set_message(format!(
-                                "[page {}] [newly downloaded {}]",
-                                page,
-                                new_archives.len()
-                            ));
-
-                            if user_has_quit() {
-                                fs.searcher.commit().await?;
-                                break 'outer;
-                            }
+                        if !yes && !utils::confirm("Continue?")? {
+                            return Ok(());
                         }
 
-                        fs.searcher.commit().await?;
-                    } else {
-                        break;
+                        tags
                     }
-                }
-                if new_archives.is_empty() {
-                    eprintln!("Added no new archives");
-                } else {
-                    eprintln!("Added the following new archives:");
-                    for archive in new_archives {
-                        println!("{}", archive.name);
+                    (None, None) if *followed => followed_tags()?,
+                    (None, None) => {
+                        unreachable!("clap requires one of tag, --all-tags-of, or --followed")
                     }
-                }
+                };
+
+                let queue = FetchQueue {
+                    tags,
+                    page: 1,
+                    max_per_tag: *max_per_tag,
+                };
+
+                run_fetch_queue(&fs, queue, false, *tui).await?;
             }
             FetchCommand::Id { id } => {
                 let (archive, size) = by_id(*id).await?;
 
                 let bar = MultiProgress::new();
-                let msg_bar = bar.add(
-                    ProgressBar::new(1).with_style(
-                        ProgressStyle::with_template("{spinner:.green} {prefix:.cyan} {wide_msg}")
-                            .unwrap(),
-                    ),
-                );
+                let msg_bar = bar.add(ProgressBar::new(1).with_style(
+                    theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}"),
+                ));
                 msg_bar.enable_steady_tick(Duration::from_millis(200));
-                let prog_bar = bar.add(
-                    ProgressBar::new(1).with_style(
-                        ProgressStyle::with_template(
-                            "[{elapsed_precise}] {wide_bar:.cyan/blue} {bytes:>}/{total_bytes}",
-                        )
-                        .unwrap(),
-                    ),
-                );
+                let prog_bar = bar.add(ProgressBar::new(1).with_style(theme().progress_style(
+                    "[{elapsed_precise}] {wide_bar:{label/value}} {bytes:>}/{total_bytes}",
+                )));
                 prog_bar.enable_steady_tick(Duration::from_millis(200));
                 bar.set_move_cursor(true);
 
@@ -241,6 +1673,9 @@ impl FetchCommand {
                     println!("{}", archive.name);
                 }
                 fs.searcher.commit().await?;
+                regenerate_artifacts(&fs);
+                report_render_pending(&fs)?;
+                enforce_library_size(&fs);
             }
         }
 
@@ -248,6 +1683,148 @@ impl FetchCommand {
     }
 }
 
+/// Scrape the metadata of the archives with `tag` that aren't local yet,
+/// without downloading them
+async fn missing_of_tag(
+    fs: &FileSystem,
+    tag: &str,
+    max: Option<usize>,
+) -> Result<Vec<(Archive, DownloadSize)>> {
+    let mut missing = vec![];
+
+    let bar = MultiProgress::new();
+    let msg_bar = bar.add(
+        ProgressBar::new(1)
+            .with_style(theme().progress_style("{spinner:{accent}} {prefix:{label}} {wide_msg}")),
+    );
+    let prog_bar =
+        bar.add(ProgressBar::new(1).with_style(theme().progress_style("{pos:{value}}/{len}")));
+    msg_bar.enable_steady_tick(Duration::from_millis(200));
+    bar.set_move_cursor(true);
+
+    for page in 1.. {
+        if user_has_quit() || max.map_or(false, |max| missing.len() >= max) {
+            break;
+        }
+
+        match fetch_tag_page(fs, tag, page, &msg_bar, &prog_bar).await? {
+            Some(a) => missing.extend(a),
+            None => break,
+        }
+    }
+
+    if let Some(max) = max {
+        missing.truncate(max);
+    }
+
+    msg_bar.finish_and_clear();
+    prog_bar.finish_and_clear();
+
+    Ok(missing)
+}
+
+impl PlanCommand {
+    pub async fn go(&self) -> Result<()> {
+        ctrlc::set_handler(move || {
+            println!("C-c acknowleged, quitting soon!");
+            utils::RUNNING.store(false, std::sync::atomic::Ordering::SeqCst);
+        })
+        .unwrap();
+
+        let fs = FileSystem::open()?;
+
+        let PlanCommand::Fetch { command } = self;
+
+        let mut candidates = vec![];
+
+        match command {
+            FetchCommand::Tag {
+                tag,
+                all_tags_of,
+                followed,
+                max_per_tag,
+                ..
+            } => {
+                let tags = match (tag, all_tags_of) {
+                    (Some(tag), _) => utils::expand_stdin_args(std::slice::from_ref(tag))?,
+                    (None, Some(id)) => fs
+                        .fetch_doc(*id)?
+                        .tags
+                        .iter()
+                        .map(|t| t.path.clone())
+                        .collect_vec(),
+                    (None, None) if *followed => followed_tags()?,
+                    (None, None) => {
+                        unreachable!("clap requires one of tag, --all-tags-of, or --followed")
+                    }
+                };
+
+                for tag in tags {
+                    candidates.extend(missing_of_tag(&fs, &tag, *max_per_tag).await?);
+
+                    if user_has_quit() {
+                        break;
+                    }
+                }
+            }
+            FetchCommand::Id { id } => {
+                if !fs.has_archive(*id) {
+                    candidates.push(by_id(*id).await?);
+                }
+            }
+        }
+
+        // tags often overlap, but each archive is only downloaded once
+        let candidates = candidates
+            .into_iter()
+            .unique_by(|(archive, _)| archive.id)
+            .collect_vec();
+
+        let plan = plan::plan(&fs, candidates)?;
+
+        let download_time = match plan.download_time {
+            Some(time) => format!("{}", HumanDuration(time)),
+            None => "unknown, nothing has been downloaded yet".to_owned(),
+        };
+
+        let mut lines = vec![
+            format!("Missing archives: {}", plan.archives.len()),
+            format!("Skipped as blacklisted: {}", plan.blacklisted),
+            format!("Pages: {}", plan.pages),
+            format!("Download size: {}", HumanBytes(plan.download_size)),
+            format!("Download time: {download_time}"),
+            format!("Disk usage after rendering: {}", HumanBytes(plan.disk_size)),
+            format!(
+                "Library size: {} -> {}",
+                HumanBytes(plan.library_size),
+                HumanBytes(plan.library_size + plan.disk_size)
+            ),
+        ];
+
+        if let Some(max_size) = opts().max_library_size {
+            if plan.library_size + plan.disk_size > max_size {
+                lines.push(format!(
+                    "This is over --max-library-size of {}, so older archives would be evicted",
+                    HumanBytes(max_size)
+                ));
+            }
+        }
+
+        if !plan.archives.is_empty() {
+            lines.push(String::new());
+            for (archive, size) in &plan.archives {
+                lines.push(format!(
+                    "{}\t{}",
+                    archive.pretty_single_line(),
+                    HumanBytes(size.0 as u64)
+                ));
+            }
+        }
+
+        pager::output_lines(&lines)
+    }
+}
+
 impl DirCommand {
     pub fn go(&self) -> Result<()> {
         let fs = FileSystem::open()?;
@@ -255,6 +1832,7 @@ impl DirCommand {
         let path = match self {
             DirCommand::Tag => fs.rendered_tag_dir(),
             DirCommand::Artist => fs.rendered_artist_dir(),
+            DirCommand::Parody => fs.rendered_parody_dir(),
             DirCommand::Data => fs.data_dir(),
             DirCommand::Meta => fs.meta_dir(),
         };
@@ -265,37 +1843,65 @@ impl DirCommand {
     }
 }
 
-fn do_pick(
+async fn do_pick(
     query: &str,
     docs: &[Archive],
     open: bool,
-    output_as: OutputAsType,
+    output: Output<'_>,
     fs: &FileSystem,
 ) -> Result<()> {
-    let selection = pick::do_pick(query, docs)?;
+    for selected in pick::do_pick(query, docs, fs).await? {
+        if open {
+            fs.open_archive(selected.id)?;
+        } else {
+            output.print(selected, fs);
+        }
+    }
 
-    let selected = match selection {
-        Some(s) => s,
-        None => return Ok(()),
-    };
+    Ok(())
+}
 
-    if open {
-        let path = fs.rendered_file_of_id(selected.id);
-        opener::open(path)?;
-    } else {
-        output_as.print(selected, fs);
+/// Order archives by `sort`, keeping the order they came in for relevance
+fn sort_archives(mut docs: Vec<Archive>, sort: SortKey, reverse: bool) -> Vec<Archive> {
+    docs.sort_by(|a, b| sort.compare(a, b));
+
+    if reverse {
+        docs.reverse();
     }
 
-    Ok(())
+    docs
 }
 
 impl GetCommand {
-    pub async fn go(&self, output_as: OutputAsType) -> Result<()> {
+    pub async fn go(&self, output: Output<'_>, sort: SortKey, reverse: bool) -> Result<()> {
         let fs = FileSystem::open()?;
 
         match self {
-            GetCommand::Tag { tags, pick, open } => {
-                let docs = fs.with_all_tags(tags).await?;
+            GetCommand::Tag {
+                tags,
+                pick,
+                open,
+                mode,
+                any,
+                all: _,
+                excluded,
+                min_pages,
+                max_pages,
+            } => {
+                let tags = utils::expand_stdin_args(tags)?;
+                let pages = PageRange {
+                    min: *min_pages,
+                    max: *max_pages,
+                };
+
+                let mode = if *any { TagMode::Any } else { *mode };
+
+                let docs = match mode {
+                    TagMode::All => fs.with_all_tags(&tags, excluded, pages).await?,
+                    TagMode::Any => fs.with_any_tags(&tags, excluded, pages).await?,
+                    TagMode::None => fs.with_no_tags(&tags, excluded, pages).await?,
+                };
+                let docs = sort_archives(docs, sort, reverse);
 
                 let pick = pick | open;
 
@@ -304,43 +1910,179 @@ impl GetCommand {
                 } else if pick {
                     let search = Itertools::intersperse(tags.iter().cloned(), ", ".to_owned())
                         .collect::<String>();
-                    do_pick(&search, &docs, *open, output_as, &fs)?;
+                    do_pick(&search, &docs, *open, output, &fs).await?;
                 } else {
-                    for doc in docs {
-                        output_as.print(&doc, &fs);
-                    }
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Artist {
+                artist,
+                tags,
+                pick,
+                open,
+            } => {
+                // the artist may be a by-artist directory name, which could
+                // have been sanitized from other names
+                let mut docs = fs.by_artist(artist, tags).await?;
+                for name in fs.original_names_of(artist)? {
+                    docs.extend(fs.by_artist(&name, tags).await?);
+                }
+                let docs = docs.into_iter().unique_by(|d| d.id).collect();
+                let docs = sort_archives(docs, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick(artist, &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Parody {
+                parody,
+                pick,
+                open,
+            } => {
+                let mut docs = fs.by_parody(parody).await?;
+                for name in fs.original_names_of(parody)? {
+                    docs.extend(fs.by_parody(&name).await?);
+                }
+                let docs = docs.into_iter().unique_by(|d| d.id).collect();
+                let docs = sort_archives(docs, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick(parody, &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Similar {
+                id,
+                limit,
+                pick,
+                open,
+            } => {
+                let docs = sort_archives(fs.similar_to(*id, *limit).await?, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick(&id.to_string(), &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Missing { field, pick, open } => {
+                let docs = sort_archives(fs.missing_metadata(*field)?, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick("missing metadata", &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Favorites { pick, open } => {
+                let docs = sort_archives(fs.favorite_archives()?, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick("favorites", &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
+                }
+            }
+            GetCommand::Regex {
+                pattern,
+                on,
+                pick,
+                open,
+            } => {
+                let docs = sort_archives(fs.matching_regex(pattern, *on)?, sort, reverse);
+
+                let pick = pick | open;
+
+                if docs.is_empty() {
+                    eprintln!("Nothing found :(");
+                } else if pick {
+                    do_pick(pattern.as_str(), &docs, *open, output, &fs).await?;
+                } else {
+                    output.print_all(&docs, &fs)?;
                 }
             }
-            GetCommand::Id { id, open } => {
-                let doc = fs.fetch_doc(*id)?;
+            GetCommand::Id { ids, pick, open } => {
+                let docs = ids
+                    .iter()
+                    .map(|id| fs.fetch_doc(*id))
+                    .collect::<Result<Vec<_>>>()?;
+                let docs = sort_archives(docs, sort, reverse);
 
-                if *open {
-                    let path = fs.rendered_file_of_id(doc.id);
-                    opener::open(path)?;
+                if *pick {
+                    do_pick(&ids.iter().join(", "), &docs, *open, output, &fs).await?;
+                } else if *open {
+                    for doc in &docs {
+                        fs.open_archive(doc.id)?;
+                    }
                 } else {
-                    output_as.print(&doc, &fs);
+                    output.print_all(&docs, &fs)?;
                 }
             }
             GetCommand::Search {
                 query,
                 indexes,
-                max,
+                limit,
+                offset,
+                why,
+                facets,
+                min_pages,
+                max_pages,
                 pick,
                 open,
             } => {
                 let indexes = indexes.iter().map(IndexType::str).collect::<Vec<_>>();
-                let docs = fs.search(query, &indexes, *max).await?;
+                let pages = PageRange {
+                    min: *min_pages,
+                    max: *max_pages,
+                };
+                let docs = fs.search(query, &indexes, *limit, *offset, pages).await?;
+                let docs = sort_archives(docs, sort, reverse);
 
                 let pick = pick | open;
 
-                if docs.is_empty() {
+                if !facets.is_empty() {
+                    pager::output_lines(&facet_lines(&docs, facets))?;
+                } else if *why && !docs.is_empty() {
+                    let why = fs.searcher.why(query, &indexes)?;
+
+                    let mut lines = vec![];
+                    for doc in &docs {
+                        lines.push(output.format(doc, &fs));
+                        for (field, matched) in why.explain(doc) {
+                            lines.push(format!("  {field}: {matched}"));
+                        }
+                    }
+
+                    pager::output_lines(&lines)?;
+                } else if docs.is_empty() {
                     eprintln!("Nothing found :(");
                 } else if pick {
-                    do_pick(query, &docs, *open, output_as, &fs)?;
+                    do_pick(query, &docs, *open, output, &fs).await?;
                 } else {
-                    for doc in docs {
-                        output_as.print(&doc, &fs);
-                    }
+                    output.print_all(&docs, &fs)?;
                 }
             }
         }
@@ -349,21 +2091,116 @@ impl GetCommand {
     }
 }
 
+fn explain_paths(doc: &Archive, fs: &FileSystem) -> String {
+    fn state(path: &Path) -> &'static str {
+        if path.exists() {
+            "exists"
+        } else {
+            "missing"
+        }
+    }
+
+    fn link_state(link: &Path, target: &Path) -> &'static str {
+        match std::fs::read_link(link) {
+            Ok(t) if t == target && target.exists() => "ok",
+            Ok(t) if t == target => "dangling",
+            Ok(_) => "wrong target",
+            Err(_) if link.symlink_metadata().is_ok() => "not a symlink",
+            Err(_) => "missing",
+        }
+    }
+
+    let mut lines = vec![format!("({}) {}", doc.id, doc.pretty_single_line())];
+
+    let mut plain = |label: &str, path: PathBuf| {
+        lines.push(format!("  {label:<16} {:<13} {}", state(&path), path.display()));
+    };
+
+    plain("data dir", fs.data_dir_of_id(doc.id));
+    plain("staging dir", fs.staging_dir_of_id(doc.id));
+    for rendered in fs.rendered_files_of_id(doc.id) {
+        plain("rendered file", rendered);
+    }
+    plain("thumbnail", fs.thumb_file_of_id(doc.id));
+    plain("gallery entry", fs.html_fragment_of_id(doc.id));
+    plain("opds entry", fs.opds_fragment_of_id(doc.id));
+
+    for (link, target) in fs.symlink_paths_for(doc) {
+        lines.push(format!(
+            "  {:<16} {:<13} {} -> {}",
+            "symlink",
+            link_state(&link, &target),
+            link.display(),
+            target.display()
+        ));
+    }
+
+    lines.join("\n")
+}
+
 impl OutputAsType {
-    pub fn print(&self, doc: &Archive, fs: &FileSystem) {
+    pub fn format(&self, doc: &Archive, fs: &FileSystem) -> String {
         match self {
-            OutputAsType::DataIdPath => println!("{}", fs.data_dir_of_id(doc.id).display()),
-            OutputAsType::DataPath => println!(
-                "{}",
-                fs.data_dir_of_artist(&doc.artist).join(&doc.name).display()
-            ),
-            OutputAsType::Id => println!("{}", doc.id),
-            OutputAsType::Url => println!("{}", doc.base_url),
-            OutputAsType::Name => println!("{}", doc.name),
-            OutputAsType::IdPath => println!("{}", fs.rendered_file_of_id(doc.id).display()),
-            OutputAsType::Path => {
-                println!("{}", fs.rendered_file_for_archive_by_artist(doc).display())
+            OutputAsType::DataIdPath => fs.data_dir_of_id(doc.id).display().to_string(),
+            OutputAsType::DataPath => match doc.artists.first() {
+                Some(artist) => fs.data_dir_for_archive_by_artist(artist, doc),
+                None => fs.data_dir_of_id(doc.id),
             }
+            .display()
+            .to_string(),
+            OutputAsType::ThumbPath => fs.thumb_file_of_id(doc.id).display().to_string(),
+            OutputAsType::Id => doc.id.to_string(),
+            OutputAsType::Url => doc.base_url.to_string(),
+            OutputAsType::DownloadUrl => doc.effective_download_url().to_string(),
+            OutputAsType::Name => doc.name.clone(),
+            OutputAsType::Site => doc.site().to_owned(),
+            OutputAsType::ExplainPaths => explain_paths(doc, fs),
+            OutputAsType::IdPath => fs.rendered_file_of_id(doc.id).display().to_string(),
+            OutputAsType::Path => match doc.artists.first() {
+                Some(artist) => fs.rendered_file_for_archive_by_artist(
+                    artist,
+                    doc,
+                    opts().render_format.extensions()[0],
+                ),
+                None => fs.rendered_file_of_id(doc.id),
+            }
+            .display()
+            .to_string(),
+        }
+    }
+
+    pub fn print(&self, doc: &Archive, fs: &FileSystem) {
+        println!("{}", self.format(doc, fs));
+    }
+
+    pub fn print_all(&self, docs: &[Archive], fs: &FileSystem) -> Result<()> {
+        let lines = docs.iter().map(|doc| self.format(doc, fs)).collect_vec();
+        pager::output_lines(&lines)
+    }
+}
+
+/// How `get` prints archives, as one of `--output-as` or with a `--format`
+/// template
+#[derive(Clone, Copy)]
+pub enum Output<'a> {
+    As(OutputAsType),
+    Template(&'a Template),
+}
+
+impl Output<'_> {
+    pub fn format(&self, doc: &Archive, fs: &FileSystem) -> String {
+        match self {
+            Output::As(output_as) => output_as.format(doc, fs),
+            Output::Template(template) => template.render(doc, fs),
         }
     }
+
+    pub fn print(&self, doc: &Archive, fs: &FileSystem) {
+        println!("{}", self.format(doc, fs));
+    }
+
+    pub fn print_all(&self, docs: &[Archive], fs: &FileSystem) -> Result<()> {
+        let lines = docs.iter().map(|doc| self.format(doc, fs)).collect_vec();
+        pager::output_lines(&lines)
+    }
 }
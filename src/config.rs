@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgEnum, IntoApp};
+use color_eyre::{eyre::eyre, Help, Result};
+use itertools::Itertools;
+use once_cell::sync::OnceCell;
+
+use crate::keymap::Action;
+use crate::opts::{opts, Opts};
+use crate::pdf::wrap;
+use crate::serve;
+use crate::theme::ThemeRole;
+
+/// The sections the config file may have, besides the settings at the top
+const SECTIONS: [&str; 4] = ["follow", "keymap", "serve", "theme"];
+
+/// The settings of the config file, a small subset of TOML: `[section]`
+/// headers followed by `key = value` lines, where values are bare words,
+/// `"strings"`, or `["arrays", "of", "them"]`
+#[derive(Default)]
+pub struct Config {
+    sections: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+}
+
+impl Config {
+    /// Read the config file at `path`, which is fine to not exist
+    fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let text = std::fs::read_to_string(path)?;
+        Config::parse(&text).map_err(|e| {
+            eyre!("Couldn't read the config file {}: {e}", path.display()).suggestion(
+                "Fix it, point `--config` elsewhere, or run `kscrpr config init --force`",
+            )
+        })
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_owned();
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {} isn't of the form `key = value`", n + 1))?;
+            let values = parse_value(value.trim()).map_err(|e| format!("line {}: {e}", n + 1))?;
+
+            config
+                .sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_owned(), values);
+        }
+
+        Ok(config)
+    }
+
+    /// Every key of a section with its values, a single value for anything
+    /// but arrays
+    pub fn section(&self, name: &str) -> impl Iterator<Item = (&str, &[String])> {
+        self.sections
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|(key, values)| (key.as_str(), values.as_slice()))
+    }
+
+    /// The tags `fetch tag --followed` fetches, from `tags` in the `[follow]`
+    /// section
+    pub fn followed_tags(&self) -> Vec<String> {
+        self.section("follow")
+            .filter(|(key, _)| *key == "tags")
+            .flat_map(|(_, tags)| tags.iter().cloned())
+            .collect()
+    }
+
+    /// Check there's nothing in the config file that would be ignored, as
+    /// misspelt settings are easy to miss otherwise
+    fn check(&self, path: &Path, settable: &[(String, String)]) -> Result<()> {
+        let path = path.display();
+
+        for (section, keys) in &self.sections {
+            if !section.is_empty() && !SECTIONS.contains(&section.as_str()) {
+                return Err(eyre!(
+                    "Unknown section `[{section}]` in the config file {path}"
+                ))
+                .with_suggestion(|| format!("The sections are {}", SECTIONS.join(", ")));
+            }
+
+            for key in keys.keys() {
+                match section.as_str() {
+                    "" if !settable.iter().any(|(k, _)| *k == setting_name(key)) => {
+                        return Err(eyre!("Unknown setting `{key}` in the config file {path}"))
+                            .with_suggestion(|| {
+                                format!(
+                                    "The settings are the global flags, i.e. {}",
+                                    settable.iter().map(|(k, _)| k).join(", ")
+                                )
+                            })
+                    }
+                    "follow" if key != "tags" => {
+                        return Err(eyre!("Unknown setting `{key}` under `[follow]` in {path}"))
+                            .suggestion("Only `tags` can be set there")
+                    }
+                    "serve" if !serve::SETTINGS.contains(&key.as_str()) => {
+                        return Err(eyre!("Unknown setting `{key}` under `[serve]` in {path}"))
+                            .with_suggestion(|| {
+                                format!("The settings are {}", serve::SETTINGS.join(", "))
+                            })
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A setting's name as written in the config file, where `base-url` and
+/// `base_url` are the same
+fn setting_name(key: &str) -> String {
+    key.trim().replace('-', "_")
+}
+
+/// The global flags the config file can set, by the name of the flag, with
+/// the environment variable each is also read from
+fn settable() -> Vec<(String, String)> {
+    Opts::command()
+        .get_arguments()
+        .filter(|arg| arg.get_long() != Some("config"))
+        .filter_map(|arg| {
+            let env = arg.get_env()?.to_str()?;
+            Some((setting_name(arg.get_long()?), env.to_owned()))
+        })
+        .collect()
+}
+
+/// Hand the top level settings to the flags through their environment
+/// variables when those aren't set, so flags win over the environment which
+/// wins over the config file
+fn apply_settings(config: &Config, settable: &[(String, String)]) {
+    for (key, values) in config.section("") {
+        let env = match settable.iter().find(|(k, _)| *k == setting_name(key)) {
+            Some((_, env)) => env,
+            None => continue,
+        };
+
+        if std::env::var_os(env).is_none() {
+            std::env::set_var(env, values.join(","));
+        }
+    }
+}
+
+/// Everything before a `#` that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn parse_value(s: &str) -> Result<Vec<String>, String> {
+    match s.strip_prefix('[') {
+        Some(inner) => inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("`{s}` is missing a closing `]`"))?
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(parse_scalar)
+            .collect(),
+        None => Ok(vec![parse_scalar(s)?]),
+    }
+}
+
+fn parse_scalar(s: &str) -> Result<String, String> {
+    match s.strip_prefix('"') {
+        Some(rest) => rest
+            .strip_suffix('"')
+            .map(str::to_owned)
+            .ok_or_else(|| format!("`{s}` is missing a closing `\"`")),
+        None => Ok(s.to_owned()),
+    }
+}
+
+/// Where the config file is read from: `--config`, `KSCRPR_CONFIG`, or
+/// `~/.config/kscrpr/config.toml`. This is worked out before the flags are
+/// parsed, as the config file gives the flags their defaults
+pub fn config_path() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    std::env::var_os("KSCRPR_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|d| d.join("kscrpr/config.toml")))
+}
+
+/// A config file with every setting commented out at its default, followed
+/// by the sections, for `kscrpr config init`. The base url has no default so
+/// the one in use is filled in
+fn template() -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# The config file of kscrpr. Flags and environment variables win over what's\n\
+         # set here, and each setting is named after its flag\n"
+    );
+    let _ = writeln!(out, "base_url = \"{}\"\n", opts().base_url);
+
+    for arg in Opts::command().get_arguments() {
+        let key = match (arg.get_long(), arg.get_env()) {
+            (Some(long), Some(_)) if long != "config" && long != "base-url" => setting_name(long),
+            _ => continue,
+        };
+
+        for line in wrap(arg.get_help().unwrap_or_default(), 76) {
+            let _ = writeln!(out, "# {line}");
+        }
+        if let Some(values) = arg.get_possible_values() {
+            let names = values
+                .iter()
+                .filter(|v| !v.is_hide_set())
+                .map(|v| v.get_name());
+            let _ = writeln!(out, "# One of {}", names.format(", "));
+        }
+
+        let value = match arg.get_default_values() {
+            [] if !arg.is_takes_value_set() => "false".to_owned(),
+            [] if arg.is_multiple_occurrences_set() => "[]".to_owned(),
+            [] => "\"\"".to_owned(),
+            defaults => format!(
+                "\"{}\"",
+                defaults.iter().map(|d| d.to_string_lossy()).join(",")
+            ),
+        };
+        let _ = writeln!(out, "# {key} = {value}\n");
+    }
+
+    let _ = writeln!(
+        out,
+        "# The tags `kscrpr fetch tag --followed` fetches\n\
+         [follow]\n\
+         # tags = [\"some-tag\", \"another-tag\"]\n"
+    );
+
+    let _ = writeln!(
+        out,
+        "# The keys bound to actions in the picker and browser, a binding or an array\n\
+         # of them. Actions left out keep their default keys\n\
+         [keymap]"
+    );
+    for action in Action::value_variants() {
+        if let Some(name) = action.to_possible_value() {
+            let keys = action.default_keys().iter().map(|k| format!("\"{k}\""));
+            let _ = writeln!(out, "# {} = [{}]", name.get_name(), keys.format(", "));
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "\n# Who may use `kscrpr serve`: `user:hash` logins made with\n\
+         # `kscrpr config hash-password <user>`, the addresses or ranges clients must\n\
+         # connect from and are turned away from, and a PEM certificate chain and key\n\
+         # to serve HTTPS with\n\
+         [serve]\n\
+         # users = []\n\
+         # allow = [\"192.168.1.0/24\"]\n\
+         # deny = []\n\
+         # tls_cert = \"/path/to/cert.pem\"\n\
+         # tls_key = \"/path/to/key.pem\""
+    );
+
+    let roles = ThemeRole::value_variants()
+        .iter()
+        .filter_map(|r| r.to_possible_value())
+        .map(|v| v.get_name());
+    let _ = writeln!(
+        out,
+        "\n# Colors of the theme picked with `theme`, a color or an array of them where\n\
+         # RGB colors are only used on terminals that support them. The roles are\n\
+         # {}\n\
+         [theme]\n\
+         # label = [\"#499f93\", \"30\"]",
+        roles.format(", ")
+    );
+
+    out
+}
+
+/// Write the template config file where it's read from, refusing to write over
+/// one that's there already unless `force` is given
+pub fn init(force: bool) -> Result<()> {
+    let path = config_path().ok_or_else(|| {
+        eyre!("There's no config directory to put the config file in")
+            .suggestion("Give a path for it with `--config`")
+    })?;
+
+    if path.exists() && !force {
+        return Err(eyre!("There's already a config file at {}", path.display()))
+            .suggestion("Pass `--force` to write over it");
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, template())?;
+    println!("Wrote {}", path.display());
+
+    Ok(())
+}
+
+/// Read a password and print the entry for it to go in `users` under
+/// `[serve]`. Typing it in isn't echoed, it can be piped in too
+pub fn hash_password(user: &str) -> Result<()> {
+    use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+    use crossterm::tty::IsTty;
+
+    if user.contains(':') {
+        return Err(eyre!("User names can't contain `:`"));
+    }
+
+    let mut password = String::new();
+    if std::io::stdin().is_tty() {
+        eprint!("Password for {user}: ");
+        crossterm::terminal::enable_raw_mode()?;
+        let read = (|| -> Result<bool> {
+            loop {
+                if let Event::Key(key) = event::read()? {
+                    match key.code {
+                        KeyCode::Enter => return Ok(true),
+                        KeyCode::Esc => return Ok(false),
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            return Ok(false)
+                        }
+                        KeyCode::Char(c) => password.push(c),
+                        KeyCode::Backspace => {
+                            password.pop();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        })();
+        crossterm::terminal::disable_raw_mode()?;
+        eprintln!();
+        if !read? {
+            return Err(eyre!("Cancelled"));
+        }
+    } else {
+        std::io::stdin().read_line(&mut password)?;
+        password.truncate(password.trim_end_matches(&['\r', '\n'][..]).len());
+    }
+
+    if password.is_empty() {
+        return Err(eyre!("The password can't be empty"));
+    }
+
+    println!("\"{user}:{}\"", serve::hash_password(user, &password));
+
+    Ok(())
+}
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Whether mistakes in the config file should only be warned about, as
+/// they'd otherwise stop `kscrpr config` and `--help` from fixing or
+/// explaining them. The flags aren't parsed yet, so the subcommand is the
+/// first argument that isn't a flag or a flag's value
+fn is_lenient() -> bool {
+    let command = Opts::command();
+    let mut args = std::env::args_os().skip(1);
+
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+
+        match arg.as_ref() {
+            "-h" | "--help" | "-V" | "--version" => return true,
+            "--" => return false,
+            flag if flag.starts_with("--") => {
+                let takes_value = !flag.contains('=')
+                    && command
+                        .get_arguments()
+                        .any(|a| a.get_long() == Some(&flag[2..]) && a.is_takes_value_set());
+                if takes_value {
+                    args.next();
+                }
+            }
+            flag if flag.starts_with('-') => {}
+            subcommand => return subcommand == "config" || subcommand == "help",
+        }
+    }
+
+    false
+}
+
+/// Read the config file and hand its settings to the flags, which needs doing
+/// before they're parsed. Mistakes in it are reported before anything else
+/// happens, except for `kscrpr config` and `--help` where they're warned about
+pub fn load() -> Result<()> {
+    let path = config_path();
+    let lenient = is_lenient();
+
+    let config = CONFIG.get_or_try_init(|| {
+        let config = match &path {
+            Some(path) => Config::read(path),
+            None => Ok(Config::default()),
+        };
+
+        match config {
+            Err(e) if lenient => {
+                eprintln!("Warning: {e}, ignoring the config file");
+                Ok(Config::default())
+            }
+            config => config,
+        }
+    })?;
+
+    let settable = settable();
+    if let Some(path) = &path {
+        match config.check(path, &settable) {
+            Err(e) if lenient => eprintln!("Warning: {e}"),
+            result => result?,
+        }
+    }
+    apply_settings(config, &settable);
+
+    Ok(())
+}
+
+/// The config file's settings, or none if it hasn't been loaded
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
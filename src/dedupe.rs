@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
+
+use crate::archive::Archive;
+use crate::filesystem::{self, page_files, probe_page, FileSystem};
+use crate::opts::{opts, DedupeAction, DedupeMode};
+use crate::utils::user_has_quit;
+
+#[derive(PartialEq, Eq, Hash)]
+enum Signature {
+    Exact([u8; 32]),
+    Heuristic(Vec<(u64, u32, u32)>),
+}
+
+fn signature_of(fs: &FileSystem, archive: &Archive, mode: DedupeMode) -> Result<Signature> {
+    let pages = page_files(&fs.data_dir_of_id(archive.id));
+
+    match mode {
+        DedupeMode::Exact => {
+            let mut hasher = Sha256::new();
+            let mut buf = vec![0; 64 * 1024];
+
+            for page in pages {
+                let mut page_hasher = Sha256::new();
+                let mut file = std::fs::File::open(page)?;
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    page_hasher.update(&buf[..n]);
+                }
+                hasher.update(page_hasher.finalize());
+            }
+
+            Ok(Signature::Exact(hasher.finalize().into()))
+        }
+        DedupeMode::Heuristic => {
+            let mut sig = Vec::with_capacity(pages.len());
+
+            for page in pages {
+                let size = std::fs::metadata(&page)?.len();
+                // only the header is read, decoding every page would be far slower
+                let (_, (w, h)) = probe_page(&page)?;
+                sig.push((size, w, h));
+            }
+
+            Ok(Signature::Heuristic(sig))
+        }
+    }
+}
+
+/// Group archives whose pages are identical (or look identical)
+///
+/// Each group is sorted by id, so the first entry is the oldest upload.
+pub fn find_duplicates(
+    fs: &FileSystem,
+    mode: DedupeMode,
+    msg_bar: &ProgressBar,
+    prog_bar: &ProgressBar,
+) -> Result<Vec<Vec<Archive>>> {
+    let mut groups = HashMap::<Signature, Vec<Archive>>::new();
+
+    msg_bar.set_prefix("Hashing");
+    prog_bar.set_length(fs.sled_db.len() as u64);
+    prog_bar.set_position(0);
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+        prog_bar.inc(1);
+
+        if user_has_quit() {
+            break;
+        }
+
+        // strip the trailing slash so the symlink itself is inspected
+        let data_dir = fs.data_dir_of_id(archive.id).components().collect::<PathBuf>();
+        if data_dir.is_symlink() || !data_dir.exists() {
+            // already deduplicated
+            continue;
+        }
+
+        msg_bar.set_message(format!("({})[{}]", archive.id, archive.name));
+
+        let sig = match signature_of(fs, &archive, mode) {
+            Ok(sig) => sig,
+            Err(e) => {
+                tracing::error!(
+                    error = crate::utils::fuck_error(&e),
+                    id = archive.id,
+                    "Failed to hash archive, skipping it"
+                );
+                continue;
+            }
+        };
+
+        groups.entry(sig).or_default().push(archive);
+    }
+
+    let mut dupes = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|a| a.id);
+            group
+        })
+        .collect::<Vec<_>>();
+    dupes.sort_by_key(|group| group[0].id);
+
+    Ok(dupes)
+}
+
+/// Replace the archive `dupe` with a reference to `keep`
+pub async fn resolve(
+    fs: &FileSystem,
+    keep: &Archive,
+    dupe: &Archive,
+    action: DedupeAction,
+) -> Result<()> {
+    let dupe_data = fs.data_dir_of_id(dupe.id).components().collect::<PathBuf>();
+
+    match action {
+        DedupeAction::Symlink => {
+            std::fs::remove_dir_all(&dupe_data)?;
+            symlink::symlink_dir(fs.data_dir_of_id(keep.id), &dupe_data)?;
+
+            fs.remove_rendered_files_of(dupe.id);
+            for ext in opts().render_format.extensions() {
+                let keep_rendered = fs.rendered_file_of_id_as(keep.id, ext);
+                let dupe_rendered = fs.rendered_file_of_id_as(dupe.id, ext);
+
+                // each part of a split PDF gets its own link
+                let parts = filesystem::split_parts(&keep_rendered);
+                if parts.is_empty() {
+                    symlink::symlink_file(keep_rendered, dupe_rendered)?;
+                }
+                for (i, part) in parts.iter().enumerate() {
+                    symlink::symlink_file(part, filesystem::part_file(&dupe_rendered, i + 1))?;
+                }
+            }
+        }
+        DedupeAction::Tombstone => {
+            fs.remove_archive(dupe).await?;
+            fs.tombstones
+                .insert(dupe.id.to_be_bytes(), keep.id.to_be_bytes().to_vec())?;
+        }
+    }
+
+    Ok(())
+}
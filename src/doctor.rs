@@ -0,0 +1,196 @@
+use color_eyre::Result;
+use indicatif::ProgressBar;
+
+use crate::client::client;
+use crate::filesystem::FileSystem;
+use crate::opts::opts;
+use crate::utils::{fuck_error, user_has_quit};
+
+/// Something wrong with the library, and how to fix it
+pub struct Finding {
+    pub problem: String,
+    pub fix: String,
+    pub fixed: bool,
+}
+
+impl Finding {
+    fn new(problem: String, fix: impl Into<String>) -> Self {
+        Self {
+            problem,
+            fix: fix.into(),
+            fixed: false,
+        }
+    }
+}
+
+/// Check that the database and search index agree on the archives there are
+async fn check_index(fs: &FileSystem, fix: bool, findings: &mut Vec<Finding>) -> Result<()> {
+    let in_db = fs.sled_db.len() as u64;
+    let in_index = fs.searcher.num_docs()?;
+
+    if in_db == in_index {
+        return Ok(());
+    }
+
+    let mut finding = Finding::new(
+        format!("The search index has {in_index} archives but the database has {in_db}"),
+        "Run `kscrpr reindex` to rebuild the search index",
+    );
+
+    if fix {
+        fs.searcher.clear().await?;
+        for archive in fs.fetch_all() {
+            fs.searcher.add_archive(&archive?).await?;
+        }
+        fs.searcher.commit().await?;
+        finding.fixed = true;
+    }
+
+    findings.push(finding);
+
+    Ok(())
+}
+
+/// Check that every archive that wasn't evicted has its images and rendered file
+fn check_content(fs: &FileSystem, fix: bool, findings: &mut Vec<Finding>) -> Result<()> {
+    let is_rendered = |id| fs.rendered_files_of_id(id).iter().all(|f| f.exists());
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+
+        if user_has_quit() {
+            break;
+        }
+
+        if fs.is_evicted(archive.id) {
+            continue;
+        }
+
+        if !fs.data_dir_of_id(archive.id).exists() {
+            let mut finding = Finding::new(
+                format!(
+                    "({}) {} has no images",
+                    archive.id,
+                    archive.pretty_single_line()
+                ),
+                format!(
+                    "Run `kscrpr fetch id {}` to download it again, or `kscrpr remove {}`",
+                    archive.id, archive.id
+                ),
+            );
+
+            if fix {
+                // marking it as evicted cleans up its symlinks and keeps it findable
+                match fs.evict_archive(&archive) {
+                    Ok(()) => {
+                        fs.mark_dirty(archive.id)?;
+                        finding.fixed = true;
+                    }
+                    Err(e) => tracing::error!(
+                        error = fuck_error(&e),
+                        id = archive.id,
+                        "Failed to evict archive"
+                    ),
+                }
+            }
+
+            findings.push(finding);
+            continue;
+        }
+
+        // left to be rendered when it's opened
+        let deferred =
+            opts().no_render && fs.render_pending.contains_key(archive.id.to_be_bytes())?;
+
+        if !is_rendered(archive.id) && !deferred {
+            let mut finding = Finding::new(
+                format!(
+                    "({}) {} isn't rendered",
+                    archive.id,
+                    archive.pretty_single_line()
+                ),
+                format!("Run `kscrpr render {}` to render it", archive.id),
+            );
+
+            if fix {
+                match fs.render_archive(&archive, &ProgressBar::hidden(), &ProgressBar::hidden()) {
+                    Ok(()) => finding.fixed = is_rendered(archive.id),
+                    Err(e) => tracing::error!(
+                        error = fuck_error(&e),
+                        id = archive.id,
+                        "Failed to render archive"
+                    ),
+                }
+            }
+
+            findings.push(finding);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check for symlinks in the tag/artist/parody trees that no longer point anywhere
+fn check_symlinks(fs: &FileSystem, fix: bool, findings: &mut Vec<Finding>) -> Result<()> {
+    let trees = [
+        fs.data_tag_dir(),
+        fs.data_artist_dir(),
+        fs.data_parody_dir(),
+        fs.rendered_tag_dir(),
+        fs.rendered_artist_dir(),
+        fs.rendered_parody_dir(),
+    ];
+
+    for tree in trees {
+        for entry in walkdir::WalkDir::new(tree)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.path_is_symlink() || entry.path().exists() {
+                continue;
+            }
+
+            let mut finding = Finding::new(
+                format!("{} is a broken symlink", entry.path().display()),
+                "Delete it, or run `kscrpr reindex` to rebuild the symlink trees",
+            );
+
+            if fix {
+                std::fs::remove_file(entry.path())?;
+                finding.fixed = true;
+            }
+
+            findings.push(finding);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the site can be reached at all
+async fn check_base_url(findings: &mut Vec<Finding>) {
+    let base_url = &opts().base_url;
+
+    let problem = match client().get(base_url.clone()).send().await {
+        Ok(resp) if resp.status().is_success() => return,
+        Ok(resp) => format!("{base_url} responded with {}", resp.status()),
+        Err(e) => format!("{base_url} couldn't be reached: {e}"),
+    };
+
+    findings.push(Finding::new(
+        problem,
+        "Check your network connection and that --base-url (KSCRPR_BASE_URL) is correct",
+    ));
+}
+
+/// Run every health check, applying the safe fixes if `fix` is set
+pub async fn run(fs: &FileSystem, fix: bool) -> Result<Vec<Finding>> {
+    let mut findings = vec![];
+
+    check_index(fs, fix, &mut findings).await?;
+    check_content(fs, fix, &mut findings)?;
+    check_symlinks(fs, fix, &mut findings)?;
+    check_base_url(&mut findings).await;
+
+    Ok(findings)
+}
@@ -0,0 +1,386 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use color_eyre::{eyre::eyre, Result};
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use indicatif::{HumanBytes, ProgressBar};
+use itertools::Itertools;
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState};
+use tui::{Frame, Terminal};
+
+use crate::archive::Archive;
+use crate::keymap::{Action, Keys};
+use crate::scrape::DownloadSize;
+use crate::theme::theme;
+use crate::utils::{self, user_has_quit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState {
+    /// Waiting for the archives before it
+    Queued,
+    Downloading,
+    Added,
+    /// Skipped by the user, or already local or blacklisted
+    Skipped,
+    /// Held back by the user until they let it go again
+    Held,
+}
+
+impl DownloadState {
+    fn label(self) -> &'static str {
+        match self {
+            DownloadState::Queued => "queued",
+            DownloadState::Downloading => "downloading",
+            DownloadState::Added => "added",
+            DownloadState::Skipped => "skipped",
+            DownloadState::Held => "held",
+        }
+    }
+}
+
+struct Download {
+    id: u32,
+    name: String,
+    size: u64,
+    state: DownloadState,
+    /// The bar the download reports its progress to, while it's in flight
+    progress: Option<ProgressBar>,
+    /// When the progress was last looked at and how far along it was then
+    sampled: Option<(Instant, u64)>,
+    /// Bytes per second since the progress was last looked at
+    speed: f64,
+}
+
+/// What the table shows, shared between the fetch and the thread drawing it
+#[derive(Default)]
+struct Downloads {
+    rows: Vec<Download>,
+    /// What the fetch is up to, i.e. which page of which tag it's on
+    status: String,
+}
+
+/// A table of the archives being fetched, drawn in the terminal by a thread
+/// of its own while the fetch carries on. Archives can be skipped or held
+/// back from the table, and quitting stops the fetch after the archive in
+/// flight, the same as Ctrl-C does for the progress bars
+pub struct DownloadView {
+    downloads: Arc<Mutex<Downloads>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl DownloadView {
+    /// Take over the terminal and start drawing the table
+    pub fn start() -> Result<Self> {
+        // read before taking over the terminal, so mistakes in it are shown
+        let keys = Keys::new()?;
+
+        let downloads = Arc::new(Mutex::new(Downloads::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::spawn({
+            let downloads = Arc::clone(&downloads);
+            let stop = Arc::clone(&stop);
+            move || run_view(&downloads, &stop, keys)
+        });
+
+        Ok(DownloadView {
+            downloads,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Downloads> {
+        // a panic while drawing doesn't leave the rows in a bad state
+        self.downloads.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_status(&self, status: String) {
+        self.lock().status = status;
+    }
+
+    /// Add archives to the bottom of the table, waiting to be downloaded
+    pub fn queue<'a>(&self, archives: impl IntoIterator<Item = (&'a Archive, DownloadSize)>) {
+        let mut downloads = self.lock();
+
+        for (archive, size) in archives {
+            downloads.rows.push(Download {
+                id: archive.id,
+                name: archive.name.clone(),
+                size: size.0 as u64,
+                state: DownloadState::Queued,
+                progress: None,
+                sampled: None,
+                speed: 0.0,
+            });
+        }
+    }
+
+    /// The state of an archive's download, which the user may have changed
+    pub fn state(&self, id: u32) -> DownloadState {
+        self.lock()
+            .rows
+            .iter()
+            .rfind(|r| r.id == id)
+            .map_or(DownloadState::Queued, |r| r.state)
+    }
+
+    /// Change the state of an archive's download, with the bar its progress
+    /// goes to once it's in flight
+    pub fn set_state(&self, id: u32, state: DownloadState, progress: Option<&ProgressBar>) {
+        if let Some(row) = self.lock().rows.iter_mut().rfind(|r| r.id == id) {
+            row.state = state;
+            row.progress = progress.cloned();
+            row.sampled = None;
+            row.speed = 0.0;
+        }
+    }
+
+    /// Stop drawing the table and give the terminal back
+    pub fn finish(mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(eyre!("The download table panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DownloadView {
+    fn drop(&mut self) {
+        // the fetch failed, so the terminal still needs giving back
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_view(downloads: &Mutex<Downloads>, stop: &AtomicBool, keys: Keys) -> Result<()> {
+    crossterm::terminal::enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+    terminal.clear()?;
+
+    let result = view_loop(&mut terminal, downloads, stop, keys);
+
+    crossterm::terminal::disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+fn view_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    downloads: &Mutex<Downloads>,
+    stop: &AtomicBool,
+    mut keys: Keys,
+) -> Result<()> {
+    let mut state = TableState::default();
+    state.select(Some(0));
+
+    while !stop.load(Ordering::SeqCst) {
+        {
+            let mut downloads = downloads.lock().unwrap_or_else(|e| e.into_inner());
+            sample_speeds(&mut downloads.rows);
+            terminal.draw(|f| ui(f, &downloads, &mut state))?;
+        }
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+
+        let mut downloads = downloads.lock().unwrap_or_else(|e| e.into_inner());
+        let last = downloads.rows.len().saturating_sub(1);
+        let selected = state.selected().unwrap_or(0).min(last);
+
+        // the table's own keys come before the keymap, like the browser's
+        match (key.code, keys.press(key)) {
+            (KeyCode::Char('s'), _) => {
+                if let Some(row) = downloads.rows.get_mut(selected) {
+                    if matches!(row.state, DownloadState::Queued | DownloadState::Held) {
+                        row.state = DownloadState::Skipped;
+                    }
+                }
+            }
+            (KeyCode::Char(' ') | KeyCode::Char('h'), _) => {
+                if let Some(row) = downloads.rows.get_mut(selected) {
+                    row.state = match row.state {
+                        DownloadState::Queued => DownloadState::Held,
+                        DownloadState::Held => DownloadState::Queued,
+                        state => state,
+                    };
+                }
+            }
+            (_, Some(Action::Quit)) => utils::RUNNING.store(false, Ordering::SeqCst),
+            (_, Some(Action::Down)) => state.select(Some((selected + 1).min(last))),
+            (_, Some(Action::Up)) => state.select(Some(selected.saturating_sub(1))),
+            (_, Some(Action::PageDown | Action::HalfPageDown)) => {
+                state.select(Some((selected + 10).min(last)))
+            }
+            (_, Some(Action::PageUp | Action::HalfPageUp)) => {
+                state.select(Some(selected.saturating_sub(10)))
+            }
+            (_, Some(Action::Top)) => state.select(Some(0)),
+            (_, Some(Action::Bottom)) => state.select(Some(last)),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Work out how fast each download in flight is going since it was last
+/// looked at
+fn sample_speeds(rows: &mut [Download]) {
+    let now = Instant::now();
+
+    for row in rows {
+        let position = match &row.progress {
+            Some(progress) => progress.position(),
+            None => continue,
+        };
+
+        if let Some((then, before)) = row.sampled {
+            let elapsed = now.duration_since(then).as_secs_f64();
+            if elapsed > 0.0 {
+                let current = position.saturating_sub(before) as f64 / elapsed;
+                // smoothed, as samples a tick apart jump about a lot
+                row.speed = row.speed * 0.7 + current * 0.3;
+            }
+        }
+        row.sampled = Some((now, position));
+    }
+}
+
+/// The widths of the id, name, speed, progress, and state columns
+const WIDTHS: [Constraint; 5] = [
+    Constraint::Length(8),
+    Constraint::Min(20),
+    Constraint::Length(12),
+    Constraint::Length(24),
+    Constraint::Length(12),
+];
+
+fn ui<B: Backend>(f: &mut Frame<B>, downloads: &Downloads, state: &mut TableState) {
+    let chunks = Layout::default()
+        .margin(1)
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    let count = |s: DownloadState| downloads.rows.iter().filter(|r| r.state == s).count();
+    let status = Paragraph::new(Spans::from(vec![
+        Span::styled(
+            downloads.status.as_str(),
+            Style::default()
+                .fg(theme().label.tui())
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(
+            format!(
+                "  {} added, {} queued, {} held, {} skipped",
+                count(DownloadState::Added),
+                count(DownloadState::Queued),
+                count(DownloadState::Held),
+                count(DownloadState::Skipped)
+            ),
+            Style::default()
+                .fg(theme().dim.tui())
+                .add_modifier(Modifier::DIM),
+        ),
+    ]));
+    f.render_widget(status, chunks[0]);
+
+    let rows = downloads
+        .rows
+        .iter()
+        .map(|row| {
+            let (speed, progress) = match (&row.progress, row.state) {
+                (Some(progress), DownloadState::Downloading) => {
+                    let done = progress.position().min(row.size);
+                    let percent = if row.size > 0 {
+                        format!(" {:>3}%", done * 100 / row.size)
+                    } else {
+                        String::new()
+                    };
+                    (
+                        format!("{}/s", HumanBytes(row.speed as u64)),
+                        format!("{}/{}{}", HumanBytes(done), HumanBytes(row.size), percent),
+                    )
+                }
+                _ => (String::new(), HumanBytes(row.size).to_string()),
+            };
+
+            let state_style = match row.state {
+                DownloadState::Downloading => Style::default().fg(theme().accent.tui()),
+                DownloadState::Added => Style::default().fg(theme().value.tui()),
+                _ => Style::default()
+                    .fg(theme().dim.tui())
+                    .add_modifier(Modifier::DIM),
+            };
+
+            Row::new(vec![
+                Cell::from(row.id.to_string()),
+                Cell::from(Span::styled(
+                    row.name.as_str(),
+                    Style::default().fg(theme().label.tui()),
+                )),
+                Cell::from(speed),
+                Cell::from(progress),
+                Cell::from(Span::styled(row.state.label(), state_style)),
+            ])
+        })
+        .collect_vec();
+
+    let table = Table::new(rows)
+        .header(
+            Row::new(vec!["Id", "Name", "Speed", "Progress", "State"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title("Downloads"))
+        .widths(&WIDTHS)
+        .highlight_style(
+            Style::default()
+                .fg(theme().highlight.tui())
+                .add_modifier(Modifier::REVERSED),
+        );
+    f.render_stateful_widget(table, chunks[1], state);
+
+    let help = if user_has_quit() {
+        "Stopping once the archive in flight is added"
+    } else {
+        "s skip · space hold or let go · q stop after the archive in flight"
+    };
+    f.render_widget(
+        Paragraph::new(Span::styled(
+            help,
+            Style::default()
+                .fg(theme().dim.tui())
+                .add_modifier(Modifier::DIM),
+        )),
+        chunks[2],
+    );
+}
@@ -0,0 +1,190 @@
+use std::fmt::Write as _;
+
+use crate::archive::Archive;
+use crate::artifacts::escape_xml;
+use crate::utils::iso8601;
+
+/// The contents of the `mimetype` file, which has to be the first file in
+/// the zip and stored uncompressed
+pub const MIMETYPE: &str = "application/epub+zip";
+
+/// Points readers at the package document
+pub const CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+/// A page image stored in the EPUB, each gets its own fixed layout document
+pub struct Page {
+    pub number: usize,
+    /// The extension of the image, i.e. `jpg`
+    pub ext: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Page {
+    /// Path of the image, relative to `OEBPS/`
+    pub fn image(&self) -> String {
+        format!("{:04}.{}", self.number, self.ext)
+    }
+
+    /// Path of the page's document, relative to `OEBPS/`
+    pub fn document(&self) -> String {
+        format!("page-{:04}.xhtml", self.number)
+    }
+
+    fn media_type(&self) -> &'static str {
+        match self.ext.as_str() {
+            "png" => "image/png",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => "image/jpeg",
+        }
+    }
+
+    /// A document showing just the page image, sized to the image
+    pub fn xhtml(&self, title: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+  <title>{title} - {number}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>html, body {{ margin: 0; padding: 0; }} img {{ display: block; width: {width}px; height: {height}px; }}</style>
+</head>
+<body>
+  <img src="{image}" alt="Page {number}"/>
+</body>
+</html>
+"#,
+            title = escape_xml(title),
+            number = self.number,
+            width = self.width,
+            height = self.height,
+            image = self.image(),
+        )
+    }
+}
+
+/// The navigation document, which EPUB3 requires even with only one entry
+pub fn nav(archive: &Archive, pages: &[Page]) -> String {
+    let first = pages.first().map_or_else(String::new, Page::document);
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+</head>
+<body>
+  <nav epub:type="toc">
+    <ol>
+      <li><a href="{first}">{title}</a></li>
+    </ol>
+  </nav>
+</body>
+</html>
+"#,
+        title = escape_xml(&archive.name),
+    )
+}
+
+/// The package document, holding the metadata, the files, and their order
+pub fn package(archive: &Archive, pages: &[Page], modified: u64, rtl: bool) -> String {
+    let mut opf = String::new();
+
+    let _ = writeln!(opf, r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    let _ = writeln!(
+        opf,
+        r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">"#
+    );
+    let _ = writeln!(
+        opf,
+        r#"  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">"#
+    );
+    let _ = writeln!(
+        opf,
+        r#"    <dc:identifier id="id">urn:kscrpr:{}</dc:identifier>"#,
+        archive.id
+    );
+    let _ = writeln!(
+        opf,
+        "    <dc:title>{}</dc:title>",
+        escape_xml(&archive.name)
+    );
+    for artist in &archive.artists {
+        let _ = writeln!(opf, "    <dc:creator>{}</dc:creator>", escape_xml(artist));
+    }
+    for tag in &archive.tags {
+        let _ = writeln!(
+            opf,
+            "    <dc:subject>{}</dc:subject>",
+            escape_xml(&tag.name)
+        );
+    }
+    let _ = writeln!(
+        opf,
+        "    <dc:source>{}</dc:source>",
+        escape_xml(archive.base_url.as_str())
+    );
+    let _ = writeln!(opf, "    <dc:language>und</dc:language>");
+    let _ = writeln!(
+        opf,
+        r#"    <meta property="dcterms:modified">{}</meta>"#,
+        iso8601(modified)
+    );
+    let _ = writeln!(
+        opf,
+        r#"    <meta property="rendition:layout">pre-paginated</meta>"#
+    );
+    let _ = writeln!(opf, r#"    <meta property="rendition:spread">none</meta>"#);
+    // older readers only find the cover through this
+    let _ = writeln!(opf, r#"    <meta name="cover" content="image-0001"/>"#);
+    let _ = writeln!(opf, "  </metadata>");
+
+    let _ = writeln!(opf, "  <manifest>");
+    let _ = writeln!(
+        opf,
+        r#"    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#
+    );
+    for page in pages {
+        let cover = if page.number == 1 {
+            r#" properties="cover-image""#
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            opf,
+            r#"    <item id="image-{:04}" href="{}" media-type="{}"{cover}/>"#,
+            page.number,
+            page.image(),
+            page.media_type()
+        );
+        let _ = writeln!(
+            opf,
+            r#"    <item id="page-{:04}" href="{}" media-type="application/xhtml+xml"/>"#,
+            page.number,
+            page.document()
+        );
+    }
+    let _ = writeln!(opf, "  </manifest>");
+
+    if rtl {
+        let _ = writeln!(opf, r#"  <spine page-progression-direction="rtl">"#);
+    } else {
+        let _ = writeln!(opf, "  <spine>");
+    }
+    for page in pages {
+        let _ = writeln!(opf, r#"    <itemref idref="page-{:04}"/>"#, page.number);
+    }
+    let _ = writeln!(opf, "  </spine>");
+    let _ = writeln!(opf, "</package>");
+
+    opf
+}
@@ -0,0 +1,59 @@
+use color_eyre::Result;
+
+use crate::archive::Archive;
+use crate::filesystem::FileSystem;
+
+/// Pick the least recently used archives to evict so the library fits in
+/// `max_size` bytes
+///
+/// Returns the current size of the library along with the archives to evict
+/// and how many bytes evicting each of them frees.
+pub fn plan(fs: &FileSystem, max_size: u64) -> Result<(u64, Vec<(Archive, u64)>)> {
+    let mut total = 0;
+    let mut candidates = vec![];
+
+    // archives others were deduplicated into can't be evicted
+    let dedupe_links = fs.dedupe_links()?;
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+
+        if fs.is_evicted(archive.id) {
+            continue;
+        }
+
+        let size = fs.content_size_of(archive.id);
+        total += size;
+
+        if size > 0 && !dedupe_links.contains_key(&archive.id) {
+            candidates.push((fs.last_used(archive.id), archive, size));
+        }
+    }
+
+    candidates.sort_by_key(|(last_used, archive, _)| (*last_used, archive.id));
+
+    let mut remaining = total;
+    let mut to_evict = vec![];
+
+    for (_, archive, size) in candidates {
+        if remaining <= max_size {
+            break;
+        }
+
+        remaining -= size;
+        to_evict.push((archive, size));
+    }
+
+    Ok((total, to_evict))
+}
+
+/// Evict archives until the library fits in `max_size` bytes
+pub fn enforce(fs: &FileSystem, max_size: u64) -> Result<Vec<(Archive, u64)>> {
+    let (_, to_evict) = plan(fs, max_size)?;
+
+    for (archive, _) in &to_evict {
+        fs.evict_archive(archive)?;
+    }
+
+    Ok(to_evict)
+}
@@ -1,24 +1,68 @@
-use std::collections::HashSet;
-use std::ffi::OsStr;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Cursor, Write};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use color_eyre::SectionExt;
 use color_eyre::{eyre::eyre, Help, Result};
+use image::imageops::{self, FilterType};
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgb, RgbImage};
 use indicatif::ProgressBar;
-use printpdf::{image_crate::GenericImageView, PdfDocument, Px};
 use tracing::{debug, info_span, Instrument};
+use url::Url;
 
-use crate::archive::Archive;
-use crate::opts::{self, Opts};
+use crate::archive::{normalize_tag, Archive, SizeMismatch};
+use crate::artifacts;
+use crate::epub;
+use crate::opts::{self, IndexType, MissingField, Opts, ReadingDirection, RenderProfile};
+use crate::pdf::{self, PdfWriter};
 use crate::scrape::DownloadSize;
-use crate::search::Searcher;
-use crate::utils::fuck_error;
+use crate::search::{PageRange, Searcher};
+use crate::template::Piece;
+use crate::theme::theme;
+use crate::utils::{fuck_error, natural_cmp, unix_now, user_has_quit};
+
+/// How many times to download an archive whose zip comes back the wrong size
+const DOWNLOAD_ATTEMPTS: usize = 3;
 
 pub struct FileSystem {
     config: &'static Opts,
     pub sled_db: sled::Db,
+    /// Ids of archives removed as duplicates, mapped to the id that was kept
+    pub tombstones: sled::Tree,
+    /// Sanitized path components mapped back to the names they came from
+    pub path_names: sled::Tree,
+    /// Tags whose archives are never downloaded
+    pub blacklist: sled::Tree,
+    /// Ids of archives whose thumbnails and gallery/catalog entries are stale
+    pub dirty: sled::Tree,
+    /// Ids of archives whose rendering was cancelled
+    pub render_pending: sled::Tree,
+    /// Ids of archives mapped to when they were last opened, in unix seconds
+    pub last_opened: sled::Tree,
+    /// Ids of archives whose images and rendered file were evicted to save space
+    pub evicted: sled::Tree,
+    /// Ids of archives mapped to the metadata JSON the site gave for them
+    pub raw_metadata: sled::Tree,
+    /// Where paused long running operations stopped, by operation
+    pub paused: sled::Tree,
+    /// A moving average of recent download speeds, in bytes per second
+    pub download_rate: sled::Tree,
+    /// Ids of archives mapped to whether they read right to left, overriding `--rtl`
+    pub rtl: sled::Tree,
+    /// Ids of archives mapped to the numbers of pages that couldn't be decoded
+    /// when they were last rendered
+    pub corrupt_pages: sled::Tree,
+    /// Ids of archives marked as favorites
+    pub favorites: sled::Tree,
+    /// Searches made in `kscrpr ui`, keyed by the order they were made in
+    pub search_history: sled::Tree,
+    /// What the picker was picking from mapped to the id of the archive
+    /// highlighted when it was left
+    pub picker_positions: sled::Tree,
     pub searcher: Searcher,
 }
 
@@ -26,77 +70,192 @@ impl FileSystem {
     pub fn open() -> Result<Self> {
         let config = opts::opts();
 
-        tracing::debug!("Ensuring data directory at {:?}", config.base_dir);
+        let data_root = config.data_root();
+        let state_root = config.state_root();
+
+        tracing::debug!("Ensuring data directory at {:?}", data_root);
+        tracing::debug!("Ensuring state directory at {:?}", state_root);
 
-        let dirs = [
+        let data_dirs = [
             "data/by_ids/",
             "data/by_tags/",
             "data/by_artist/",
+            "data/by_parody/",
             "rendered/by_ids/",
             "rendered/by_tags/",
             "rendered/by_artist/",
+            "rendered/by_parody/",
+        ];
+
+        let state_dirs = [
             "meta/tantivy/",
             "meta/sled/",
+            "meta/thumbs/",
+            "meta/html/",
+            "meta/opds/",
         ];
 
-        for dir in dirs {
-            std::fs::create_dir_all(config.base_dir.join(dir))?;
+        for dir in data_dirs {
+            std::fs::create_dir_all(data_root.join(dir))?;
         }
 
-        let sled_db = sled::open(config.base_dir.join("meta/sled/"))
+        for dir in state_dirs {
+            std::fs::create_dir_all(state_root.join(dir))?;
+        }
+
+        let sled_db = sled::open(state_root.join("meta/sled/"))
             .note("While opening/creating the sled database")?;
 
-        let searcher = Searcher::new(&config.base_dir.join("meta/tantivy/"))
-            .note("While opening/creating the tantivy database")?;
+        let tombstones = sled_db.open_tree("tombstones")?;
+        let path_names = sled_db.open_tree("path_names")?;
+        let blacklist = sled_db.open_tree("blacklist")?;
+        let dirty = sled_db.open_tree("dirty")?;
+        let render_pending = sled_db.open_tree("render_pending")?;
+        let last_opened = sled_db.open_tree("last_opened")?;
+        let evicted = sled_db.open_tree("evicted")?;
+        let raw_metadata = sled_db.open_tree("raw_metadata")?;
+        let paused = sled_db.open_tree("paused")?;
+        let download_rate = sled_db.open_tree("download_rate")?;
+        let rtl = sled_db.open_tree("rtl")?;
+        let corrupt_pages = sled_db.open_tree("corrupt_pages")?;
+        let favorites = sled_db.open_tree("favorites")?;
+        let search_history = sled_db.open_tree("search_history")?;
+        let picker_positions = sled_db.open_tree("picker_positions")?;
+
+        let mut searcher = Searcher::new(
+            &state_root.join("meta/tantivy/"),
+            sled_db.open_tree("query_cache")?,
+            config.search_tokenizer,
+        )
+        .note("While opening/creating the tantivy database")?;
+
+        if searcher.migrated() {
+            eprintln!("The search index was built by an older version, rebuilding it");
+            let archives = sled_db
+                .iter()
+                .values()
+                .map(|v| -> Result<Archive> { Ok(serde_cbor::from_slice::<Archive>(&v?)?) });
+            let count = searcher
+                .refill(archives)
+                .note("While rebuilding the search index")
+                .suggestion("Run `kscrpr reindex` to rebuild it from scratch")?;
+            tracing::info!(count, "Rebuilt the search index");
+        }
 
-        Ok(Self {
+        let fs = Self {
             config,
             sled_db,
+            tombstones,
+            path_names,
+            blacklist,
+            dirty,
+            render_pending,
+            last_opened,
+            evicted,
+            raw_metadata,
+            paused,
+            download_rate,
+            rtl,
+            corrupt_pages,
+            favorites,
+            search_history,
+            picker_positions,
             searcher,
-        })
+        };
+
+        std::fs::create_dir_all(fs.staging_dir())?;
+
+        Ok(fs)
     }
 
     pub fn reset_tantivy_dir() {
         let config = opts::opts();
-        let _ = std::fs::remove_dir_all(config.base_dir.join("meta/tantivy/"));
+        let _ = std::fs::remove_dir_all(config.state_root().join("meta/tantivy/"));
     }
 
     pub fn reset_tags_dir() {
         let config = opts::opts();
-        let _ = std::fs::remove_dir_all(config.base_dir.join("data/by_tags/"));
+        let _ = std::fs::remove_dir_all(config.data_root().join("data/by_tags/"));
     }
 
     pub fn reset_artists_dir() {
         let config = opts::opts();
-        let _ = std::fs::remove_dir_all(config.base_dir.join("data/by_artist/"));
+        let _ = std::fs::remove_dir_all(config.data_root().join("data/by_artist/"));
+    }
+
+    pub fn reset_parodies_dir() {
+        let config = opts::opts();
+        let _ = std::fs::remove_dir_all(config.data_root().join("data/by_parody/"));
     }
 
     pub fn reset_rendered_dir() {
         let config = opts::opts();
-        let _ = std::fs::remove_dir_all(config.base_dir.join("rendered/by_tags/"));
-        let _ = std::fs::remove_dir_all(config.base_dir.join("rendered/by_artist/"));
+        let data_root = config.data_root();
+        let _ = std::fs::remove_dir_all(data_root.join("rendered/by_tags/"));
+        let _ = std::fs::remove_dir_all(data_root.join("rendered/by_artist/"));
+        let _ = std::fs::remove_dir_all(data_root.join("rendered/by_parody/"));
     }
 
     pub fn data_dir(&self) -> PathBuf {
-        self.config.base_dir.join("data/")
+        self.config.data_root().join("data/")
     }
 
     pub fn meta_dir(&self) -> PathBuf {
-        self.config.base_dir.join("meta/")
+        self.config.state_root().join("meta/")
     }
 
     pub fn rendered_dir(&self) -> PathBuf {
-        self.config.base_dir.join("rendered/")
+        self.config.data_root().join("rendered/")
+    }
+
+    /// Scratch space for unpacking imports
+    pub fn import_staging_dir(&self) -> PathBuf {
+        match self.config.cache_root() {
+            Some(cache) => cache.join("import/"),
+            None => self.meta_dir().join("import/"),
+        }
     }
 
     pub fn tantivy_dir(&self) -> PathBuf {
-        self.meta_dir().join("sled/")
+        self.meta_dir().join("tantivy/")
     }
 
     pub fn sled_dir(&self) -> PathBuf {
         self.meta_dir().join("sled/")
     }
 
+    pub fn thumbs_dir(&self) -> PathBuf {
+        self.meta_dir().join("thumbs/")
+    }
+
+    pub fn thumb_file_of_id(&self, id: u32) -> PathBuf {
+        self.thumbs_dir().join(format!("{id}.jpg"))
+    }
+
+    pub fn html_fragment_dir(&self) -> PathBuf {
+        self.meta_dir().join("html/")
+    }
+
+    pub fn html_fragment_of_id(&self, id: u32) -> PathBuf {
+        self.html_fragment_dir().join(format!("{id}.html"))
+    }
+
+    pub fn opds_fragment_dir(&self) -> PathBuf {
+        self.meta_dir().join("opds/")
+    }
+
+    pub fn opds_fragment_of_id(&self, id: u32) -> PathBuf {
+        self.opds_fragment_dir().join(format!("{id}.xml"))
+    }
+
+    pub fn gallery_file(&self) -> PathBuf {
+        self.meta_dir().join("gallery.html")
+    }
+
+    pub fn opds_file(&self) -> PathBuf {
+        self.meta_dir().join("opds.xml")
+    }
+
     pub fn data_id_dir(&self) -> PathBuf {
         self.data_dir().join("by_ids/")
     }
@@ -109,26 +268,53 @@ impl FileSystem {
         self.data_dir().join("by_artist/")
     }
 
+    pub fn data_parody_dir(&self) -> PathBuf {
+        self.data_dir().join("by_parody/")
+    }
+
     pub fn data_dir_of_id(&self, id: u32) -> PathBuf {
         self.data_id_dir().join(format!("{id}/"))
     }
 
+    /// Where archives are extracted to before being moved into `data/by_ids/`
+    pub fn staging_dir(&self) -> PathBuf {
+        match self.config.cache_root() {
+            Some(cache) => cache.join("staging/"),
+            None => self.data_dir().join(".staging/"),
+        }
+    }
+
+    pub fn staging_dir_of_id(&self, id: u32) -> PathBuf {
+        self.staging_dir().join(format!("{id}/"))
+    }
+
     pub fn data_dir_of_tag(&self, tag: &str) -> PathBuf {
-        self.data_tag_dir().join(format!("{tag}/"))
+        self.data_tag_dir()
+            .join(format!("{}/", tag_component(tag)))
     }
 
     pub fn data_dir_of_artist(&self, artist: &str) -> PathBuf {
-        self.data_artist_dir().join(format!("{artist}/"))
+        self.data_artist_dir()
+            .join(format!("{}/", sanitize_component(artist)))
     }
 
-    pub fn data_dir_for_archive_by_artist(&self, archive: &Archive) -> PathBuf {
-        let name = format!("{}-{}", archive.name, archive.id);
-        self.data_dir_of_artist(&archive.artist).join(name)
+    pub fn data_dir_of_parody(&self, parody: &str) -> PathBuf {
+        self.data_parody_dir()
+            .join(format!("{}/", sanitize_component(parody)))
+    }
+
+    pub fn data_dir_for_archive_by_artist(&self, artist: &str, archive: &Archive) -> PathBuf {
+        self.data_dir_of_artist(artist)
+            .join(archive_component(archive, ""))
     }
 
     pub fn data_dir_for_archive_by_tag(&self, tag: &str, archive: &Archive) -> PathBuf {
-        let name = format!("{}-{}", archive.name, archive.id);
-        self.data_dir_of_tag(tag).join(name)
+        self.data_dir_of_tag(tag).join(archive_component(archive, ""))
+    }
+
+    pub fn data_dir_for_archive_by_parody(&self, archive: &Archive) -> PathBuf {
+        self.data_dir_of_parody(&archive.parody)
+            .join(archive_component(archive, ""))
     }
 
     pub fn rendered_id_dir(&self) -> PathBuf {
@@ -143,33 +329,611 @@ impl FileSystem {
         self.rendered_dir().join("by_artist/")
     }
 
+    pub fn rendered_parody_dir(&self) -> PathBuf {
+        self.rendered_dir().join("by_parody/")
+    }
+
+    pub fn rendered_eink_dir(&self) -> PathBuf {
+        self.rendered_dir().join("eink/")
+    }
+
+    /// The PDF of an archive rendered with `--profile eink`
+    pub fn rendered_eink_file_of_id(&self, id: u32) -> PathBuf {
+        self.rendered_eink_dir().join(format!("{id}.pdf"))
+    }
+
+    /// The rendered file of an archive that's opened and printed, in the first
+    /// of the `--render-format` extensions
     pub fn rendered_file_of_id(&self, id: u32) -> PathBuf {
-        self.rendered_id_dir().join(format!("{id}.pdf"))
+        let rendered_file =
+            self.rendered_file_of_id_as(id, self.config.render_format.extensions()[0]);
+
+        // a split PDF is opened at its first part
+        split_parts(&rendered_file)
+            .into_iter()
+            .next()
+            .unwrap_or(rendered_file)
+    }
+
+    pub fn rendered_file_of_id_as(&self, id: u32, ext: &str) -> PathBuf {
+        self.rendered_id_dir().join(format!("{id}.{ext}"))
+    }
+
+    /// Every rendered file of an archive for the current `--render-format`,
+    /// with each part of a split PDF
+    pub fn rendered_files_of_id(&self, id: u32) -> Vec<PathBuf> {
+        self.config
+            .render_format
+            .extensions()
+            .iter()
+            .flat_map(|ext| rendered_parts(&self.rendered_file_of_id_as(id, ext)))
+            .collect()
     }
 
     pub fn rendered_dir_of_tag(&self, tag: &str) -> PathBuf {
-        self.rendered_tag_dir().join(format!("{tag}/"))
+        self.rendered_tag_dir()
+            .join(format!("{}/", tag_component(tag)))
     }
 
     pub fn rendered_dir_of_artist(&self, artist: &str) -> PathBuf {
-        self.rendered_artist_dir().join(format!("{artist}/"))
+        self.rendered_artist_dir()
+            .join(format!("{}/", sanitize_component(artist)))
+    }
+
+    pub fn rendered_dir_of_parody(&self, parody: &str) -> PathBuf {
+        self.rendered_parody_dir()
+            .join(format!("{}/", sanitize_component(parody)))
+    }
+
+    pub fn rendered_file_for_archive_by_artist(
+        &self,
+        artist: &str,
+        archive: &Archive,
+        ext: &str,
+    ) -> PathBuf {
+        self.rendered_dir_of_artist(artist)
+            .join(rendered_component(archive, ext))
+    }
+
+    pub fn rendered_file_for_archive_by_tag(
+        &self,
+        tag: &str,
+        archive: &Archive,
+        ext: &str,
+    ) -> PathBuf {
+        self.rendered_dir_of_tag(tag)
+            .join(rendered_component(archive, ext))
+    }
+
+    pub fn rendered_file_for_archive_by_parody(&self, archive: &Archive, ext: &str) -> PathBuf {
+        self.rendered_dir_of_parody(&archive.parody)
+            .join(rendered_component(archive, ext))
     }
 
-    pub fn rendered_file_for_archive_by_artist(&self, archive: &Archive) -> PathBuf {
-        let name = format!("{}-{}.pdf", archive.name, archive.id);
-        self.rendered_dir_of_artist(&archive.artist).join(name)
+    /// Remember what the sanitized path components of an archive were
+    /// generated from, so they can be mapped back with `original_names_of`
+    pub fn record_path_names(&self, archive: &Archive) -> Result<()> {
+        let tags = archive
+            .tags
+            .iter()
+            .map(|t| (tag_component(&t.name), t.name.as_str()));
+        let names = archive
+            .artists
+            .iter()
+            .map(String::as_str)
+            .chain([archive.parody.as_str(), archive.name.as_str()])
+            .map(|name| (sanitize_component(name), name));
+
+        for (component, name) in tags.chain(names) {
+            if component == name {
+                continue;
+            }
+
+            let mut originals = self.original_names_of(&component)?;
+            if !originals.iter().any(|o| o == name) {
+                originals.push(name.to_owned());
+                self.path_names
+                    .insert(component, serde_cbor::to_vec(&originals)?)?;
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn rendered_file_for_archive_by_tag(&self, tag: &str, archive: &Archive) -> PathBuf {
-        let name = format!("{}-{}.pdf", archive.name, archive.id);
-        self.rendered_dir_of_tag(tag).join(name)
+    /// The tags, artists, or archive names a path component was generated
+    /// from, as several names can sanitize to the same component. Empty if
+    /// the component is a name as it is.
+    pub fn original_names_of(&self, component: &str) -> Result<Vec<String>> {
+        let names = match self.path_names.get(component)? {
+            // older versions stored a single name as a plain string
+            Some(v) => serde_cbor::from_slice(&v)
+                .unwrap_or_else(|_| vec![String::from_utf8_lossy(&v).into_owned()]),
+            None => vec![],
+        };
+
+        Ok(names)
     }
 
     pub fn has_archive(&self, id: u32) -> bool {
-        self.data_dir_of_id(id).exists()
+        self.data_dir_of_id(id).exists() || self.is_tombstoned(id)
+    }
+
+    pub fn is_tombstoned(&self, id: u32) -> bool {
+        matches!(self.tombstones.contains_key(id.to_be_bytes()), Ok(true))
+    }
+
+    pub fn is_evicted(&self, id: u32) -> bool {
+        matches!(self.evicted.contains_key(id.to_be_bytes()), Ok(true))
+    }
+
+    /// Open the rendered file of an archive, recording when it was opened
+    pub fn open_archive(&self, id: u32) -> Result<()> {
+        if self.is_evicted(id) {
+            return Err(eyre!("The content of archive {} was evicted", id))
+                .suggestion(format!("Run `kscrpr fetch id {id}` to download it again"));
+        }
+
+        let now = unix_now();
+        self.last_opened
+            .insert(id.to_be_bytes(), now.to_be_bytes().to_vec())?;
+
+        if !self.rendered_file_of_id(id).exists() {
+            let archive = self.fetch_doc(id)?;
+            eprintln!(
+                "Rendering {} before opening it",
+                archive.pretty_single_line()
+            );
+            self.render_archive(&archive, &ProgressBar::hidden(), &ProgressBar::hidden())?;
+
+            if !self.rendered_file_of_id(id).exists() {
+                return Err(eyre!("Rendering archive {} was cancelled", id))
+                    .suggestion(format!("Run `kscrpr render {id}` to render it"));
+            }
+        }
+
+        opener::open(self.rendered_file_of_id(id))?;
+
+        Ok(())
+    }
+
+    /// When an archive was last opened, falling back to when it was downloaded
+    pub fn last_used(&self, id: u32) -> u64 {
+        if let Ok(Some(v)) = self.last_opened.get(id.to_be_bytes()) {
+            if let Ok(bytes) = v.as_ref().try_into() {
+                return u64::from_be_bytes(bytes);
+            }
+        }
+
+        std::fs::metadata(self.data_dir_of_id(id))
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs())
+    }
+
+    /// Bytes used by an archive's images and rendered files
+    ///
+    /// Deduplicated archives whose content is a symlink to another archive
+    /// take up no space.
+    pub fn content_size_of(&self, id: u32) -> u64 {
+        let data_dir = self.data_dir_of_id(id).components().collect::<PathBuf>();
+
+        let data_size = if data_dir.is_symlink() {
+            0
+        } else {
+            walkdir::WalkDir::new(&data_dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum()
+        };
+
+        let rendered_size: u64 = RENDERED_EXTENSIONS
+            .iter()
+            .map(|ext| self.rendered_file_of_id_as(id, ext))
+            .chain([self.rendered_eink_file_of_id(id)])
+            .flat_map(|file| rendered_parts(&file))
+            .filter_map(|file| std::fs::symlink_metadata(file).ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum();
+
+        data_size + rendered_size
+    }
+
+    /// Delete an archive's images, rendered file, and symlinks, keeping its
+    /// metadata and thumbnail so it can still be found and fetched again
+    ///
+    /// Archives that others were deduplicated into are refused, as evicting
+    /// them would leave the duplicates' data dirs pointing nowhere.
+    pub fn evict_archive(&self, archive: &Archive) -> Result<()> {
+        if let Some(dupes) = self.dedupe_links()?.get(&archive.id) {
+            return Err(eyre!(
+                "({}) can't be evicted as archives {} were deduplicated into it",
+                archive.id,
+                dupes
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .suggestion("Remove the duplicates with `kscrpr remove` first");
+        }
+
+        self.remove_content_of(archive)?;
+
+        self.render_pending.remove(archive.id.to_be_bytes())?;
+        self.evicted
+            .insert(archive.id.to_be_bytes(), sled::IVec::default())?;
+
+        Ok(())
+    }
+
+    /// The archives `dedupe` replaced with a link to another's data dir, by
+    /// the id of the archive they link to
+    pub fn dedupe_links(&self) -> Result<HashMap<u32, Vec<u32>>> {
+        let mut links = HashMap::<u32, Vec<u32>>::new();
+
+        for archive in self.fetch_all() {
+            let archive = archive?;
+
+            // strip the trailing slash so the symlink itself is read
+            let data_dir = self
+                .data_dir_of_id(archive.id)
+                .components()
+                .collect::<PathBuf>();
+            let target = match std::fs::read_link(&data_dir) {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+
+            let target_id = target
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<u32>().ok());
+            if let Some(target_id) = target_id {
+                links.entry(target_id).or_default().push(archive.id);
+            }
+        }
+
+        Ok(links)
+    }
+
+    pub(crate) fn remove_content_of(&self, archive: &Archive) -> Result<()> {
+        let data_dir = self.data_dir_of_id(archive.id);
+
+        for (link, target) in self.symlink_paths_for(archive) {
+            // the link may have been given a ` (n)` suffix by `symlink_unique`
+            for candidate in existing_link_names(&link, target == data_dir) {
+                if std::fs::read_link(&candidate).map_or(false, |t| t == target) {
+                    let _ = std::fs::remove_file(&candidate);
+                }
+            }
+        }
+
+        self.remove_data_dir_of(archive.id)?;
+        self.remove_rendered_files_of(archive.id);
+
+        Ok(())
+    }
+
+    /// Delete an archive's images, or only the link to the images of the
+    /// archive it was deduplicated into
+    pub fn remove_data_dir_of(&self, id: u32) -> Result<()> {
+        // strip the trailing slash so a symlinked (deduplicated) data dir is
+        // removed rather than the directory it points to
+        let data_dir = self.data_dir_of_id(id).components().collect::<PathBuf>();
+        if data_dir.is_symlink() {
+            std::fs::remove_file(&data_dir)?;
+        } else if data_dir.exists() {
+            std::fs::remove_dir_all(&data_dir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete every rendered file of an archive, including formats that
+    /// aren't the current `--render-format` and other profiles
+    pub fn remove_rendered_files_of(&self, id: u32) {
+        for ext in RENDERED_EXTENSIONS {
+            for file in rendered_parts(&self.rendered_file_of_id_as(id, ext)) {
+                let _ = std::fs::remove_file(file);
+            }
+        }
+        self.remove_eink_file_of(id);
+    }
+
+    pub fn remove_eink_file_of(&self, id: u32) {
+        for file in rendered_parts(&self.rendered_eink_file_of_id(id)) {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    /// Remember where a long running operation stopped so it can be resumed
+    pub fn save_paused<T: serde::Serialize>(&self, operation: &str, state: &T) -> Result<()> {
+        self.paused.insert(operation, serde_cbor::to_vec(state)?)?;
+        Ok(())
+    }
+
+    pub fn paused<T: serde::de::DeserializeOwned>(&self, operation: &str) -> Result<Option<T>> {
+        match self.paused.get(operation)? {
+            Some(v) => Ok(Some(serde_cbor::from_slice(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn clear_paused(&self, operation: &str) -> Result<()> {
+        self.paused.remove(operation)?;
+        Ok(())
+    }
+
+    fn record_download_rate(&self, bytes: u64, elapsed: std::time::Duration) -> Result<()> {
+        // tiny downloads are mostly latency, so say little about throughput
+        if bytes < 1 << 20 || elapsed.is_zero() {
+            return Ok(());
+        }
+
+        let sample = bytes as f64 / elapsed.as_secs_f64();
+        let rate = match self.download_rate()? {
+            Some(rate) => rate * 0.8 + sample * 0.2,
+            None => sample,
+        };
+
+        self.download_rate.insert("rate", &rate.to_be_bytes())?;
+
+        Ok(())
+    }
+
+    /// Recent download speed in bytes per second, if anything has been downloaded
+    pub fn download_rate(&self) -> Result<Option<f64>> {
+        Ok(self
+            .download_rate
+            .get("rate")?
+            .and_then(|v| v.as_ref().try_into().ok())
+            .map(f64::from_be_bytes))
+    }
+
+    /// Whether an archive reads right to left, by its own setting or `--rtl`
+    pub fn is_rtl(&self, id: u32) -> Result<bool> {
+        Ok(match self.rtl.get(id.to_be_bytes())? {
+            Some(v) => v[..] == [1],
+            None => self.config.rtl,
+        })
+    }
+
+    /// Set which way an archive reads, throwing away its rendered files so
+    /// they're rendered again the right way round
+    pub fn set_reading_direction(&self, id: u32, direction: ReadingDirection) -> Result<()> {
+        match direction {
+            ReadingDirection::Ltr => self.rtl.insert(id.to_be_bytes(), vec![0])?,
+            ReadingDirection::Rtl => self.rtl.insert(id.to_be_bytes(), vec![1])?,
+            ReadingDirection::Default => self.rtl.remove(id.to_be_bytes())?,
+        };
+
+        if !self.is_evicted(id) {
+            self.remove_rendered_files_of(id);
+            self.render_pending
+                .insert(id.to_be_bytes(), sled::IVec::default())?;
+        }
+
+        Ok(())
+    }
+
+    /// Record which pages of an archive couldn't be decoded, for `kscrpr verify`
+    pub fn set_corrupt_pages(&self, id: u32, pages: &[usize]) -> Result<()> {
+        if pages.is_empty() {
+            self.corrupt_pages.remove(id.to_be_bytes())?;
+        } else {
+            self.corrupt_pages
+                .insert(id.to_be_bytes(), serde_cbor::to_vec(pages)?)?;
+        }
+        Ok(())
+    }
+
+    /// Archives with pages that couldn't be decoded, along with which pages
+    pub fn all_corrupt_pages(&self) -> Result<Vec<(u32, Vec<usize>)>> {
+        self.corrupt_pages
+            .iter()
+            .map(|kv| {
+                let (k, v) = kv?;
+                Ok((
+                    u32::from_be_bytes(k.as_ref().try_into()?),
+                    serde_cbor::from_slice(&v)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Flag an archive's thumbnail and gallery/catalog entries for regeneration
+    pub fn mark_dirty(&self, id: u32) -> Result<()> {
+        self.dirty.insert(id.to_be_bytes(), sled::IVec::default())?;
+        Ok(())
+    }
+
+    pub fn blacklist_tag(&self, tag: &str) -> Result<()> {
+        self.blacklist.insert(tag, sled::IVec::default())?;
+        Ok(())
+    }
+
+    pub fn unblacklist_tag(&self, tag: &str) -> Result<()> {
+        self.blacklist.remove(tag)?;
+        Ok(())
+    }
+
+    pub fn blacklisted_tags(&self) -> Result<Vec<String>> {
+        self.blacklist
+            .iter()
+            .keys()
+            .map(|k| Ok(String::from_utf8_lossy(&k?).into_owned()))
+            .collect()
+    }
+
+    /// The first tag of the archive that is blacklisted, if any
+    pub fn blacklisted_tag_of<'a>(&self, archive: &'a Archive) -> Option<&'a str> {
+        archive
+            .tags
+            .iter()
+            .find(|t| matches!(self.blacklist.contains_key(&t.name), Ok(true)))
+            .map(|t| t.name.as_str())
+    }
+
+    pub fn is_favorite(&self, id: u32) -> bool {
+        matches!(self.favorites.contains_key(id.to_be_bytes()), Ok(true))
+    }
+
+    pub fn set_favorite(&self, id: u32, favorite: bool) -> Result<()> {
+        if favorite {
+            self.favorites
+                .insert(id.to_be_bytes(), sled::IVec::default())?;
+        } else {
+            self.favorites.remove(id.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Every archive marked as a favorite
+    pub fn favorite_archives(&self) -> Result<Vec<Archive>> {
+        self.favorites
+            .iter()
+            .keys()
+            .map(|k| self.fetch_doc(u32::from_be_bytes(k?.as_ref().try_into()?)))
+            .collect()
+    }
+
+    /// Remember a search, forgetting earlier runs of the same search and the
+    /// oldest searches past `--history-size`
+    pub fn push_search_history(&self, query: &str) -> Result<()> {
+        for entry in self.search_history.iter() {
+            let (k, v) = entry?;
+            if v.as_ref() == query.as_bytes() {
+                self.search_history.remove(k)?;
+            }
+        }
+
+        let key = self.sled_db.generate_id()?;
+        self.search_history
+            .insert(key.to_be_bytes(), query.as_bytes())?;
+
+        let excess = self
+            .search_history
+            .len()
+            .saturating_sub(self.config.history_size);
+        for k in self.search_history.iter().keys().take(excess) {
+            self.search_history.remove(k?)?;
+        }
+
+        Ok(())
+    }
+
+    /// The searches remembered, oldest first
+    pub fn recent_searches(&self) -> Result<Vec<String>> {
+        self.search_history
+            .iter()
+            .values()
+            .map(|v| -> Result<String> { Ok(String::from_utf8(v?.to_vec())?) })
+            .collect()
+    }
+
+    /// The archive highlighted when the picker was last left while picking
+    /// from `query`
+    pub fn picker_position(&self, query: &str) -> Option<u32> {
+        let v = self.picker_positions.get(query).ok()??;
+        Some(u32::from_be_bytes(v.as_ref().try_into().ok()?))
+    }
+
+    pub fn set_picker_position(&self, query: &str, id: u32) -> Result<()> {
+        self.picker_positions
+            .insert(query, id.to_be_bytes().to_vec())?;
+        Ok(())
+    }
+
+    /// Delete an archive's data, rendered file, symlinks, and metadata
+    ///
+    /// The searcher still needs to be committed afterwards.
+    pub async fn remove_archive(&self, archive: &Archive) -> Result<()> {
+        self.remove_content_of(archive)?;
+        let _ = std::fs::remove_file(self.thumb_file_of_id(archive.id));
+
+        self.sled_db.remove(archive.id.to_be_bytes())?;
+        self.render_pending.remove(archive.id.to_be_bytes())?;
+        self.last_opened.remove(archive.id.to_be_bytes())?;
+        self.evicted.remove(archive.id.to_be_bytes())?;
+        self.raw_metadata.remove(archive.id.to_be_bytes())?;
+        self.rtl.remove(archive.id.to_be_bytes())?;
+        self.corrupt_pages.remove(archive.id.to_be_bytes())?;
+        self.favorites.remove(archive.id.to_be_bytes())?;
+        self.searcher.delete_archive(archive.id).await?;
+        self.mark_dirty(archive.id)?;
+
+        Ok(())
+    }
+
+    /// Every symlink that should exist for an archive, as `(link, target)` pairs
+    pub fn symlink_paths_for(&self, archive: &Archive) -> Vec<(PathBuf, PathBuf)> {
+        let data_dir = self.data_dir_of_id(archive.id);
+
+        let mut paths = vec![];
+
+        for tag in &archive.tags {
+            paths.push((
+                self.data_dir_for_archive_by_tag(&tag.name, archive),
+                data_dir.clone(),
+            ));
+        }
+        for artist in &archive.artists {
+            paths.push((
+                self.data_dir_for_archive_by_artist(artist, archive),
+                data_dir.clone(),
+            ));
+        }
+        paths.push((self.data_dir_for_archive_by_parody(archive), data_dir));
+
+        for ext in self.config.render_format.extensions() {
+            paths.extend(self.rendered_symlink_paths_for(archive, ext));
+        }
+
+        paths
+    }
+
+    /// The symlinks to an archive's rendered file in the by-tag, by-artist,
+    /// and by-parody trees, as `(link, target)` pairs, with a link per part
+    /// of a split PDF
+    fn rendered_symlink_paths_for(&self, archive: &Archive, ext: &str) -> Vec<(PathBuf, PathBuf)> {
+        let rendered_file = self.rendered_file_of_id_as(archive.id, ext);
+
+        let mut links = vec![];
+        for tag in &archive.tags {
+            links.push(self.rendered_file_for_archive_by_tag(&tag.name, archive, ext));
+        }
+        for artist in &archive.artists {
+            links.push(self.rendered_file_for_archive_by_artist(artist, archive, ext));
+        }
+        links.push(self.rendered_file_for_archive_by_parody(archive, ext));
+
+        let parts = split_parts(&rendered_file);
+        if parts.is_empty() {
+            return links
+                .into_iter()
+                .map(|link| (link, rendered_file.clone()))
+                .collect();
+        }
+
+        links
+            .iter()
+            .flat_map(|link| {
+                parts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, part)| (part_file(link, i + 1), part.clone()))
+            })
+            .collect()
     }
 
     pub fn build_data_symlinks_for(&self, archive: &Archive) -> Result<()> {
+        self.record_path_names(archive)?;
+
         let target_dir = self.data_dir_of_id(archive.id);
         for tag in &archive.tags {
             let tag_dir = self.data_dir_for_archive_by_tag(&tag.name, archive);
@@ -178,15 +942,21 @@ impl FileSystem {
             let src_dir_v = target_dir.clone().to_string_lossy().to_string();
             let dst_dir_v = tag_dir.to_string_lossy().to_string();
 
-            symlink::symlink_dir(&target_dir, tag_dir)
+            symlink_unique(&target_dir, &tag_dir, true)
                 .note("While symlinking the tag directory")
                 .with_section(move || src_dir_v.header("Source:"))
                 .with_section(move || dst_dir_v.header("Destination:"))?;
         }
 
-        let artist_dir = self.data_dir_for_archive_by_artist(archive);
-        std::fs::create_dir_all(artist_dir.parent().unwrap())?;
-        symlink::symlink_dir(&target_dir, artist_dir)?;
+        for artist in &archive.artists {
+            let artist_dir = self.data_dir_for_archive_by_artist(artist, archive);
+            std::fs::create_dir_all(artist_dir.parent().unwrap())?;
+            symlink_unique(&target_dir, &artist_dir, true)?;
+        }
+
+        let parody_dir = self.data_dir_for_archive_by_parody(archive);
+        std::fs::create_dir_all(parody_dir.parent().unwrap())?;
+        symlink_unique(&target_dir, &parody_dir, true)?;
 
         Ok(())
     }
@@ -204,49 +974,150 @@ impl FileSystem {
             return Ok(false);
         }
 
-        msg_bar.set_prefix("Downloading zip");
-        msg_bar.set_message(format!("({})[{}]", archive.id, archive.name));
-        prog_bar.set_length(download_size.0 as u64);
-        prog_bar.set_position(0);
+        if let Some(tag) = self.blacklisted_tag_of(archive).filter(|_| !force) {
+            debug!(id = %archive.id, name = %archive.name, tag, "Not downloading archive as it has a blacklisted tag");
+            return Ok(false);
+        }
 
-        let mut zip = archive
-            .download(|_cl, ch| {
-                prog_bar.inc(ch.len() as u64);
-            })
-            .instrument(
-                info_span!("Downloading archive zip", id = archive.id, name = %archive.name),
-            )
-            .await?;
+        let staging_dir = self.staging_dir_of_id(archive.id);
+        let complete_marker = staging_dir.join(".complete");
+
+        // a size of zero means the site didn't say
+        let expected_size = Some(download_size.0 as u64).filter(|s| *s > 0);
+
+        let final_url = match std::fs::read_to_string(&complete_marker) {
+            Ok(final_url) => {
+                debug!(id = %archive.id, name = %archive.name, "Resuming from a previously extracted download");
+                Url::parse(final_url.trim())?
+            }
+            Err(_) => {
+                let _ = std::fs::remove_dir_all(&staging_dir);
+
+                msg_bar.set_prefix("Downloading zip");
+                msg_bar.set_message(format!("({})[{}]", archive.id, archive.name));
+                prog_bar.set_length(download_size.0 as u64);
+
+                let mut attempt = 1;
+                let ((mut zip, final_url), elapsed) = loop {
+                    prog_bar.set_position(0);
+                    let started = std::time::Instant::now();
+
+                    let result = archive
+                        .download(expected_size, |_cl, ch| {
+                            prog_bar.inc(ch.len() as u64);
+                        })
+                        .instrument(
+                            info_span!("Downloading archive zip", id = archive.id, name = %archive.name),
+                        )
+                        .await;
+
+                    match result {
+                        Err(e)
+                            if attempt < DOWNLOAD_ATTEMPTS
+                                && e.downcast_ref::<SizeMismatch>().is_some() =>
+                        {
+                            tracing::warn!(
+                                error = fuck_error(&e),
+                                id = archive.id,
+                                attempt,
+                                "Downloaded zip was the wrong size, retrying"
+                            );
+                            attempt += 1;
+                        }
+                        result => break (result?, started.elapsed()),
+                    }
+                };
+
+                if let Err(e) = self.record_download_rate(prog_bar.position(), elapsed) {
+                    tracing::error!(
+                        error = fuck_error(&e),
+                        "Failed to record download rate, treating this as a non-fatal error though"
+                    );
+                }
+
+                std::fs::create_dir_all(&staging_dir)?;
+
+                msg_bar.set_prefix("Extracting");
+
+                if let Err(e) = zip.extract(&staging_dir) {
+                    tracing::error!(
+                        error = fuck_error(&e.into()),
+                        id = archive.id,
+                        name = %archive.name,
+                        "Failed to extract zip, treating this as a non-fatal error though"
+                    );
+                    let _ = std::fs::remove_dir_all(&staging_dir);
+                    return Ok(false);
+                }
+
+                if self.config.compress_pages {
+                    msg_bar.set_prefix("Compressing");
+                    compress_pages(&staging_dir)?;
+                }
+
+                std::fs::write(&complete_marker, final_url.as_str())?;
+
+                final_url
+            }
+        };
+
+        if final_url != archive.download_url {
+            debug!(id = archive.id, %final_url, "Download was redirected");
+        }
+        // keep when it was first added and any custom metadata if this is a refetch
+        let previous = self.fetch_doc(archive.id).ok();
+        let added_at = previous.as_ref().and_then(|previous| previous.added_at);
+        let custom = previous.map_or_else(Default::default, |previous| previous.custom);
+        let now = unix_now();
+
+        let archive = &Archive {
+            final_download_url: Some(final_url),
+            verified: expected_size.is_some(),
+            added_at: Some(added_at.unwrap_or(now)),
+            last_fetched_at: Some(now),
+            custom,
+            ..archive.clone()
+        };
+
+        std::fs::remove_file(&complete_marker)?;
 
         let target_data_dir = self.data_dir_of_id(archive.id);
-        std::fs::create_dir_all(&target_data_dir)?;
+        if force {
+            self.remove_data_dir_of(archive.id)?;
+        }
+        move_dir(&staging_dir, &target_data_dir)
+            .note("While moving the extracted archive into place")?;
 
-        msg_bar.set_prefix("Extracting");
+        msg_bar.set_prefix("Building symlinks");
 
-        if let Err(e) = zip.extract(&target_data_dir) {
+        if let Err(e) = self.build_data_symlinks_for(archive) {
             tracing::error!(
-                error = fuck_error(&e.into()),
+                error = fuck_error(&e),
                 id = archive.id,
                 name = %archive.name,
-                "Failed to extract zip, treating this as a non-fatal error though"
+                "Failed to create symlinks?, treating this as a non-fatal error though"
             );
-            return Ok(false);
         }
 
-        msg_bar.set_prefix("Building symlinks");
+        msg_bar.set_prefix("Generating thumbnail");
 
-        if let Err(e) = self.build_data_symlinks_for(archive) {
+        if let Err(e) = artifacts::generate_thumbnail(self, archive) {
             tracing::error!(
                 error = fuck_error(&e),
                 id = archive.id,
                 name = %archive.name,
-                "Failed to create symlinks?, treating this as a non-fatal error though"
+                "Failed to generate thumbnail, treating this as a non-fatal error though"
             );
         }
 
         msg_bar.set_prefix("Rendering");
+        // the bar was counting bytes of the download up to now
+        prog_bar
+            .set_style(theme().progress_style(
+                "[{elapsed_precise}] {wide_bar:{label/value}} {pos:>}/{len} pages",
+            ));
 
-        if let Err(e) = self.render_archive(archive) {
+        if let Err(e) = self.render_or_defer(archive, msg_bar, prog_bar) {
             tracing::error!(
                 error = fuck_error(&e),
                 id = archive.id,
@@ -259,132 +1130,822 @@ impl FileSystem {
 
         self.sled_db
             .insert(archive.id.to_be_bytes(), serde_cbor::to_vec(&archive)?)?;
+        if let Some(raw) = &archive.raw_metadata {
+            self.raw_metadata
+                .insert(archive.id.to_be_bytes(), raw.as_bytes())?;
+        }
+        self.evicted.remove(archive.id.to_be_bytes())?;
         self.searcher.add_archive(archive).await?;
+        self.mark_dirty(archive.id)?;
 
         Ok(true)
     }
 
-    pub fn render_archive(&self, archive: &Archive) -> Result<()> {
-        let target_data_dir = self.data_dir_of_id(archive.id);
-        let target_file = self.rendered_file_of_id(archive.id);
-
-        if !target_file.exists() {
-            std::fs::create_dir_all(target_file.parent().unwrap())?;
+    /// Archives whose rendering was cancelled and still need rendering
+    pub fn render_pending_ids(&self) -> Result<Vec<u32>> {
+        self.render_pending
+            .iter()
+            .keys()
+            .map(|k| Ok(u32::from_be_bytes(k?.as_ref().try_into()?)))
+            .collect()
+    }
 
-            self.generate_pdf_for(&archive.name, &target_data_dir, &target_file)?;
+    /// Render an archive, or with `--no-render` just record that it still
+    /// needs rendering so it's rendered when it's first opened
+    pub fn render_or_defer(
+        &self,
+        archive: &Archive,
+        msg_bar: &ProgressBar,
+        prog_bar: &ProgressBar,
+    ) -> Result<()> {
+        if self.config.no_render {
+            self.render_pending
+                .insert(archive.id.to_be_bytes(), sled::IVec::default())?;
+            return Ok(());
         }
 
-        for tag in &archive.tags {
-            let tag_file = self.rendered_file_for_archive_by_tag(&tag.name, archive);
-            std::fs::create_dir_all(tag_file.parent().unwrap())?;
-
-            let src_file_v = target_file.clone().to_string_lossy().to_string();
-            let dst_file_v = tag_file.to_string_lossy().to_string();
+        self.render_archive(archive, msg_bar, prog_bar)
+    }
 
-            symlink::symlink_file(&target_file, tag_file)
-                .note("While symlinking the tag directory")
-                .with_section(move || src_file_v.header("Source:"))
-                .with_section(move || dst_file_v.header("Destination:"))?;
+    /// Render an archive to each `--render-format` and create its symlinks,
+    /// showing which page is being rendered in `msg_bar` and how many pages
+    /// are done in `prog_bar`
+    ///
+    /// If the user quits part way through rendering, the partial file is
+    /// removed and the archive is recorded as render-pending instead.
+    pub fn render_archive(
+        &self,
+        archive: &Archive,
+        msg_bar: &ProgressBar,
+        prog_bar: &ProgressBar,
+    ) -> Result<()> {
+        let progress = page_progress(archive, msg_bar, prog_bar);
+
+        for ext in self.config.render_format.extensions() {
+            let target_file = self.rendered_file_of_id_as(archive.id, ext);
+
+            if rendered_parts(&target_file)[0].exists() {
+                continue;
+            }
+
+            prog_bar.set_position(0);
+            let profile = RenderProfile::Standard;
+            if !self.render_file(archive, ext, profile, &target_file, &progress)? {
+                debug!(id = archive.id, name = %archive.name, "Rendering was cancelled");
+                self.render_pending
+                    .insert(archive.id.to_be_bytes(), sled::IVec::default())?;
+                return Ok(());
+            }
         }
 
-        let artist_file = self.rendered_file_for_archive_by_artist(archive);
-        std::fs::create_dir_all(artist_file.parent().unwrap())?;
-        symlink::symlink_file(&target_file, artist_file)?;
+        self.render_pending.remove(archive.id.to_be_bytes())?;
+        // the gallery and catalog link each part of a split file
+        self.mark_dirty(archive.id)?;
+
+        for ext in self.config.render_format.extensions() {
+            for (link, target_file) in self.rendered_symlink_paths_for(archive, ext) {
+                std::fs::create_dir_all(link.parent().unwrap())?;
+
+                let src_file_v = target_file.to_string_lossy().to_string();
+                let dst_file_v = link.to_string_lossy().to_string();
+
+                symlink_unique(&target_file, &link, false)
+                    .note("While symlinking the rendered file")
+                    .with_section(move || src_file_v.header("Source:"))
+                    .with_section(move || dst_file_v.header("Destination:"))?;
+            }
+        }
 
         Ok(())
     }
 
+    /// Render an archive's PDF for e-ink screens into `rendered/eink/`,
+    /// leaving its standard rendered files and symlinks alone, showing
+    /// progress like [`FileSystem::render_archive`]
+    ///
+    /// Returns `false` if the user quit part way through rendering.
+    pub fn render_eink(
+        &self,
+        archive: &Archive,
+        msg_bar: &ProgressBar,
+        prog_bar: &ProgressBar,
+    ) -> Result<bool> {
+        let target_file = self.rendered_eink_file_of_id(archive.id);
+
+        if rendered_parts(&target_file)[0].exists() {
+            return Ok(true);
+        }
+
+        prog_bar.set_position(0);
+        self.render_file(
+            archive,
+            "pdf",
+            RenderProfile::Eink,
+            &target_file,
+            page_progress(archive, msg_bar, prog_bar),
+        )
+    }
+
+    /// Render an archive to each `--render-format` in `dir`, named by
+    /// `--rendered-name-template`, leaving the library's rendered files and
+    /// symlinks alone, or to just a PDF for profiles other than the standard
+    ///
+    /// Files already in `dir` are only replaced with `overwrite`. Returns
+    /// `false` if the user quit part way through rendering.
+    pub fn export_archive(
+        &self,
+        archive: &Archive,
+        dir: &Path,
+        profile: RenderProfile,
+        overwrite: bool,
+        msg_bar: &ProgressBar,
+        prog_bar: &ProgressBar,
+    ) -> Result<bool> {
+        let progress = page_progress(archive, msg_bar, prog_bar);
+
+        let extensions = match profile {
+            RenderProfile::Standard => self.config.render_format.extensions(),
+            RenderProfile::Eink => &["pdf"],
+        };
+        for ext in extensions {
+            let target_file = dir.join(rendered_component(archive, ext));
+
+            if rendered_parts(&target_file)[0].exists() && !overwrite {
+                continue;
+            }
+
+            prog_bar.set_position(0);
+            if !self.render_file(archive, ext, profile, &target_file, &progress)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Render an archive's pages as `ext` with `profile` to `target_file`,
+    /// through a temporary file so an interrupted render isn't mistaken for a
+    /// finished one
+    ///
+    /// Returns `false` if the user quit part way through.
+    fn render_file(
+        &self,
+        archive: &Archive,
+        ext: &str,
+        profile: RenderProfile,
+        target_file: &Path,
+        progress: impl Fn(usize, usize, &Path),
+    ) -> Result<bool> {
+        let source = self.data_dir_of_id(archive.id);
+        std::fs::create_dir_all(target_file.parent().unwrap())?;
+
+        let partial_file = target_file.with_extension(format!("{ext}.part"));
+        let completed = match ext {
+            "cbz" => self.generate_cbz_for(archive, &source, &partial_file, &progress),
+            "epub" => self.generate_epub_for(archive, &source, &partial_file, &progress),
+            _ => self.generate_pdf_for(archive, &source, profile, &partial_file, &progress),
+        }
+        .map_err(|e| {
+            for file in rendered_parts(&partial_file) {
+                let _ = std::fs::remove_file(file);
+            }
+            e
+        })?;
+
+        if completed {
+            // an earlier render could have been split differently
+            for file in rendered_parts(target_file) {
+                let _ = std::fs::remove_file(file);
+            }
+
+            let parts = split_parts(&partial_file);
+            if parts.is_empty() {
+                std::fs::rename(&partial_file, target_file)?;
+            }
+            for (i, part) in parts.iter().enumerate() {
+                std::fs::rename(part, part_file(target_file, i + 1))?;
+            }
+        }
+
+        Ok(completed)
+    }
+
+    /// Zip the pages in `source_path` into a CBZ at `destination` along with a
+    /// ComicInfo.xml, calling `progress` like [`FileSystem::generate_pdf_for`]
+    ///
+    /// Returns `false` if the user quit before every page was added.
+    pub fn generate_cbz_for(
+        &self,
+        archive: &Archive,
+        source_path: &Path,
+        destination: &Path,
+        progress: impl Fn(usize, usize, &Path),
+    ) -> Result<bool> {
+        let images = page_files(source_path);
+        let total = images.len();
+
+        let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(destination)?));
+        // the images are already compressed, deflating them gains nothing
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        for (i, image_path) in images.into_iter().enumerate() {
+            if user_has_quit() {
+                drop(zip);
+                let _ = std::fs::remove_file(destination);
+                return Ok(false);
+            }
+
+            // pages are renamed so readers sorting by name keep them in order
+            zip.start_file(
+                format!("{:04}.{}", i + 1, page_extension(&image_path)),
+                options,
+            )?;
+            zip.write_all(&read_page(&image_path)?)?;
+
+            progress(i + 1, total, &image_path);
+        }
+
+        zip.start_file("ComicInfo.xml", options)?;
+        let rtl = self.is_rtl(archive.id)?;
+        zip.write_all(artifacts::comic_info(archive, rtl).as_bytes())?;
+        zip.finish()?;
+
+        Ok(true)
+    }
+
+    /// Package the pages in `source_path` into a fixed layout EPUB at
+    /// `destination`, calling `progress` like [`FileSystem::generate_pdf_for`]
+    ///
+    /// Returns `false` if the user quit before every page was added.
+    pub fn generate_epub_for(
+        &self,
+        archive: &Archive,
+        source_path: &Path,
+        destination: &Path,
+        progress: impl Fn(usize, usize, &Path),
+    ) -> Result<bool> {
+        let images = page_files(source_path);
+        let total = images.len();
+
+        let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(destination)?));
+        let stored =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let deflated = zip::write::FileOptions::default();
+
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(epub::MIMETYPE.as_bytes())?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(epub::CONTAINER.as_bytes())?;
+
+        let mut pages = vec![];
+
+        for (i, image_path) in images.into_iter().enumerate() {
+            if user_has_quit() {
+                drop(zip);
+                let _ = std::fs::remove_file(destination);
+                return Ok(false);
+            }
+
+            let bytes = read_page(&image_path)?;
+            let (width, height) = ImageReader::new(Cursor::new(&bytes))
+                .with_guessed_format()?
+                .into_dimensions()?;
+            let page = epub::Page {
+                number: i + 1,
+                ext: page_extension(&image_path),
+                width,
+                height,
+            };
+
+            zip.start_file(format!("OEBPS/{}", page.image()), stored)?;
+            zip.write_all(&bytes)?;
+            zip.start_file(format!("OEBPS/{}", page.document()), deflated)?;
+            zip.write_all(page.xhtml(&archive.name).as_bytes())?;
+
+            pages.push(page);
+
+            progress(i + 1, total, &image_path);
+        }
+
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        zip.write_all(epub::nav(archive, &pages).as_bytes())?;
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        let rtl = self.is_rtl(archive.id)?;
+        zip.write_all(epub::package(archive, &pages, unix_now(), rtl).as_bytes())?;
+        zip.finish()?;
+
+        Ok(true)
+    }
+
+    /// Render the pages in `source_path` to a PDF at `destination` carrying
+    /// the archive's metadata, with the pages prepared for `profile`, calling
+    /// `progress` with the pages done, the total pages, and the page just
+    /// rendered after each page
+    ///
+    /// Returns `false` if the user quit before every page was rendered.
     pub fn generate_pdf_for(
         &self,
-        name: &str,
+        archive: &Archive,
         source_path: &Path,
+        profile: RenderProfile,
         destination: &Path,
-    ) -> Result<()> {
-        let file_types = HashSet::<&'static OsStr>::from_iter([
-            OsStr::new("png"),
-            OsStr::new("jpg"),
-            OsStr::new("jpeg"),
-        ]);
-
-        let images = walkdir::WalkDir::new(source_path)
-            .sort_by_file_name()
-            .into_iter()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_type().is_file())
-            .filter(|entry| {
-                entry
-                    .path()
-                    .extension()
-                    .map_or(false, |ext| file_types.contains(ext))
-            })
-            .map(|entry| entry.path().to_owned());
+        progress: impl Fn(usize, usize, &Path),
+    ) -> Result<bool> {
+        // "original" is what archives without a parody are given
+        let subject = match archive.parody.as_str() {
+            "original" => String::new(),
+            parody => parody.to_owned(),
+        };
+
+        // carried along so the PDF can still be traced back to where it came
+        // from and found by other readers' searches
+        let info = pdf::Info {
+            title: archive.name.clone(),
+            author: archive.artist(),
+            subject,
+            keywords: archive.tags.iter().map(|t| t.name.clone()).collect(),
+            identifier: archive.base_url.to_string(),
+        };
+        let rtl = self.is_rtl(archive.id)?;
+
+        // with splitting the parts are numbered from the start, one that only
+        // needed one part is given the plain name at the end
+        let (split_size, split_pages) = (self.config.split_size, self.config.split_pages);
+        let split = split_size.is_some() || split_pages.is_some();
+        let mut parts = vec![if split {
+            part_file(destination, 1)
+        } else {
+            destination.to_owned()
+        }];
+
+        // pages are written out as they're added, so memory use doesn't grow
+        // with the number of pages
+        let mut writer = PdfWriter::new(BufWriter::new(File::create(&parts[0])?))?;
+
+        let mut next_part = |writer: &mut PdfWriter<BufWriter<File>>, size: u64| -> Result<()> {
+            let pages = writer.page_count();
+            let full = split_pages.map_or(false, |max| pages >= max)
+                || split_size.map_or(false, |max| writer.written() + size > max);
+            // a page too big for a part on its own still gets one
+            if pages == 0 || !full {
+                return Ok(());
+            }
+
+            let path = part_file(destination, parts.len() + 1);
+            let new_writer = PdfWriter::new(BufWriter::new(File::create(&path)?))?;
+            parts.push(path);
+            std::mem::replace(writer, new_writer)
+                .finish(&info, rtl)?
+                .flush()?;
+
+            Ok(())
+        };
+
+        let completed = self.write_pdf_pages(
+            &mut writer,
+            archive,
+            source_path,
+            profile,
+            true,
+            &mut next_part,
+            progress,
+        )?;
+        if !completed {
+            drop(writer);
+            for part in &parts {
+                let _ = std::fs::remove_file(part);
+            }
+            return Ok(false);
+        }
+        writer.finish(&info, rtl)?.flush()?;
 
-        let out_file = File::create(destination)?;
+        if split && parts.len() == 1 {
+            std::fs::rename(&parts[0], destination)?;
+        }
 
-        let doc = PdfDocument::empty(name);
+        Ok(true)
+    }
 
-        for (i, image_path) in images.enumerate() {
-            let d_image = printpdf::image_crate::open(image_path)?;
-            let image = printpdf::Image::from_dynamic_image(&d_image);
-            let (page, layer) = doc.add_page(
-                Px(d_image.width() as usize).into_pt(300.0).into(),
-                Px(d_image.height() as usize).into_pt(300.0).into(),
-                format!("Page {}", i + 1),
-            );
-            let layer_ref = doc.get_page(page).get_layer(layer);
-            image.add_to_layer(layer_ref, printpdf::ImageTransform::default());
+    /// Render each of `archives` in turn into one PDF at `destination`, with
+    /// a bookmark to where each starts
+    ///
+    /// Returns `false` if the user quit before every page was rendered.
+    pub fn generate_merged_pdf(
+        &self,
+        archives: &[Archive],
+        destination: &Path,
+        msg_bar: &ProgressBar,
+        prog_bar: &ProgressBar,
+    ) -> Result<bool> {
+        let mut writer = PdfWriter::new(BufWriter::new(File::create(destination)?))?;
+
+        for archive in archives {
+            let progress = page_progress(archive, msg_bar, prog_bar);
+            let source_path = self.data_dir_of_id(archive.id);
+
+            let completed = self.write_pdf_pages(
+                &mut writer,
+                archive,
+                &source_path,
+                RenderProfile::Standard,
+                false,
+                &mut |_, _| Ok(()),
+                progress,
+            )?;
+            if !completed {
+                drop(writer);
+                let _ = std::fs::remove_file(destination);
+                return Ok(false);
+            }
+        }
+
+        let mut author = vec![];
+        let mut keywords = vec![];
+        for archive in archives {
+            for artist in &archive.artists {
+                if !author.contains(artist) {
+                    author.push(artist.clone());
+                }
+            }
+            for tag in &archive.tags {
+                if !keywords.contains(&tag.name) {
+                    keywords.push(tag.name.clone());
+                }
+            }
         }
 
-        doc.save(&mut BufWriter::new(out_file))?;
+        // the archives are mostly parts of one series, which the file is
+        // named after
+        let info = pdf::Info {
+            title: destination
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            author: author.join(", "),
+            subject: String::new(),
+            keywords,
+            identifier: String::new(),
+        };
+        // mixing directions there's no right answer, so it's only right to
+        // left if every part is
+        let mut rtl = !archives.is_empty();
+        for archive in archives {
+            rtl &= self.is_rtl(archive.id)?;
+        }
+        writer.finish(&info, rtl)?.flush()?;
 
-        Ok(())
+        Ok(true)
     }
 
-    pub async fn with_all_tags(&self, tags: &[String]) -> Result<Vec<Archive>> {
-        let doc_ids = self
+    /// Add the pages in `source_path` to `writer`, bookmarking each page when
+    /// `bookmark_pages` is set or else just the first page after the archive,
+    /// calling `progress` like [`FileSystem::generate_pdf_for`]
+    ///
+    /// `next_part` is given the writer and the size of each image before it's
+    /// added, and can swap in a new writer to split the PDF there.
+    ///
+    /// Pages that can't be decoded are recorded against the archive once
+    /// they've all been tried. Returns `false` if the user quit part way.
+    fn write_pdf_pages<W: Write>(
+        &self,
+        writer: &mut PdfWriter<W>,
+        archive: &Archive,
+        source_path: &Path,
+        profile: RenderProfile,
+        bookmark_pages: bool,
+        next_part: &mut impl FnMut(&mut PdfWriter<W>, u64) -> Result<()>,
+        progress: impl Fn(usize, usize, &Path),
+    ) -> Result<bool> {
+        let images = page_files(source_path);
+        let total = images.len();
+
+        let sheets = if self.config.stitch_spreads {
+            pair_spreads(images)
+        } else {
+            images.into_iter().map(|image| (image, None)).collect()
+        };
+        let rtl = self.is_rtl(archive.id)?;
+
+        // subdirectories of the zip are taken to be chapters
+        let mut chapter = None;
+        let mut pages_done = 0;
+        let mut corrupt = vec![];
+        // placeholders for pages that can't be decoded take the size of the
+        // page before them
+        let mut last_size = self.config.page_size.points();
+        let mut covered = false;
+        // without page bookmarks the archive's goes on whichever page ends up
+        // first
+        let mut archive_bookmark = (!bookmark_pages).then(|| archive.pretty_single_line());
+        let mut bookmark = |page_bookmark: String| {
+            if bookmark_pages {
+                Some(page_bookmark)
+            } else {
+                archive_bookmark.take()
+            }
+        };
+
+        for (image_path, other_half) in sheets {
+            let number = pages_done + 1;
+            pages_done += 1 + other_half.is_some() as usize;
+
+            if user_has_quit() {
+                return Ok(false);
+            }
+            let decoded = open_page(&image_path).and_then(|page| match &other_half {
+                Some(other_half) => {
+                    let other_half = open_page(other_half)?;
+                    // reading right to left the first half goes on the right
+                    Ok(if rtl {
+                        stitch(&other_half, &page)
+                    } else {
+                        stitch(&page, &other_half)
+                    })
+                }
+                None => Ok(page),
+            });
+            let mut d_image = match decoded {
+                Ok(d_image) => d_image,
+                Err(e) => {
+                    tracing::error!(
+                        error = fuck_error(&e),
+                        id = archive.id,
+                        page = %image_path.display(),
+                        "Failed to decode page, leaving it out of the PDF"
+                    );
+                    corrupt.extend(number..=pages_done);
+
+                    if self.config.placeholder_pages {
+                        let (width, height) = last_size.unwrap_or((595.28, 841.89));
+                        writer.add_placeholder_page(
+                            width,
+                            height,
+                            &format!("Page {number} couldn't be decoded"),
+                            bookmark(format!("Page {}", number)),
+                        )?;
+                    }
+
+                    progress(pages_done, total, &image_path);
+                    continue;
+                }
+            };
+            // the page is sized from the original image, a downscaled image
+            // is just stretched over it
+            let (width, height) = d_image.dimensions();
+            let (page_width, page_height) = match self.config.page_size.points() {
+                None => (
+                    width as f64 * 72.0 / self.config.render_dpi,
+                    height as f64 * 72.0 / self.config.render_dpi,
+                ),
+                // landscape images, like spreads, get the paper turned sideways
+                Some((paper_width, paper_height)) if width > height => (paper_height, paper_width),
+                Some(paper) => paper,
+            };
+            if let Some(max) = self.config.max_render_dimension {
+                if width > max || height > max {
+                    d_image = d_image.resize(max, max, FilterType::Lanczos3);
+                }
+            }
+            if profile == RenderProfile::Eink {
+                d_image = eink_page(d_image, self.config.eink_resolution);
+            }
+
+            // pages lose any alpha channel here, which PDF images don't have
+            let image = match (profile, self.config.render_quality) {
+                (RenderProfile::Standard, Some(quality)) => pdf::Image::jpeg(&d_image, quality)?,
+                (RenderProfile::Standard, None) => pdf::Image::rgb(&d_image)?,
+                (RenderProfile::Eink, Some(quality)) => pdf::Image::gray_jpeg(&d_image, quality)?,
+                (RenderProfile::Eink, None) => pdf::Image::gray(&d_image)?,
+            };
+            drop(d_image);
+            next_part(writer, image.size())?;
+            let image = writer.add_image(&image)?;
+
+            // the cover is of the first page that could be put in the PDF
+            if self.config.cover_page && !covered {
+                writer.add_cover_page(
+                    image,
+                    page_width,
+                    page_height,
+                    &cover_lines(archive),
+                    bookmark("Cover".to_owned()),
+                )?;
+                covered = true;
+            }
+
+            // the outline is flat with a bookmark per page, so the first page
+            // of a chapter is named after it instead
+            let page_chapter = image_path
+                .parent()
+                .and_then(|dir| dir.strip_prefix(source_path).ok())
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map(Path::to_owned);
+            let page_bookmark = match &page_chapter {
+                Some(dir) if chapter.as_ref() != Some(dir) => {
+                    format!("{} (page {})", dir.display(), number)
+                }
+                _ => format!("Page {}", number),
+            };
+            writer.add_page(image, page_width, page_height, bookmark(page_bookmark))?;
+            chapter = page_chapter;
+            last_size = Some((page_width, page_height));
+
+            progress(pages_done, total, &image_path);
+        }
+
+        self.set_corrupt_pages(archive.id, &corrupt)?;
+
+        Ok(true)
+    }
+
+    pub async fn with_all_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let archives = self
             .searcher
-            .with_all_tags(tags)
+            .with_all_tags(tags, excluded, pages)
             .instrument(tracing::debug_span!(
                 "Searching for archives with all given tags",
+                ?tags,
+                ?excluded,
+                ?pages
+            ))
+            .await?;
+
+        Ok(archives)
+    }
+
+    pub async fn with_any_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let archives = self
+            .searcher
+            .with_any_tags(tags, excluded, pages)
+            .instrument(tracing::debug_span!(
+                "Searching for archives with any of the given tags",
+                ?tags,
+                ?excluded,
+                ?pages
+            ))
+            .await?;
+
+        Ok(archives)
+    }
+
+    pub async fn with_no_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let archives = self
+            .searcher
+            .with_no_tags(tags, excluded, pages)
+            .instrument(tracing::debug_span!(
+                "Searching for archives with none of the given tags",
+                ?tags,
+                ?excluded,
+                ?pages
+            ))
+            .await?;
+
+        Ok(archives)
+    }
+
+    pub async fn by_artist(&self, artist: &str, tags: &[String]) -> Result<Vec<Archive>> {
+        let archives = self
+            .searcher
+            .by_artist(artist, tags)
+            .instrument(tracing::debug_span!(
+                "Searching for archives by an artist",
+                artist,
                 ?tags
             ))
             .await?;
 
-        self.fetch_inner(doc_ids)
+        Ok(archives)
+    }
+
+    /// Every archive with nothing but an empty or placeholder value for `field`
+    pub fn missing_metadata(&self, field: MissingField) -> Result<Vec<Archive>> {
+        let mut out = vec![];
+
+        for archive in self.fetch_all() {
+            let archive = archive?;
+
+            let missing = match field {
+                MissingField::Parody => {
+                    archive.parody.trim().is_empty() || archive.parody == "original"
+                }
+                MissingField::Tags => archive.tags.is_empty(),
+                MissingField::Artist => archive.artists.iter().all(|a| a.trim().is_empty()),
+            };
+
+            if missing {
+                out.push(archive);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Every archive with a field matching `regex`, scanning the stored
+    /// metadata rather than the index so nothing is lost to tokenizing
+    pub fn matching_regex(&self, regex: &regex::Regex, on: IndexType) -> Result<Vec<Archive>> {
+        let mut out = vec![];
+
+        for archive in self.fetch_all() {
+            let archive = archive?;
+
+            let matched = match on {
+                IndexType::Name => regex.is_match(&archive.name),
+                IndexType::Artist => archive.artists.iter().any(|a| regex.is_match(a)),
+                IndexType::Parody => regex.is_match(&archive.parody),
+                IndexType::Tag => archive.tags.iter().any(|t| regex.is_match(&t.name)),
+                IndexType::Site => regex.is_match(archive.site()),
+            };
+
+            if matched {
+                out.push(archive);
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub async fn by_parody(&self, parody: &str) -> Result<Vec<Archive>> {
+        let archives = self
+            .searcher
+            .by_parody(parody)
+            .instrument(tracing::debug_span!(
+                "Searching for archives of a parody",
+                parody
+            ))
+            .await?;
+
+        Ok(archives)
+    }
+
+    pub async fn similar_to(&self, id: u32, limit: usize) -> Result<Vec<Archive>> {
+        let archive = self.fetch_doc(id)?;
+
+        self.searcher
+            .similar(&archive, limit)
+            .instrument(tracing::debug_span!(
+                "Searching for archives similar to another",
+                id,
+                limit
+            ))
+            .await
+    }
+
+    /// A local archive under a different id with the same title and artists,
+    /// which is usually an earlier upload of the same thing
+    pub async fn same_title_as(&self, archive: &Archive) -> Result<Option<Archive>> {
+        let artist = match archive.artists.first() {
+            Some(artist) => artist,
+            None => return Ok(None),
+        };
+
+        let by_artist = match self.by_artist(artist, &[]).await {
+            Ok(by_artist) => by_artist,
+            // the artist name has nothing searchable in it
+            Err(_) => return Ok(None),
+        };
+
+        Ok(by_artist
+            .into_iter()
+            .find(|a| a.id != archive.id && a.name == archive.name && a.artists == archive.artists))
     }
 
     pub async fn search(
         &self,
         query: &str,
         default_indexes: &[&str],
-        max: Option<usize>,
+        limit: Option<usize>,
+        offset: usize,
+        pages: PageRange,
     ) -> Result<Vec<Archive>> {
-        let doc_ids = self
+        let archives = self
             .searcher
-            .search(query, default_indexes, max)
+            .search(query, default_indexes, limit, offset, pages)
             .instrument(tracing::debug_span!(
                 "Searching for archives matching the given query",
                 ?query,
                 ?default_indexes,
-                ?max
+                ?limit,
+                offset,
+                ?pages
             ))
             .await?;
 
-        self.fetch_inner(doc_ids)
-    }
-
-    fn fetch_inner(&self, doc_ids: Vec<u32>) -> Result<Vec<Archive>> {
-        let r = doc_ids
-            .into_iter()
-            .filter_map(|id| match self.fetch_doc(id) {
-                Ok(a) => Some(a),
-                Err(e) => {
-                    tracing::error!(reason = fuck_error(&e), id, "Failed to fetch document");
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-        Ok(r)
+        Ok(archives)
     }
 
     pub fn fetch_all(&self) -> impl Iterator<Item = Result<Archive>> {
@@ -395,6 +1956,49 @@ impl FileSystem {
         })
     }
 
+    /// Up to `n` archives in order of id, starting after the archive `after`,
+    /// to read the library a page at a time
+    pub fn fetch_page(&self, after: Option<u32>, n: usize) -> Result<Vec<Archive>> {
+        let entries = match after {
+            Some(id) => self
+                .sled_db
+                .range((Bound::Excluded(id.to_be_bytes()), Bound::Unbounded)),
+            None => self.sled_db.iter(),
+        };
+
+        entries
+            .values()
+            .take(n)
+            .map(|v| -> Result<Archive> { Ok(serde_cbor::from_slice::<Archive>(&v?)?) })
+            .collect()
+    }
+
+    /// Set or remove custom metadata of an archive, reindexing it so the new
+    /// values are searchable
+    pub async fn update_custom_meta(
+        &self,
+        id: u32,
+        set: &[(String, String)],
+        unset: &[String],
+    ) -> Result<Archive> {
+        let mut archive = self.fetch_doc(id)?;
+
+        for (key, value) in set {
+            archive.custom.insert(key.clone(), value.clone());
+        }
+        for key in unset {
+            archive.custom.remove(key);
+        }
+
+        self.sled_db
+            .insert(archive.id.to_be_bytes(), serde_cbor::to_vec(&archive)?)?;
+
+        self.searcher.add_archive(&archive).await?;
+        self.searcher.commit().await?;
+
+        Ok(archive)
+    }
+
     pub fn fetch_doc(&self, id: u32) -> Result<Archive> {
         let v = self
             .sled_db
@@ -405,3 +2009,496 @@ impl FileSystem {
         Ok(a)
     }
 }
+
+/// Move a directory, falling back to copying it if `from` and `to` are on
+/// different filesystems (such as a separate cache dir). Any other reason it
+/// can't be moved, like `to` already existing, is an error
+pub fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    match std::fs::rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let dest = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    std::fs::remove_dir_all(from)?;
+
+    Ok(())
+}
+
+/// Longest name (in bytes) used for a single path component, leaving room for
+/// the id suffix, extension, and collision suffixes within common 255 byte limits
+const MAX_COMPONENT_LEN: usize = 200;
+
+/// Make a name safe to use as a single path component on any filesystem
+///
+/// Path separators and characters that are invalid on NTFS/exFAT are
+/// replaced, trailing dots and spaces are stripped, reserved Windows device
+/// names have a `_` added after the stem (`CON.txt` becomes `CON_.txt`), and
+/// the result is truncated to `MAX_COMPONENT_LEN`.
+pub fn sanitize_component(name: &str) -> String {
+    sanitize_component_within(name, MAX_COMPONENT_LEN)
+}
+
+/// Like `sanitize_component`, truncating to `max_len` bytes instead
+fn sanitize_component_within(name: &str, max_len: usize) -> String {
+    let mut out = replace_invalid_chars(name);
+    truncate_at_char(&mut out, max_len);
+
+    let trimmed_len = out.trim_end_matches(|c| c == '.' || c == ' ').len();
+    out.truncate(trimmed_len);
+
+    if out.is_empty() {
+        return "_".to_owned();
+    }
+
+    let stem = out.split('.').next().unwrap_or_default().to_ascii_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit());
+
+    if reserved {
+        let stem_len = out.find('.').unwrap_or(out.len());
+        out.insert(stem_len, '_');
+    }
+
+    out
+}
+
+/// Replace path separators and characters that are invalid on NTFS/exFAT
+fn replace_invalid_chars(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Cut `s` down to at most `max_len` bytes, without splitting a character
+fn truncate_at_char(s: &mut String, max_len: usize) {
+    if s.len() > max_len {
+        let mut end = max_len;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+    }
+}
+
+/// The path component used for a tag's directory in the by-tag trees
+fn tag_component(tag: &str) -> String {
+    sanitize_component(&normalize_tag(tag))
+}
+
+/// The path component used for an archive in the by-tag/by-artist trees,
+/// the id suffix is never truncated away
+fn archive_component(archive: &Archive, ext: &str) -> String {
+    format!("{}-{}{ext}", sanitize_component(&archive.name), archive.id)
+}
+
+/// Show which page of an archive is being rendered in `msg_bar` and how many
+/// pages are done in `prog_bar`
+fn page_progress<'a>(
+    archive: &'a Archive,
+    msg_bar: &'a ProgressBar,
+    prog_bar: &'a ProgressBar,
+) -> impl Fn(usize, usize, &Path) + 'a {
+    move |done, total, page| {
+        let page = page.file_name().unwrap_or_default().to_string_lossy();
+        msg_bar.set_message(format!(
+            "({})[{}] page {done}/{total} {page}",
+            archive.id, archive.name
+        ));
+        prog_bar.set_length(total as u64);
+        prog_bar.set_position(done as u64);
+    }
+}
+
+/// The `n`th part of a file split by `--split-size` or `--split-pages`, i.e.
+/// `name.part2.pdf` for `name.pdf`
+pub fn part_file(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{stem}.part{n}.{}", ext.to_string_lossy())),
+        None => path.with_file_name(format!("{stem}.part{n}")),
+    }
+}
+
+/// The parts a rendered file was split into, in order, or nothing if it
+/// wasn't split
+pub fn split_parts(path: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| part_file(path, n))
+        .take_while(|part| part.symlink_metadata().is_ok())
+        .collect()
+}
+
+/// The files a rendered file was written as, its parts if it was split and
+/// otherwise just the file
+fn rendered_parts(path: &Path) -> Vec<PathBuf> {
+    let parts = split_parts(path);
+
+    if parts.is_empty() {
+        vec![path.to_owned()]
+    } else {
+        parts
+    }
+}
+
+/// Every extension a rendered file can have
+pub const RENDERED_EXTENSIONS: [&str; 3] = ["pdf", "cbz", "epub"];
+
+/// The file name used for a rendered archive in the by-tag/by-artist trees,
+/// following `--rendered-name-template`
+///
+/// Each field is sanitized and truncated on its own, with the ones that can be
+/// long sharing `MAX_COMPONENT_LEN` between them, so `{id}` is never cut off
+/// however long the name is.
+fn rendered_component(archive: &Archive, ext: &str) -> String {
+    let pieces = opts::opts().rendered_name_template.pieces(archive);
+
+    let long = pieces
+        .iter()
+        .filter(|p| matches!(p, Piece::Long(_)))
+        .count();
+    let field_len = MAX_COMPONENT_LEN / long.max(1);
+
+    let mut stem = String::new();
+
+    for piece in pieces {
+        match piece {
+            Piece::Text(value) | Piece::Short(value) => {
+                stem.push_str(&replace_invalid_chars(&value))
+            }
+            Piece::Long(value) => {
+                let mut value = replace_invalid_chars(&value);
+                truncate_at_char(&mut value, field_len);
+                stem.push_str(&value);
+            }
+        }
+    }
+
+    // the fields are already short enough, this only tidies the ends
+    format!("{}.{ext}", sanitize_component_within(&stem, usize::MAX))
+}
+
+/// Extensions of the files in an archive that are pages
+///
+/// AVIF isn't one of them: the image crate can only decode it through the
+/// native dav1d library, so AVIF files are left out of rendered files,
+/// thumbnails, and dedupe rather than failing them.
+const PAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "webp", "gif"];
+
+fn is_page_file(path: &Path) -> bool {
+    PAGE_EXTENSIONS.contains(&page_extension(path).as_str())
+}
+
+/// The page images of an archive's data directory, in page order
+pub fn page_files(source_path: &Path) -> Vec<PathBuf> {
+    // not every archive zero pads its page numbers
+    walkdir::WalkDir::new(source_path)
+        .sort_by(|a, b| {
+            natural_cmp(
+                &a.file_name().to_string_lossy(),
+                &b.file_name().to_string_lossy(),
+            )
+        })
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| is_page_file(entry.path()))
+        .map(|entry| entry.path().to_owned())
+        .collect()
+}
+
+/// Decode a page image, transparently decompressing it if needed
+pub fn open_page(path: &Path) -> Result<DynamicImage> {
+    if path.extension().map_or(false, |ext| ext == "zst") {
+        let bytes = zstd::decode_all(File::open(path)?)?;
+        Ok(image::load_from_memory(&bytes)?)
+    } else {
+        Ok(image::open(path)?)
+    }
+}
+
+/// The encoded bytes of a page image, transparently decompressing it if needed
+pub fn read_page(path: &Path) -> Result<Vec<u8>> {
+    if path.extension().map_or(false, |ext| ext == "zst") {
+        Ok(zstd::decode_all(File::open(path)?)?)
+    } else {
+        Ok(std::fs::read(path)?)
+    }
+}
+
+/// The extension of a page image, ignoring any `.zst` added by `compress_pages`
+fn page_extension(path: &Path) -> String {
+    let path = match path.extension() {
+        Some(ext) if ext == "zst" => Path::new(path.file_stem().unwrap_or_default()),
+        _ => path,
+    };
+
+    path.extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// The file name of a page image without its extension or any `.zst`
+fn page_stem(path: &Path) -> String {
+    let path = match path.extension() {
+        Some(ext) if ext == "zst" => Path::new(path.file_stem().unwrap_or_default()),
+        _ => path,
+    };
+
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// Whether two pages are the halves of a double page spread, named the same
+/// but for ending in `a` and `b`
+fn is_spread(first: &Path, second: &Path) -> bool {
+    if first.parent() != second.parent() {
+        return false;
+    }
+
+    let (first, second) = (page_stem(first), page_stem(second));
+    match (first.strip_suffix('a'), second.strip_suffix('b')) {
+        (Some(first), Some(second)) => !first.is_empty() && first == second,
+        _ => false,
+    }
+}
+
+/// The text on the cover page added by `--cover-page`
+fn cover_lines(archive: &Archive) -> Vec<String> {
+    let mut lines = vec![archive.name.clone()];
+    if !archive.artists.is_empty() {
+        lines.push(format!("by {}", archive.artist()));
+    }
+    // "original" is what archives without a parody are given
+    if archive.parody != "original" {
+        lines.push(archive.parody.clone());
+    }
+    if !archive.tags.is_empty() {
+        let tags = archive
+            .tags
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>();
+        lines.push(tags.join(", "));
+    }
+    lines
+}
+
+/// Pair each page with the other half of its spread, if it's the first half
+/// of one
+fn pair_spreads(images: Vec<PathBuf>) -> Vec<(PathBuf, Option<PathBuf>)> {
+    let mut sheets = Vec::with_capacity(images.len());
+    let mut images = images.into_iter().peekable();
+
+    while let Some(image) = images.next() {
+        let other_half = images.next_if(|next| is_spread(&image, next));
+        sheets.push((image, other_half));
+    }
+
+    sheets
+}
+
+/// A page made to suit an e-ink screen of `(width, height)` pixels, in
+/// grayscale with its contrast boosted and downsampled to fit the screen
+fn eink_page(page: DynamicImage, (width, height): (u32, u32)) -> DynamicImage {
+    // spreads are read with the screen turned sideways
+    let (width, height) = if page.width() > page.height() {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let page = page.grayscale();
+    let page = if page.width() > width || page.height() > height {
+        page.resize(width, height, FilterType::Lanczos3)
+    } else {
+        page
+    };
+
+    // e-ink shows few grays, so faint lines wash out without this
+    page.adjust_contrast(20.0)
+}
+
+/// Two pages side by side on one, the shorter centred vertically on white
+fn stitch(left: &DynamicImage, right: &DynamicImage) -> DynamicImage {
+    let (left, right) = (left.to_rgb8(), right.to_rgb8());
+    let height = left.height().max(right.height());
+
+    let mut spread =
+        RgbImage::from_pixel(left.width() + right.width(), height, Rgb([255, 255, 255]));
+    imageops::overlay(&mut spread, &left, 0, (height - left.height()) / 2);
+    imageops::overlay(
+        &mut spread,
+        &right,
+        left.width(),
+        (height - right.height()) / 2,
+    );
+
+    DynamicImage::ImageRgb8(spread)
+}
+
+/// The format and dimensions of a page image, without decoding the whole image
+pub fn probe_page(path: &Path) -> Result<(ImageFormat, (u32, u32))> {
+    let bytes = read_page(path)?;
+
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader
+        .format()
+        .ok_or_else(|| eyre!("Unrecognised image format"))?;
+
+    Ok((format, reader.into_dimensions()?))
+}
+
+/// Compress every page in `dir` with zstd, replacing the originals
+///
+/// Pages that don't get any smaller are left alone.
+pub fn compress_pages(dir: &Path) -> Result<()> {
+    for page in page_files(dir) {
+        if page.extension().map_or(false, |ext| ext == "zst") {
+            continue;
+        }
+
+        let original = std::fs::read(&page)?;
+        let compressed = zstd::encode_all(original.as_slice(), 9)?;
+
+        if compressed.len() >= original.len() {
+            continue;
+        }
+
+        let mut compressed_path = page.clone().into_os_string();
+        compressed_path.push(".zst");
+        std::fs::write(&compressed_path, compressed)?;
+        std::fs::remove_file(&page)?;
+    }
+
+    Ok(())
+}
+
+/// Create a symlink at `link` pointing to `target`
+///
+/// If `link` already exists and points somewhere else (two archives whose
+/// names collide), a suffix of ` (2)`, ` (3)`, ... is added until a free name
+/// or an existing link to `target` is found. Archives are always linked in id
+/// order, so the chosen names are stable across reindexes.
+fn symlink_unique(target: &Path, link: &Path, dir: bool) -> Result<PathBuf> {
+    if link.file_name().is_none() {
+        return Err(eyre!("Symlink path has no file name"));
+    }
+
+    // a link made before others below it were removed is still reused
+    if let Some(existing) = existing_link_names(link, dir)
+        .into_iter()
+        .find(|candidate| std::fs::read_link(candidate).map_or(false, |t| t == target))
+    {
+        return Ok(existing);
+    }
+
+    let mut candidate = link.to_owned();
+
+    for next in unique_link_names(link, dir).skip(1) {
+        match std::fs::read_link(&candidate) {
+            Ok(existing) if existing == target => return Ok(candidate),
+            Ok(existing) => {
+                debug!(?candidate, ?existing, ?target, "Symlink name collision");
+            }
+            Err(_) if candidate.symlink_metadata().is_ok() => {
+                debug!(?candidate, ?target, "Symlink name taken by a non-link");
+            }
+            Err(_) => break,
+        }
+
+        candidate = next;
+    }
+
+    if dir {
+        symlink::symlink_dir(target, &candidate)?;
+    } else {
+        symlink::symlink_file(target, &candidate)?;
+    }
+
+    Ok(candidate)
+}
+
+/// Split a link's file name where `symlink_unique` puts its ` (n)` suffix,
+/// before the extension of a file
+fn link_stem_and_ext(link: &Path, dir: bool) -> (String, String) {
+    let file_name = link
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    match link.extension() {
+        Some(ext) if !dir => (
+            link.file_stem().unwrap().to_string_lossy().to_string(),
+            format!(".{}", ext.to_string_lossy()),
+        ),
+        _ => (file_name, String::new()),
+    }
+}
+
+/// The names `symlink_unique` tries for `link` in order: `link` itself, then
+/// with ` (2)`, ` (3)`, ... added before the extension of a file
+fn unique_link_names(link: &Path, dir: bool) -> impl Iterator<Item = PathBuf> + '_ {
+    let (stem, ext) = link_stem_and_ext(link, dir);
+
+    std::iter::once(link.to_owned())
+        .chain((2..).map(move |n| link.with_file_name(format!("{stem} ({n}){ext}"))))
+}
+
+/// The names of `unique_link_names` that exist, in order
+///
+/// Unlike walking `unique_link_names` until a free name, this finds every
+/// suffixed link even after links with lower suffixes were removed.
+pub fn existing_link_names(link: &Path, dir: bool) -> Vec<PathBuf> {
+    let entries = match link.parent().map(std::fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return vec![],
+    };
+
+    let (stem, ext) = link_stem_and_ext(link, dir);
+    let base = format!("{stem}{ext}");
+
+    let mut links = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == base {
+                return Some((1, entry.path()));
+            }
+
+            let n = name
+                .strip_prefix(&stem)?
+                .strip_suffix(&ext)?
+                .strip_prefix(" (")?
+                .strip_suffix(')')?
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n >= 2)?;
+            Some((n, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    links.sort_by_key(|(n, _)| *n);
+
+    links.into_iter().map(|(_, path)| path).collect()
+}
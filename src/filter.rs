@@ -0,0 +1,331 @@
+use std::str::FromStr;
+
+use crate::archive::{normalize_tag, Archive};
+use crate::filesystem::FileSystem;
+
+/// A filter expression for selecting archives in bulk commands
+///
+/// Terms compare a field against a value, i.e. `tag=foo`, `pages>50`, or
+/// `name~"big book"`, and can be combined with `&&`, `||`, `!`, and brackets.
+/// Text fields (`tag`, `artist`, `parody`, `name`) support `=`, `!=`, and `~`
+/// (case insensitive substring match), number fields (`id`, `pages`) support
+/// `=`, `!=`, `<`, `<=`, `>`, and `>=`. The flags `read` (has been opened),
+/// `evicted`, `verified`, `pending` (rendering was cancelled), and `favorite`
+/// stand alone.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Text(TextField, Op, String),
+    Number(NumberField, Op, u64),
+    Flag(Flag),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextField {
+    Tag,
+    Artist,
+    Parody,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum NumberField {
+    Id,
+    Pages,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Flag {
+    Read,
+    Evicted,
+    Verified,
+    Pending,
+    Favorite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let two_char = match (c, chars.peek()) {
+            ('&', Some('&')) => Some(Token::And),
+            ('|', Some('|')) => Some(Token::Or),
+            ('!', Some('=')) => Some(Token::Op(Op::Ne)),
+            ('<', Some('=')) => Some(Token::Op(Op::Le)),
+            ('>', Some('=')) => Some(Token::Op(Op::Ge)),
+            _ => None,
+        };
+
+        if let Some(token) = two_char {
+            chars.next();
+            tokens.push(token);
+            continue;
+        }
+
+        let token = match c {
+            '!' => Token::Not,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Op(Op::Eq),
+            '<' => Token::Op(Op::Lt),
+            '>' => Token::Op(Op::Gt),
+            '~' => Token::Op(Op::Contains),
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("Unterminated quote".to_owned()),
+                    }
+                }
+                Token::Word(word)
+            }
+            '&' | '|' => return Err(format!("Expected `{c}{c}`")),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "&|!=<>~()\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+        };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            lhs = Filter::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            lhs = Filter::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        match self.advance() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let inner = self.or()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err("Expected `)`".to_owned()),
+                }
+            }
+            Some(Token::Word(field)) => self.term(&field),
+            Some(t) => Err(format!("Unexpected {t:?}")),
+            None => Err("Unexpected end of filter".to_owned()),
+        }
+    }
+
+    fn term(&mut self, field: &str) -> Result<Filter, String> {
+        let flag = match field {
+            "read" => Some(Flag::Read),
+            "evicted" => Some(Flag::Evicted),
+            "verified" => Some(Flag::Verified),
+            "pending" => Some(Flag::Pending),
+            "favorite" => Some(Flag::Favorite),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            return Ok(Filter::Flag(flag));
+        }
+
+        let op = match self.advance() {
+            Some(Token::Op(op)) => op,
+            _ => return Err(format!("Expected a comparison after `{field}`")),
+        };
+        let value = match self.advance() {
+            Some(Token::Word(value)) => value,
+            _ => return Err(format!("Expected a value after `{field}`")),
+        };
+
+        let text_field = match field {
+            "tag" => Some(TextField::Tag),
+            "artist" => Some(TextField::Artist),
+            "parody" => Some(TextField::Parody),
+            "name" => Some(TextField::Name),
+            _ => None,
+        };
+        if let Some(text_field) = text_field {
+            if !matches!(op, Op::Eq | Op::Ne | Op::Contains) {
+                return Err(format!("`{field}` can only be compared with =, !=, or ~"));
+            }
+            return Ok(Filter::Text(text_field, op, value));
+        }
+
+        let number_field = match field {
+            "id" => NumberField::Id,
+            "pages" => NumberField::Pages,
+            _ => return Err(format!("Unknown field `{field}`")),
+        };
+        if op == Op::Contains {
+            return Err(format!("`{field}` can't be compared with ~"));
+        }
+        let value = value
+            .parse()
+            .map_err(|_| format!("`{value}` isn't a number"))?;
+
+        Ok(Filter::Number(number_field, op, value))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+
+        let filter = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(filter),
+            Some(t) => Err(format!("Unexpected {t:?}")),
+        }
+    }
+}
+
+fn compare_text(op: Op, have: &str, want: &str) -> bool {
+    match op {
+        Op::Eq => have == want,
+        Op::Ne => have != want,
+        Op::Contains => have.to_lowercase().contains(&want.to_lowercase()),
+        _ => unreachable!("rejected when parsing"),
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, archive: &Archive, fs: &FileSystem) -> bool {
+        match self {
+            Filter::And(a, b) => a.matches(archive, fs) && b.matches(archive, fs),
+            Filter::Or(a, b) => a.matches(archive, fs) || b.matches(archive, fs),
+            Filter::Not(f) => !f.matches(archive, fs),
+            Filter::Text(TextField::Tag, op, want) => {
+                let want = normalize_tag(want);
+                let has = |op| {
+                    archive
+                        .tags
+                        .iter()
+                        .any(|t| compare_text(op, &normalize_tag(&t.name), &want))
+                };
+                // an archive "isn't tagged foo" only if none of its tags are foo
+                match op {
+                    Op::Ne => !has(Op::Eq),
+                    op => has(*op),
+                }
+            }
+            Filter::Text(TextField::Artist, op, want) => {
+                let has = |op| archive.artists.iter().any(|a| compare_text(op, a, want));
+                // likewise an archive "isn't by foo" only if none of its artists are foo
+                match op {
+                    Op::Ne => !has(Op::Eq),
+                    op => has(*op),
+                }
+            }
+            Filter::Text(field, op, want) => {
+                let have = match field {
+                    TextField::Artist => unreachable!(),
+                    TextField::Parody => &archive.parody,
+                    TextField::Name => &archive.name,
+                    TextField::Tag => unreachable!(),
+                };
+                compare_text(*op, have, want)
+            }
+            Filter::Number(field, op, want) => {
+                let have = match field {
+                    NumberField::Id => archive.id as u64,
+                    NumberField::Pages => archive.num_pages as u64,
+                };
+                match op {
+                    Op::Eq => have == *want,
+                    Op::Ne => have != *want,
+                    Op::Lt => have < *want,
+                    Op::Le => have <= *want,
+                    Op::Gt => have > *want,
+                    Op::Ge => have >= *want,
+                    Op::Contains => unreachable!("rejected when parsing"),
+                }
+            }
+            Filter::Flag(Flag::Read) => {
+                matches!(
+                    fs.last_opened.contains_key(archive.id.to_be_bytes()),
+                    Ok(true)
+                )
+            }
+            Filter::Flag(Flag::Evicted) => fs.is_evicted(archive.id),
+            Filter::Flag(Flag::Verified) => archive.verified,
+            Filter::Flag(Flag::Pending) => {
+                matches!(
+                    fs.render_pending.contains_key(archive.id.to_be_bytes()),
+                    Ok(true)
+                )
+            }
+            Filter::Flag(Flag::Favorite) => fs.is_favorite(archive.id),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::path::Path;
+
+use color_eyre::{Help, Result};
+use indicatif::ProgressBar;
+use tracing::debug;
+
+use crate::archive::Archive;
+use crate::artifacts;
+use crate::filesystem::{compress_pages, move_dir, FileSystem};
+use crate::opts::opts;
+use crate::utils::{fuck_error, user_has_quit};
+
+/// Write archives to a `.tar.zst` export that `import` can restore
+///
+/// Archives without images, like evicted ones, are left out. Returns how many
+/// archives were exported.
+pub fn export(
+    fs: &FileSystem,
+    archives: &[Archive],
+    path: &Path,
+    msg_bar: &ProgressBar,
+    prog_bar: &ProgressBar,
+) -> Result<usize> {
+    let encoder = zstd::Encoder::new(File::create(path)?, 0)?;
+    // symlinks are followed, so deduplicated archives get their own copy
+    let mut builder = tar::Builder::new(encoder);
+
+    msg_bar.set_prefix("Exporting");
+    prog_bar.set_length(archives.len() as u64);
+    prog_bar.set_position(0);
+
+    let mut exported = 0;
+
+    for archive in archives {
+        prog_bar.inc(1);
+
+        if user_has_quit() {
+            break;
+        }
+
+        msg_bar.set_message(format!("({})[{}]", archive.id, archive.name));
+
+        let data_dir = fs.data_dir_of_id(archive.id);
+        if !data_dir.exists() {
+            debug!(id = %archive.id, name = %archive.name, "Not exporting archive as it has no images");
+            continue;
+        }
+
+        let meta = serde_cbor::to_vec(archive)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(meta.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(
+            &mut header,
+            format!("archives/{}.cbor", archive.id),
+            meta.as_slice(),
+        )?;
+        builder.append_dir_all(format!("by_ids/{}", archive.id), &data_dir)?;
+
+        exported += 1;
+    }
+
+    builder.into_inner()?.finish()?;
+
+    Ok(exported)
+}
+
+/// Restore archives from a `.tar.zst` export
+///
+/// The export is expected to contain `archives/<id>.cbor` holding the
+/// serialized `Archive` record and `by_ids/<id>/` holding the page images,
+/// mirroring the layout of `data/by_ids/`, as written by `export`.
+pub async fn import(
+    fs: &FileSystem,
+    path: &Path,
+    force: bool,
+    msg_bar: &ProgressBar,
+    prog_bar: &ProgressBar,
+) -> Result<Vec<Archive>> {
+    let staging_dir = fs.import_staging_dir();
+    let _ = std::fs::remove_dir_all(&staging_dir);
+    std::fs::create_dir_all(&staging_dir)?;
+
+    msg_bar.set_prefix("Unpacking");
+    msg_bar.set_message(path.display().to_string());
+
+    let decoder = zstd::Decoder::new(File::open(path)?)?;
+    tar::Archive::new(decoder)
+        .unpack(&staging_dir)
+        .note("While unpacking the export")?;
+
+    let metas = std::fs::read_dir(staging_dir.join("archives/"))
+        .note("The export has no archives/ directory")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "cbor"))
+        .collect::<Vec<_>>();
+
+    prog_bar.set_length(metas.len() as u64);
+    prog_bar.set_position(0);
+
+    let mut imported = vec![];
+
+    for meta in metas {
+        prog_bar.inc(1);
+
+        if user_has_quit() {
+            break;
+        }
+
+        let archive = serde_cbor::from_reader::<Archive, _>(File::open(&meta)?)?;
+        msg_bar.set_message(format!("({})[{}]", archive.id, archive.name));
+
+        if !force && fs.has_archive(archive.id) {
+            debug!(id = %archive.id, name = %archive.name, "Not importing archive as it already exists");
+            continue;
+        }
+
+        let source_dir = staging_dir.join("by_ids/").join(archive.id.to_string());
+        if !source_dir.exists() {
+            tracing::error!(
+                id = archive.id,
+                name = %archive.name,
+                "Export contained metadata but no images for archive, skipping"
+            );
+            continue;
+        }
+
+        msg_bar.set_prefix("Installing");
+
+        if opts().compress_pages {
+            compress_pages(&source_dir)?;
+        }
+
+        if force {
+            // the links of the archive being replaced may be under other names
+            if let Ok(existing) = fs.fetch_doc(archive.id) {
+                fs.remove_content_of(&existing)?;
+            }
+            let _ = std::fs::remove_file(fs.thumb_file_of_id(archive.id));
+        }
+
+        fs.remove_data_dir_of(archive.id)?;
+        move_dir(&source_dir, &fs.data_dir_of_id(archive.id))?;
+
+        msg_bar.set_prefix("Building symlinks");
+
+        if let Err(e) = fs.build_data_symlinks_for(&archive) {
+            tracing::error!(
+                error = fuck_error(&e),
+                id = archive.id,
+                name = %archive.name,
+                "Failed to create symlinks?, treating this as a non-fatal error though"
+            );
+        }
+
+        msg_bar.set_prefix("Generating thumbnail");
+
+        if let Err(e) = artifacts::generate_thumbnail(fs, &archive) {
+            tracing::error!(
+                error = fuck_error(&e),
+                id = archive.id,
+                name = %archive.name,
+                "Failed to generate thumbnail, treating this as a non-fatal error though"
+            );
+        }
+
+        msg_bar.set_prefix("Rendering");
+
+        // prog_bar is counting archives, not pages
+        if let Err(e) = fs.render_or_defer(&archive, msg_bar, &ProgressBar::hidden()) {
+            tracing::error!(
+                error = fuck_error(&e),
+                id = archive.id,
+                name = %archive.name,
+                "Failed to render archive and generate symlinks?, treating this as a non-fatal error though"
+            );
+        }
+
+        msg_bar.set_prefix("Indexing");
+
+        fs.sled_db
+            .insert(archive.id.to_be_bytes(), serde_cbor::to_vec(&archive)?)?;
+        fs.evicted.remove(archive.id.to_be_bytes())?;
+        fs.searcher.add_archive(&archive).await?;
+        fs.mark_dirty(archive.id)?;
+
+        imported.push(archive);
+    }
+
+    fs.searcher.commit().await?;
+
+    let _ = std::fs::remove_dir_all(&staging_dir);
+
+    Ok(imported)
+}
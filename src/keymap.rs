@@ -0,0 +1,299 @@
+use std::str::FromStr;
+
+use clap::ArgEnum;
+use color_eyre::{eyre::eyre, Help, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use once_cell::sync::OnceCell;
+
+use crate::config::config;
+
+/// Something a key can be bound to in the picker and browser
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[clap(rename_all = "snake_case")]
+pub enum Action {
+    /// Highlight the previous archive
+    Up,
+    /// Highlight the next archive
+    Down,
+    /// Move the highlight up a page
+    PageUp,
+    /// Move the highlight down a page
+    PageDown,
+    /// Move the highlight up half a page
+    HalfPageUp,
+    /// Move the highlight down half a page
+    HalfPageDown,
+    /// Highlight the first archive
+    Top,
+    /// Highlight the last archive
+    Bottom,
+    /// Pick the marked archives, or the highlighted one
+    Select,
+    /// Mark or unmark the highlighted archive
+    Mark,
+    /// Open the menu of what to do to all the marked archives, or the
+    /// highlighted one, at once
+    Batch,
+    /// Leave without picking anything
+    Quit,
+    /// Show or hide the cover preview
+    Preview,
+    /// Delete the highlighted archive, after confirming
+    Delete,
+    /// Blacklist one of the highlighted archive's tags
+    Blacklist,
+    /// Start typing into the picker's filter, until enter keeps it or escape
+    /// throws it away
+    Filter,
+    /// Start typing into the search box of `kscrpr ui`, until enter searches
+    /// the library or escape leaves the search as it was. Up and down go
+    /// through earlier searches meanwhile
+    Search,
+    /// Open the highlighted archive's rendered file, rendering it first if
+    /// needed
+    Open,
+    /// Open the highlighted archive's image directory in the file manager
+    OpenData,
+    /// Copy the highlighted archive's rendered file path to the clipboard
+    CopyPath,
+    /// Copy the highlighted archive's image directory to the clipboard
+    CopyDataPath,
+    /// Copy the highlighted archive's id to the clipboard
+    CopyId,
+    /// Copy the highlighted archive's url to the clipboard
+    CopyUrl,
+    /// Mark or unmark the highlighted archive as a favorite
+    Favorite,
+    /// Cycle the order of the picker's list between relevance, name, artist,
+    /// id, pages, and date added
+    Sort,
+}
+
+impl Action {
+    /// The keys bound to the action unless the config file says otherwise
+    pub fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Up => &["up", "k"],
+            Action::Down => &["down", "j"],
+            Action::PageUp => &["pageup"],
+            Action::PageDown => &["pagedown"],
+            Action::HalfPageUp => &["ctrl-u"],
+            Action::HalfPageDown => &["ctrl-d"],
+            Action::Top => &["home", "g g"],
+            Action::Bottom => &["end", "G"],
+            Action::Select => &["enter"],
+            Action::Mark => &["tab"],
+            Action::Batch => &["a"],
+            Action::Quit => &["esc", "q", "ctrl-c"],
+            Action::Preview => &["p", "ctrl-p"],
+            Action::Delete => &["d"],
+            Action::Blacklist => &["b", "ctrl-b"],
+            Action::Filter => &["/"],
+            Action::Search => &["ctrl-f"],
+            Action::Open => &["o"],
+            Action::OpenData => &["O"],
+            Action::CopyPath => &["y"],
+            Action::CopyDataPath => &["ctrl-y"],
+            Action::CopyId => &["#"],
+            Action::CopyUrl => &["Y"],
+            Action::Favorite => &["f"],
+            Action::Sort => &["s"],
+        }
+    }
+}
+
+/// A key along with the modifiers held with it, written like `ctrl-p`,
+/// `enter`, or `G`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    fn matches(self, event: &KeyEvent) -> bool {
+        // shift is already in the case of letters
+        let modifiers = match event.code {
+            KeyCode::Char(_) => event.modifiers & !KeyModifiers::SHIFT,
+            _ => event.modifiers,
+        };
+
+        self.code == event.code && self.modifiers == modifiers
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s.trim();
+
+        // `ctrl--` is control and minus
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "c" => KeyModifiers::CONTROL,
+                "alt" | "m" => KeyModifiers::ALT,
+                "shift" | "s" => KeyModifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier `{modifier}` in `{s}`")),
+            };
+            rest = key;
+        }
+
+        let code = match rest.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            f if f.len() > 1 && f.starts_with('f') && f[1..].parse::<u8>().is_ok() => {
+                KeyCode::F(f[1..].parse().unwrap())
+            }
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("Unknown key `{s}`")),
+                }
+            }
+        };
+
+        Ok(Key { code, modifiers })
+    }
+}
+
+/// Parse a binding of keys pressed one after another, separated by spaces
+fn parse_binding(s: &str) -> Result<Vec<Key>, String> {
+    let keys = s
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err("Empty key binding".to_owned());
+    }
+
+    Ok(keys)
+}
+
+/// Which keys do what, from the `[keymap]` section of the config file with
+/// the defaults for any action it leaves out
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Keymap {
+    /// The keymap from the config file, where each action is given a binding
+    /// or an array of them, i.e. `quit = ["q", "esc"]` or `top = "g g"`
+    fn from_config() -> Result<Self> {
+        let mut bindings = vec![];
+        let mut configured = vec![];
+
+        for (name, keys) in config().section("keymap") {
+            let action = <Action as ArgEnum>::from_str(name, true)
+                .map_err(|e| eyre!("Unknown action `{name}` in the keymap: {e}"))
+                .with_suggestion(|| {
+                    format!(
+                        "The actions are {}",
+                        Action::value_variants()
+                            .iter()
+                            .filter_map(|a| a.to_possible_value())
+                            .map(|v| v.get_name())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?;
+
+            for binding in keys {
+                let binding =
+                    parse_binding(binding).map_err(|e| eyre!("{e} in the keymap for `{name}`"))?;
+                bindings.push((binding, action));
+            }
+            configured.push(action);
+        }
+
+        for action in Action::value_variants() {
+            if configured.contains(action) {
+                continue;
+            }
+            for binding in action.default_keys() {
+                bindings.push((parse_binding(binding).unwrap(), *action));
+            }
+        }
+
+        Ok(Keymap { bindings })
+    }
+
+    /// The action bound to exactly the keys pressed, if any. Bindings the
+    /// config file gives win over the defaults
+    fn action(&self, pressed: &[KeyEvent]) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(keys, _)| {
+                keys.len() == pressed.len() && keys.iter().zip(pressed).all(|(k, p)| k.matches(p))
+            })
+            .map(|(_, action)| *action)
+    }
+
+    /// Whether some binding starts with the keys pressed
+    fn is_prefix(&self, pressed: &[KeyEvent]) -> bool {
+        self.bindings.iter().any(|(keys, _)| {
+            keys.len() > pressed.len() && keys.iter().zip(pressed).all(|(k, p)| k.matches(p))
+        })
+    }
+}
+
+/// The keymap, read from the config file the first time it's asked for
+pub fn keymap() -> Result<&'static Keymap> {
+    static KEYMAP: OnceCell<Keymap> = OnceCell::new();
+    KEYMAP.get_or_try_init(Keymap::from_config)
+}
+
+/// Turns key presses into actions, holding on to the first keys of bindings
+/// like `g g` until the rest are pressed
+pub struct Keys {
+    keymap: &'static Keymap,
+    pending: Vec<KeyEvent>,
+}
+
+impl Keys {
+    pub fn new() -> Result<Self> {
+        Ok(Keys {
+            keymap: keymap()?,
+            pending: vec![],
+        })
+    }
+
+    /// What a key press does, if anything yet
+    pub fn press(&mut self, event: KeyEvent) -> Option<Action> {
+        self.pending.push(event);
+
+        if let Some(action) = self.keymap.action(&self.pending) {
+            self.pending.clear();
+            return Some(action);
+        }
+        if self.keymap.is_prefix(&self.pending) {
+            return None;
+        }
+
+        // a key that breaks off a binding might start another one
+        let started = self.pending.len() > 1;
+        self.pending.clear();
+        if started {
+            self.press(event)
+        } else {
+            None
+        }
+    }
+}
@@ -1,37 +1,88 @@
 #![feature(hash_raw_entry)]
+#![feature(io_error_more)]
 
 use color_eyre::Result;
 
+use crate::opts::Opts;
+
 pub mod archive;
+pub mod artifacts;
+pub mod browse;
 pub mod client;
 pub mod command;
+pub mod config;
+pub mod dedupe;
+pub mod doctor;
+pub mod downloads;
+pub mod epub;
+pub mod evict;
 pub mod opts;
+pub mod pager;
+pub mod pdf;
+pub mod plan;
+pub mod preview;
 pub mod scrape;
 pub mod filesystem;
+pub mod filter;
+pub mod import;
+pub mod keymap;
 pub mod search;
+pub mod serve;
+pub mod stats;
+pub mod template;
+pub mod theme;
 pub mod utils;
 pub mod pick;
 
-fn install_tracing() -> color_eyre::Result<()> {
+fn install_tracing(opts: &Opts) -> color_eyre::Result<()> {
+    use color_eyre::eyre::WrapErr;
+    use std::sync::Mutex;
+    use tracing::level_filters::LevelFilter;
     use tracing_subscriber::fmt::format::FmtSpan;
-    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
     use tracing_subscriber::util::SubscriberInitExt;
 
+    let level = opts.log_level();
+    // the log file gets debug messages too, so the terminal's level is kept
+    // to the terminal's own layer
+    let (kscrpr_level, terminal_level) = match opts.log_file {
+        Some(_) => (level.max(LevelFilter::DEBUG), level),
+        None => (level, LevelFilter::TRACE),
+    };
+
     let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stderr());
     std::mem::forget(guard);
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_writer(non_blocking)
-        .with_span_events(FmtSpan::CLOSE);
+        .with_span_events(FmtSpan::CLOSE)
+        .with_filter(terminal_level);
     // .pretty();
-    let filter_layer =
-        tracing_subscriber::EnvFilter::from_default_env()
-            .add_directive("kscrpr=error".parse()?)
-        ;
+
+    // written to directly, so nothing is lost when exiting
+    let file_layer = match &opts.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .wrap_err_with(|| format!("Couldn't open the log file {}", path.display()))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE);
+            Some(layer)
+        }
+        None => None,
+    };
+
+    let filter_layer = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive(format!("kscrpr={kscrpr_level}").parse()?);
 
     tracing_subscriber::registry()
         .with(tracing_error::ErrorLayer::default())
         .with(filter_layer)
         .with(fmt_layer)
+        .with(file_layer)
         .init();
 
     Ok(())
@@ -39,10 +90,13 @@ fn install_tracing() -> color_eyre::Result<()> {
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    install_tracing()?;
-
     color_eyre::install()?;
 
+    // the config file gives the flags their defaults, and the flags say how
+    // much to log, so both are read before anything is logged
+    config::load()?;
+    install_tracing(opts::opts())?;
+
     command::do_stuff().await?;
 
     Ok(())
@@ -1,9 +1,17 @@
+use std::cmp::Ordering;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use clap::{ArgEnum, Parser, Subcommand};
 use once_cell::sync::OnceCell;
+use tracing::level_filters::LevelFilter;
 use url::Url;
 
+use crate::archive::Archive;
+use crate::filter::Filter;
+use crate::template::Template;
+use crate::theme::{ThemeColor, ThemeName, ThemeRole};
+
 /// Download stuff
 #[derive(Parser)]
 #[clap(about, version)]
@@ -17,6 +25,184 @@ pub struct Opts {
            default_value_os_t = dirs::document_dir().unwrap().join("kscrpr/"))]
     pub base_dir: PathBuf,
 
+    /// The directory to store archive images and rendered files in [default: BASE_DIR]
+    #[clap(env = "KSCRPR_DATA_DIR", long, parse(from_os_str), value_hint = clap::ValueHint::DirPath)]
+    pub data_dir: Option<PathBuf>,
+
+    /// The directory to store in-progress downloads in [default: BASE_DIR/data/.staging]
+    #[clap(env = "KSCRPR_CACHE_DIR", long, parse(from_os_str), value_hint = clap::ValueHint::DirPath)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// The directory to store the database and search index in [default: BASE_DIR]
+    #[clap(env = "KSCRPR_STATE_DIR", long, parse(from_os_str), value_hint = clap::ValueHint::DirPath)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Use the XDG data, cache, and state directories for anything not set explicitly
+    #[clap(env = "KSCRPR_XDG", long)]
+    pub xdg: bool,
+
+    /// The config file to read settings, followed tags, the keymap, and the
+    /// theme from [default: ~/.config/kscrpr/config.toml]
+    #[clap(env = "KSCRPR_CONFIG", long, parse(from_os_str), value_hint = clap::ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
+
+    /// Log more of what's going on, `-vv` to debug scrapes and `-vvv` for
+    /// everything. Setting `KSCRPR_VERBOSE` counts as a single `-v`
+    #[clap(
+        env = "KSCRPR_VERBOSE",
+        short,
+        long,
+        parse(from_occurrences),
+        global = true
+    )]
+    pub verbose: u8,
+
+    /// Don't log anything, not even non-fatal errors
+    #[clap(
+        env = "KSCRPR_QUIET",
+        short,
+        long,
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet: bool,
+
+    /// Also write logs to this file, down to debug messages however verbose
+    /// the terminal is
+    #[clap(env = "KSCRPR_LOG_FILE", long, parse(from_os_str), value_hint = clap::ValueHint::FilePath)]
+    pub log_file: Option<PathBuf>,
+
+    /// The maximum number of redirects to follow when downloading an archive
+    #[clap(env = "KSCRPR_MAX_REDIRECTS", long, default_value_t = 10)]
+    pub max_redirects: usize,
+
+    /// Hosts archives may be downloaded from, any host is allowed if none are given
+    #[clap(env = "KSCRPR_ALLOWED_DOWNLOAD_HOSTS", long = "allowed-download-host",
+           value_delimiter = ',', value_hint = clap::ValueHint::Hostname)]
+    pub allowed_download_hosts: Vec<String>,
+
+    /// How tag names are normalized in directory names and the search index
+    #[clap(env = "KSCRPR_TAG_NORMALIZATION", long, arg_enum, default_value_t = TagNormalization::Slug)]
+    pub tag_normalization: TagNormalization,
+
+    /// Don't render archives as they're added, leaving them to be rendered
+    /// when first opened or by `kscrpr render`
+    #[clap(env = "KSCRPR_NO_RENDER", long)]
+    pub no_render: bool,
+
+    /// Render archives to read right to left, like manga, unless they've been
+    /// set otherwise with `kscrpr direction`
+    #[clap(env = "KSCRPR_RTL", long)]
+    pub rtl: bool,
+
+    /// What archives are rendered to, CBZ suits comic readers and EPUB
+    /// e-readers better than PDF
+    #[clap(env = "KSCRPR_RENDER_FORMAT", long, arg_enum, default_value_t = RenderFormat::Pdf)]
+    pub render_format: RenderFormat,
+
+    /// Downscale pages so neither side is longer than this many pixels before
+    /// putting them in a PDF, the stored images are left as they are
+    #[clap(env = "KSCRPR_MAX_RENDER_DIMENSION", long)]
+    pub max_render_dimension: Option<u32>,
+
+    /// Re-encode pages as JPEGs of this quality, from 1 to 100, before putting
+    /// them in a PDF. Run `kscrpr reindex` to re-render existing PDFs
+    #[clap(env = "KSCRPR_RENDER_QUALITY", long, parse(try_from_str = crate::utils::parse_quality))]
+    pub render_quality: Option<u8>,
+
+    /// How many pixels of a page image make an inch of a PDF page, with
+    /// `--page-size fit`
+    #[clap(env = "KSCRPR_RENDER_DPI", long, default_value_t = 300.0, parse(try_from_str = crate::utils::parse_dpi))]
+    pub render_dpi: f64,
+
+    /// The size of PDF pages, either each image's size at `--render-dpi` or a
+    /// paper size that images are scaled to fit
+    #[clap(env = "KSCRPR_PAGE_SIZE", long, arg_enum, default_value_t = PageSize::Fit)]
+    pub page_size: PageSize,
+
+    /// Stitch the halves of double page spreads, named like `012a.jpg` and
+    /// `012b.jpg`, back into single wide pages of PDFs. Run
+    /// `kscrpr render --filter '!evicted' --force` to re-render existing PDFs
+    #[clap(env = "KSCRPR_STITCH_SPREADS", long)]
+    pub stitch_spreads: bool,
+
+    /// Put a page saying which page is missing into PDFs in place of pages
+    /// that can't be decoded, rather than just leaving them out
+    #[clap(env = "KSCRPR_PLACEHOLDER_PAGES", long)]
+    pub placeholder_pages: bool,
+
+    /// Start PDFs with a cover of the first page along with the title,
+    /// artists, parody, and tags, so file managers previewing the first page
+    /// show what each one is. Text outside Latin-1 is shown as `?`
+    #[clap(env = "KSCRPR_COVER_PAGE", long)]
+    pub cover_page: bool,
+
+    /// Split PDFs that would be bigger than this, i.e. `500M`, into
+    /// `name.part1.pdf`, `name.part2.pdf`, ... for readers that choke on huge
+    /// files
+    #[clap(env = "KSCRPR_SPLIT_SIZE", long, parse(try_from_str = crate::utils::parse_size))]
+    pub split_size: Option<u64>,
+
+    /// Split PDFs into parts of at most this many pages, like `--split-size`
+    #[clap(env = "KSCRPR_SPLIT_PAGES", long)]
+    pub split_pages: Option<usize>,
+
+    /// The screen resolution that `render --profile eink` downsamples pages
+    /// to fit, as `<width>x<height>`
+    #[clap(env = "KSCRPR_EINK_RESOLUTION", long, default_value = "1236x1648",
+           parse(try_from_str = crate::utils::parse_resolution))]
+    pub eink_resolution: (u32, u32),
+
+    /// How rendered files are named in the by-tag, by-artist, and by-parody
+    /// trees, using any of `{id}`, `{name}`, `{artist}`, `{parody}`, `{tags}`,
+    /// `{pages}`, `{added_at}`, `{site}`, and `{meta.<key>}`
+    #[clap(env = "KSCRPR_RENDERED_NAME_TEMPLATE", long, default_value = "{name}-{id}.pdf",
+           parse(try_from_str = Template::parse_name))]
+    pub rendered_name_template: Template,
+
+    /// What to do when fetching a tag finds an archive with the same title and
+    /// artist as a local archive under a different id
+    #[clap(env = "KSCRPR_DUPLICATE_TITLES", long, arg_enum, default_value_t = DuplicateTitles::Ask)]
+    pub duplicate_titles: DuplicateTitles,
+
+    /// Compress stored page images with zstd after extracting them
+    #[clap(env = "KSCRPR_COMPRESS_PAGES", long)]
+    pub compress_pages: bool,
+
+    /// How many searches made in `kscrpr ui` are remembered, to recall with
+    /// up and down in its search box or run again with `kscrpr history`
+    #[clap(env = "KSCRPR_HISTORY_SIZE", long, default_value_t = 100)]
+    pub history_size: usize,
+
+    /// Never pipe long output through a pager
+    #[clap(env = "KSCRPR_NO_PAGER", long)]
+    pub no_pager: bool,
+
+    /// Evict the content of the least recently opened archives after fetching
+    /// once the library grows past this size, i.e. `200G`
+    #[clap(env = "KSCRPR_MAX_LIBRARY_SIZE", long, parse(try_from_str = crate::utils::parse_size))]
+    pub max_library_size: Option<u64>,
+
+    /// How archive names and artists are split into searchable terms, `ngram`
+    /// lets searches match parts of words. Changing this needs a `kscrpr reindex`
+    #[clap(env = "KSCRPR_SEARCH_TOKENIZER", long, arg_enum, default_value_t = SearchTokenizer::Default)]
+    pub search_tokenizer: SearchTokenizer,
+
+    /// How the picker shows the cover of the highlighted archive, `auto`
+    /// guesses kitty or iTerm2 graphics from the environment
+    #[clap(env = "KSCRPR_PICKER_PREVIEW", long, arg_enum, default_value_t = PreviewProtocol::Auto)]
+    pub picker_preview: PreviewProtocol,
+
+    /// The color palette of the picker and progress bars
+    #[clap(env = "KSCRPR_THEME", long, arg_enum, default_value_t = ThemeName::Dark)]
+    pub theme: ThemeName,
+
+    /// Override colors of the theme with `role=color`, where color is a name
+    /// like `cyan`, an ANSI color number, `#rrggbb`, or `none`
+    #[clap(env = "KSCRPR_THEME_COLORS", long = "theme-color", value_delimiter = ',',
+           parse(try_from_str = crate::theme::parse_override))]
+    pub theme_colors: Vec<(ThemeRole, ThemeColor)>,
+
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -27,14 +213,31 @@ pub enum Command {
     Get {
         #[clap(subcommand)]
         command: GetCommand,
-        #[clap(long, arg_enum, default_value_t = OutputAsType::Path, global = true)]
+        #[clap(env = "KSCRPR_OUTPUT_AS", long, arg_enum, default_value_t = OutputAsType::Path, global = true)]
         output_as: OutputAsType,
+        /// Print archives with this template instead of `--output-as`, i.e.
+        /// `{id}\t{artist}\t{name}`. The placeholders are the `--output-as`
+        /// kinds along with `{artist}`, `{parody}`, `{tags}`, `{pages}`, and
+        /// `{added_at}`
+        #[clap(long, global = true)]
+        format: Option<Template>,
+        /// How to order results
+        #[clap(env = "KSCRPR_SORT", long, arg_enum, default_value_t = SortKey::Relevance, global = true)]
+        sort: SortKey,
+        /// Reverse the order of results
+        #[clap(long, global = true)]
+        reverse: bool,
     },
     /// Fetch archives from the site
     Fetch {
         #[clap(subcommand)]
         command: FetchCommand,
     },
+    /// Report what a command would do without doing it
+    Plan {
+        #[clap(subcommand)]
+        command: PlanCommand,
+    },
     /// Print a data dir
     Dir {
         #[clap(subcommand)]
@@ -42,18 +245,252 @@ pub enum Command {
     },
     /// Rebuild symlinks and the tantivy searcher
     Reindex,
+    /// Carry on with a fetch or reindex that was paused with Ctrl-C
+    Resume,
+    /// Set which way archives read, they're rendered again when next opened
+    /// or by `kscrpr render`
+    Direction {
+        #[clap(arg_enum)]
+        direction: ReadingDirection,
+        #[clap(required = true, value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+    },
+    /// Render archives, by default those that haven't been rendered yet
+    #[clap(args_conflicts_with_subcommands = true)]
+    Render {
+        #[clap(subcommand)]
+        command: Option<RenderCommand>,
+
+        /// Archives to render
+        #[clap(value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+        /// Only render archives matching this filter expression, i.e.
+        /// `tag=foo && pages>50`. Applies to the whole library if no ids are
+        /// given, so `--filter '!evicted'` renders everything
+        #[clap(long)]
+        filter: Option<Filter>,
+        /// Render archives again even if they're already rendered, i.e. after
+        /// changing the render settings
+        #[clap(long)]
+        force: bool,
+        /// Write the rendered files into this directory, named by
+        /// `--rendered-name-template`, instead of into the library
+        #[clap(long, value_hint = clap::ValueHint::DirPath)]
+        out: Option<PathBuf>,
+        /// What the rendered files are tuned for, other profiles are rendered
+        /// as PDFs under `rendered/<profile>/` alongside the standard files
+        #[clap(long, arg_enum, default_value_t = RenderProfile::Standard)]
+        profile: RenderProfile,
+    },
     /// Generate shell completions
     Completion { shell: clap_complete_command::Shell },
     /// Print stats about things
-    Stats,
+    Stats {
+        /// Print the stats as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// List every tag in the library with how many archives have it
+    Tags {
+        /// Leave out tags with fewer archives than this
+        #[clap(long, default_value_t = 1)]
+        min_count: u64,
+
+        #[clap(long, arg_enum, default_value_t = ListSort::Count)]
+        sort: ListSort,
+    },
+    /// List every artist in the library with their archive and page counts
+    Artists {
+        /// Leave out artists with fewer archives than this
+        #[clap(long, default_value_t = 1)]
+        min_count: u64,
+
+        #[clap(long, arg_enum, default_value_t = ListSort::Count)]
+        sort: ListSort,
+    },
+    /// Browse the library by tag or artist, opening the archives under them
+    Browse,
+    /// Pick from the whole library, searching it from inside the picker, and
+    /// open what's picked
+    Ui {
+        /// Print the picked archives like `get` does instead of opening them
+        #[clap(long, arg_enum)]
+        output_as: Option<OutputAsType>,
+    },
+    /// Serve the gallery, OPDS catalog, and rendered files over HTTP, with
+    /// the logins, allowed addresses, and TLS set under `[serve]` in the
+    /// config file
+    Serve {
+        /// The address and port to listen on, anything but a loopback address
+        /// needs `users` set
+        #[clap(long, default_value = "127.0.0.1:8080", value_hint = clap::ValueHint::Other)]
+        bind: SocketAddr,
+    },
+    /// Print the details of a local archive
+    Info {
+        /// List every page with its format, dimensions, and size
+        #[clap(long)]
+        pages: bool,
+
+        #[clap(value_hint = clap::ValueHint::Other)]
+        id: u32,
+    },
+    /// Check the symlink trees for broken, missing, and misdirected links, and
+    /// list pages that couldn't be decoded when rendering
+    Verify,
+    /// Check the health of the whole library and suggest fixes
+    Doctor {
+        /// Apply the fixes that don't download anything: rebuild the search
+        /// index, evict archives whose images are missing, render archives
+        /// that aren't, and delete broken symlinks
+        #[clap(long)]
+        fix: bool,
+    },
+    /// Find archives that were re-uploaded under a different id
+    Dedupe {
+        /// How to decide whether two archives are the same
+        #[clap(env = "KSCRPR_DEDUPE_MODE", long, arg_enum, default_value_t = DedupeMode::Exact)]
+        mode: DedupeMode,
+
+        /// What to do with the newer copies, only lists duplicates if not given
+        #[clap(long, arg_enum)]
+        action: Option<DedupeAction>,
+
+        /// Don't ask for confirmation for each group
+        #[clap(long)]
+        yes: bool,
+    },
+    /// Delete archives from the library
+    Remove {
+        /// Don't ask for confirmation
+        #[clap(long)]
+        yes: bool,
+
+        /// Only delete archives matching this filter expression, i.e.
+        /// `tag=foo && pages>50 && !read`. Applies to the whole library if no
+        /// ids are given
+        #[clap(long)]
+        filter: Option<Filter>,
+
+        #[clap(value_hint = clap::ValueHint::Other, required_unless_present = "filter")]
+        ids: Vec<u32>,
+    },
+    /// List the searches made in `kscrpr ui`, latest first, or run one of
+    /// them again
+    History {
+        /// Run the search this many back again, 1 being the latest, printing
+        /// what it finds like `get search`
+        #[clap(long)]
+        run: Option<usize>,
+
+        #[clap(long, requires = "run")]
+        pick: bool,
+
+        #[clap(long, requires = "run")]
+        open: bool,
+
+        #[clap(long, arg_enum, default_value_t = OutputAsType::Path)]
+        output_as: OutputAsType,
+    },
+    /// Mark archives as favorites, list them with `kscrpr get favorites`
+    Favorite {
+        /// Unmark them instead
+        #[clap(long)]
+        remove: bool,
+
+        #[clap(required = true, value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+    },
+    /// Manage tags whose archives are never downloaded
+    Blacklist {
+        #[clap(subcommand)]
+        command: BlacklistCommand,
+    },
+    /// Manage the config file
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage custom metadata of archives
+    Meta {
+        #[clap(subcommand)]
+        command: MetaCommand,
+    },
+    /// Write archives to a `.tar.zst` that `kscrpr import` can restore
+    Export {
+        /// Only export archives matching this filter expression, i.e.
+        /// `tag=foo && pages>50`. Applies to the whole library if no ids are
+        /// given
+        #[clap(long)]
+        filter: Option<Filter>,
+
+        #[clap(parse(from_os_str), value_hint = clap::ValueHint::FilePath)]
+        path: PathBuf,
+
+        #[clap(value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+    },
+    /// Restore archives from a `.tar.zst` export
+    Import {
+        /// Re-import archives that already exist locally
+        #[clap(long)]
+        force: bool,
+
+        #[clap(parse(from_os_str), value_hint = clap::ValueHint::FilePath)]
+        path: PathBuf,
+    },
+    /// Remove the images and rendered files of the least recently opened
+    /// archives, keeping their metadata so they can be fetched again later
+    Evict {
+        /// Evict until the library is at most this size [default: --max-library-size]
+        #[clap(long, parse(try_from_str = crate::utils::parse_size))]
+        max_size: Option<u64>,
+
+        /// Only list what would be evicted
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PlanCommand {
+    /// Report how many archives a fetch would download, their size, how long
+    /// downloading would take, and how much disk space they would use
+    Fetch {
+        #[clap(subcommand)]
+        command: FetchCommand,
+    },
 }
 
 #[derive(Subcommand)]
 pub enum FetchCommand {
     /// Fetch all archives with the given tag
     Tag {
-        #[clap(value_hint = clap::ValueHint::Other)]
-        tag: String
+        /// The tag to fetch, `-` reads newline separated tags from stdin
+        #[clap(value_hint = clap::ValueHint::Other,
+               required_unless_present_any = &["all-tags-of", "followed"])]
+        tag: Option<String>,
+
+        /// Fetch every tag of the local archive with this id instead
+        #[clap(long, conflicts_with_all = &["tag", "followed"], value_hint = clap::ValueHint::Other)]
+        all_tags_of: Option<u32>,
+
+        /// Fetch every tag under `[follow]` in the config file instead
+        #[clap(long, conflicts_with = "tag")]
+        followed: bool,
+
+        /// Stop fetching a tag after this many new archives
+        #[clap(env = "KSCRPR_MAX_PER_TAG", long)]
+        max_per_tag: Option<usize>,
+
+        /// Don't ask for confirmation before fetching every tag
+        #[clap(long)]
+        yes: bool,
+
+        /// Show a table of the downloads, where archives can be skipped or
+        /// held back, rather than progress bars
+        #[clap(long)]
+        tui: bool,
     },
     /// Fetch an archive by id
     Id {
@@ -63,6 +500,168 @@ pub enum FetchCommand {
     // TODO: artist
 }
 
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum SearchTokenizer {
+    /// Split on whitespace and punctuation, matching whole words
+    Default,
+    /// Index every two and three character slice of the text, so searches
+    /// match parts of words at the cost of a bigger index
+    Ngram,
+}
+
+impl SearchTokenizer {
+    /// The name the tokenizer is registered with in tantivy
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchTokenizer::Default => "default",
+            SearchTokenizer::Ngram => "ngram",
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum TagNormalization {
+    /// Use tag names as they are
+    None,
+    /// Lowercase tag names
+    Lowercase,
+    /// Lowercase tag names and join words with dashes, so "Full Color" becomes "full-color"
+    Slug,
+}
+
+impl TagNormalization {
+    /// The name recorded alongside the search index it was built with
+    pub fn name(&self) -> &'static str {
+        match self {
+            TagNormalization::None => "none",
+            TagNormalization::Lowercase => "lowercase",
+            TagNormalization::Slug => "slug",
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum RenderFormat {
+    /// A PDF with a page per image
+    Pdf,
+    /// A zip of the page images along with a ComicInfo.xml
+    Cbz,
+    /// A fixed layout EPUB with a page per image, for e-readers
+    Epub,
+    /// Both a PDF and a CBZ
+    Both,
+}
+
+impl RenderFormat {
+    /// Extensions of the files rendered, the first is the one opened and printed
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            RenderFormat::Pdf => &["pdf"],
+            RenderFormat::Cbz => &["cbz"],
+            RenderFormat::Epub => &["epub"],
+            RenderFormat::Both => &["pdf", "cbz"],
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum PageSize {
+    /// The size of the image at `--render-dpi`
+    Fit,
+    A4,
+    Letter,
+}
+
+impl PageSize {
+    /// The portrait width and height of the paper in points, if it has a fixed
+    /// size
+    pub fn points(self) -> Option<(f64, f64)> {
+        match self {
+            PageSize::Fit => None,
+            PageSize::A4 => Some((595.28, 841.89)),
+            PageSize::Letter => Some((612.0, 792.0)),
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum RenderProfile {
+    /// The pages as they are, in each `--render-format`
+    Standard,
+    /// Grayscale pages with their contrast boosted, downsampled to fit
+    /// `--eink-resolution`
+    Eink,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum ReadingDirection {
+    /// Left to right
+    Ltr,
+    /// Right to left, like manga
+    Rtl,
+    /// Whichever way `--rtl` says
+    Default,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum PreviewProtocol {
+    Auto,
+    /// The kitty graphics protocol, also supported by WezTerm and Konsole
+    Kitty,
+    /// iTerm2's inline images, also supported by WezTerm
+    Iterm,
+    Sixel,
+    /// Don't show covers
+    None,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum DuplicateTitles {
+    /// Ask for each one, or keep both if stdin isn't a terminal
+    Ask,
+    /// Don't download the new upload
+    Skip,
+    /// Download the new upload and remove the local archive
+    Replace,
+    /// Download the new upload and keep the local archive too
+    Keep,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum ListSort {
+    /// Most common first
+    Count,
+    /// Alphabetically
+    Name,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum DedupeMode {
+    /// Compare the hashes of every page
+    Exact,
+    /// Compare the file size and dimensions of every page
+    Heuristic,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum DedupeAction {
+    /// Replace the duplicate's data and rendered file with symlinks to the kept copy
+    Symlink,
+    /// Delete the duplicate and remember not to fetch it again
+    Tombstone,
+}
+
 #[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
 #[clap(rename_all = "snake_case")]
 pub enum OutputAsType {
@@ -74,12 +673,67 @@ pub enum OutputAsType {
     IdPath,
     /// Show the path to the rendered archive by artist/archive name
     Path,
+    /// Show the path to the cover thumbnail of the archive
+    ThumbPath,
     /// Show the id of each archive
     Id,
     /// Show the url of the archive
     Url,
+    /// Show the url the archive was downloaded from, after following redirects
+    DownloadUrl,
     /// Show the name of the archive
     Name,
+    /// Show the site the archive is from
+    Site,
+    /// Show every path used for the archive and whether it exists, for debugging
+    ExplainPaths,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum TagMode {
+    /// Archives with every tag
+    All,
+    /// Archives with at least one of the tags
+    Any,
+    /// Archives with none of the tags
+    None,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum MissingField {
+    /// No parody, or the "original" placeholder
+    Parody,
+    Tags,
+    Artist,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum SortKey {
+    Id,
+    Name,
+    Artist,
+    Pages,
+    /// When the archive was first added
+    Added,
+    /// Best matches first for searches, otherwise the order of the index
+    Relevance,
+}
+
+impl SortKey {
+    /// How two archives are ordered, relevance keeps them as they are
+    pub fn compare(self, a: &Archive, b: &Archive) -> Ordering {
+        match self {
+            SortKey::Id => a.id.cmp(&b.id),
+            SortKey::Name => a.name.cmp(&b.name).then(a.id.cmp(&b.id)),
+            SortKey::Artist => (&a.artists, &a.name, a.id).cmp(&(&b.artists, &b.name, b.id)),
+            SortKey::Pages => (a.num_pages, a.id).cmp(&(b.num_pages, b.id)),
+            SortKey::Added => (a.added_at, a.id).cmp(&(b.added_at, b.id)),
+            SortKey::Relevance => Ordering::Equal,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -94,28 +748,170 @@ pub enum GetCommand {
         #[clap(long)]
         open: bool,
 
+        /// Whether archives need all, any, or none of the tags
+        #[clap(long, arg_enum, default_value_t = TagMode::All)]
+        mode: TagMode,
+
+        /// List archives with any of the tags rather than all of them, the same as `--mode any`
+        #[clap(long, conflicts_with_all = &["all", "mode"])]
+        any: bool,
+
+        /// List archives with all of the tags, the same as `--mode all`
+        #[clap(long, conflicts_with = "mode")]
+        all: bool,
+
+        /// Leave out archives with this tag, can be given multiple times
+        #[clap(long = "not", value_name = "TAG", multiple_occurrences = true,
+               value_hint = clap::ValueHint::Other)]
+        excluded: Vec<String>,
+
+        /// Only list archives with at least this many pages
+        #[clap(long)]
+        min_pages: Option<u64>,
+
+        /// Only list archives with at most this many pages
+        #[clap(long)]
+        max_pages: Option<u64>,
+
+        /// Tags to match, `-` reads newline separated tags from stdin
         #[clap(min_values = 1, value_hint = clap::ValueHint::Other)]
         tags: Vec<String>,
     },
-    /// Get an archive by id
-    Id {
+    /// List all archives by an artist, optionally only those with the given tags
+    Artist {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
+        #[clap(long)]
+        open: bool,
+
+        #[clap(value_hint = clap::ValueHint::Other)]
+        artist: String,
+
+        /// Tags the archives must also have
+        #[clap(value_hint = clap::ValueHint::Other)]
+        tags: Vec<String>,
+    },
+    /// List all archives of a parody
+    Parody {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
         #[clap(long)]
         open: bool,
 
+        #[clap(value_hint = clap::ValueHint::Other)]
+        parody: String,
+    },
+    /// List the archives most like another, by shared tags, artist, and parody
+    Similar {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
+        #[clap(long)]
+        open: bool,
+
+        /// Maximum number of archives to show
+        #[clap(long, default_value_t = 10)]
+        limit: usize,
+
         #[clap(value_hint = clap::ValueHint::Other)]
         id: u32,
     },
+    /// List all archives with no value for a field, so their metadata can be refreshed
+    #[clap(visible_alias = "untagged")]
+    Missing {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
+        #[clap(long)]
+        open: bool,
+
+        #[clap(long, arg_enum, default_value_t = MissingField::Tags)]
+        field: MissingField,
+    },
+    /// List all archives marked as favorites
+    Favorites {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
+        #[clap(long)]
+        open: bool,
+    },
+    /// List all archives with a field matching a regex
+    Regex {
+        /// Display a ui for selecting from after filtering
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archive. Implies --pick
+        #[clap(long)]
+        open: bool,
+
+        /// The field to match against, tags match if any tag does
+        #[clap(long, arg_enum, default_value_t = IndexType::Name)]
+        on: IndexType,
+
+        #[clap(parse(try_from_str = regex::Regex::new), value_hint = clap::ValueHint::Other)]
+        pattern: regex::Regex,
+    },
+    /// Get archives by id
+    Id {
+        /// Display a ui for selecting from the archives
+        #[clap(long)]
+        pick: bool,
+
+        /// Open the rendered archives one after another, or the picked ones
+        /// with --pick
+        #[clap(long)]
+        open: bool,
+
+        #[clap(required = true, value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+    },
     /// Search for things
     Search {
         /// Default indexes to use for search terms that don't specify an index
         ///
         /// Specify an index with `index:term`, i.e. `tag:foo`
-        #[clap(long, arg_enum, default_values = &["name", "artist", "parody", "tag"])]
+        #[clap(env = "KSCRPR_SEARCH_INDEXES", long, arg_enum, value_delimiter = ',',
+               default_values = &["name", "artist", "parody", "tag"])]
         indexes: Vec<IndexType>,
 
         /// Maximum number of results to show
+        #[clap(env = "KSCRPR_SEARCH_MAX", long, visible_alias = "max")]
+        limit: Option<usize>,
+
+        /// Skip this many of the best results, to page through them with --limit
+        #[clap(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Show which terms of each result's name, artist, parody, and tags matched
+        #[clap(long, conflicts_with_all = &["pick", "open", "facets"])]
+        why: bool,
+
+        /// Instead of listing archives, count the values of these fields
+        /// among the results, i.e. `--facets tag,artist`
+        #[clap(long, arg_enum, value_delimiter = ',', conflicts_with_all = &["pick", "open"])]
+        facets: Vec<IndexType>,
+
+        /// Only list archives with at least this many pages
+        #[clap(long)]
+        min_pages: Option<u64>,
+
+        /// Only list archives with at most this many pages
         #[clap(long)]
-        max: Option<usize>,
+        max_pages: Option<u64>,
 
         /// Display a ui for selecting from after filtering
         #[clap(long)]
@@ -137,6 +933,7 @@ pub enum IndexType {
     Artist,
     Parody,
     Tag,
+    Site,
 }
 
 impl IndexType {
@@ -146,22 +943,196 @@ impl IndexType {
             IndexType::Artist => "artist",
             IndexType::Parody => "parody",
             IndexType::Tag => "tag",
+            IndexType::Site => "site",
         }
     }
 }
 
+#[derive(Subcommand)]
+pub enum RenderCommand {
+    /// Render a PDF grid of the covers of every archive matching a filter,
+    /// captioned with their names and ids, as a visual index of i.e. a tag
+    ContactSheet {
+        /// The filter expression picking the archives, i.e. `tag=foo`
+        #[clap(long)]
+        filter: Filter,
+        /// The title of the contact sheet
+        #[clap(long, value_hint = clap::ValueHint::Other)]
+        title: Option<String>,
+        /// How many covers go across each page
+        #[clap(long, default_value_t = 4)]
+        columns: usize,
+        /// Where to write the PDF, by default `<title>-contact-sheet.pdf`, or
+        /// `contact-sheet.pdf` without a title, in the current directory
+        #[clap(long, value_hint = clap::ValueHint::FilePath)]
+        out: Option<PathBuf>,
+    },
+
+    /// Render several archives one after another into a single PDF with a
+    /// bookmark to the start of each, such as the parts of a series
+    Merge {
+        /// The archives to merge, in the order they're given
+        #[clap(value_hint = clap::ValueHint::Other)]
+        ids: Vec<u32>,
+
+        /// Merge every archive matching this filter expression ordered by
+        /// name, i.e. `artist=foo && tag=bar`, or only those of the given ids
+        #[clap(long)]
+        filter: Option<Filter>,
+
+        /// Where to write the PDF
+        #[clap(long, value_hint = clap::ValueHint::FilePath)]
+        out: PathBuf,
+    },
+
+    /// Join an archive's pages top to bottom into long strips, for vertical
+    /// scrolling comics that were split into screenshots
+    Strip {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        id: u32,
+
+        /// Where to write the PDF of strips, or the directory for the strip
+        /// images with `--images`
+        #[clap(long, value_hint = clap::ValueHint::AnyPath)]
+        out: PathBuf,
+
+        /// Write each strip as a numbered image instead of a page of a PDF
+        #[clap(long)]
+        images: bool,
+
+        /// The tallest a strip can get in pixels before a new one is started,
+        /// pages are never split between strips
+        #[clap(long, default_value_t = 20000)]
+        max_height: u32,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MetaCommand {
+    /// Set custom metadata on an archive, searchable as `meta.key:value`
+    Set {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        id: u32,
+
+        /// `key=value` pairs to set
+        #[clap(min_values = 1, parse(try_from_str = parse_meta_pair),
+               value_hint = clap::ValueHint::Other)]
+        pairs: Vec<(String, String)>,
+    },
+    /// Remove custom metadata from an archive
+    Unset {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        id: u32,
+
+        #[clap(min_values = 1, value_hint = clap::ValueHint::Other)]
+        keys: Vec<String>,
+    },
+    /// List the custom metadata of an archive
+    List {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        id: u32,
+    },
+}
+
+fn parse_meta_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("`{s}` isn't of the form key=value")),
+    }
+}
+
+#[derive(Subcommand)]
+pub enum BlacklistCommand {
+    /// Stop downloading archives with this tag
+    Add {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        tag: String,
+    },
+    /// Allow downloading archives with this tag again
+    Remove {
+        #[clap(value_hint = clap::ValueHint::Other)]
+        tag: String,
+    },
+    /// List blacklisted tags
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Write a config file with every setting commented out at its default
+    Init {
+        /// Write over the config file if there's one already
+        #[clap(long)]
+        force: bool,
+    },
+    /// Read a password from stdin and print the `user:hash` entry to add to
+    /// `users` under `[serve]`
+    HashPassword {
+        #[clap(value_hint = clap::ValueHint::Username)]
+        user: String,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum DirCommand {
     /// Output the tag-organised rendered directory
     Tag,
     /// Output the artist organised rendered directory
     Artist,
+    /// Output the parody organised rendered directory
+    Parody,
     /// Output the data directory root
     Data,
     /// Output the metadata directory root
     Meta,
 }
 
+impl Opts {
+    fn xdg_dir(&self, dir: Option<PathBuf>) -> Option<PathBuf> {
+        if self.xdg {
+            dir.map(|d| d.join("kscrpr/"))
+        } else {
+            None
+        }
+    }
+
+    /// Root of the `data/` and `rendered/` trees
+    pub fn data_root(&self) -> PathBuf {
+        self.data_dir
+            .clone()
+            .or_else(|| self.xdg_dir(dirs::data_dir()))
+            .unwrap_or_else(|| self.base_dir.clone())
+    }
+
+    /// Root of the `meta/` tree
+    pub fn state_root(&self) -> PathBuf {
+        self.state_dir
+            .clone()
+            .or_else(|| self.xdg_dir(dirs::state_dir().or_else(dirs::data_local_dir)))
+            .unwrap_or_else(|| self.base_dir.clone())
+    }
+
+    /// How much is logged to the terminal, from `-q` and `-v`
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::OFF,
+            (false, 0) => LevelFilter::ERROR,
+            (false, 1) => LevelFilter::INFO,
+            (false, 2) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        }
+    }
+
+    /// Root for in-progress downloads and imports, if kept separately from the data
+    pub fn cache_root(&self) -> Option<PathBuf> {
+        self.cache_dir
+            .clone()
+            .or_else(|| self.xdg_dir(dirs::cache_dir()))
+    }
+}
+
 pub fn opts() -> &'static Opts {
     static INSTANCE: OnceCell<Opts> = OnceCell::new();
     INSTANCE.get_or_init(Opts::parse)
@@ -0,0 +1,62 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use color_eyre::Result;
+use crossterm::tty::IsTty;
+
+use crate::opts::opts;
+
+/// Print lines to stdout, going through `$PAGER` (or `less`) if stdout is a
+/// terminal and the lines wouldn't fit on screen
+pub fn output_lines(lines: &[String]) -> Result<()> {
+    let stdout = std::io::stdout();
+
+    let fits = match crossterm::terminal::size() {
+        Ok((_, rows)) => lines.iter().map(|l| l.lines().count()).sum::<usize>() < rows as usize,
+        Err(_) => true,
+    };
+
+    if opts().no_pager || fits || !stdout.is_tty() {
+        return print_lines(lines);
+    }
+
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_owned());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+
+    let mut child = match Command::new(program)
+        .args(parts)
+        .env("LESS", std::env::var("LESS").unwrap_or_else(|_| "FRX".to_owned()))
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::debug!(error = %e, %pager, "Failed to spawn pager, printing directly");
+            return print_lines(lines);
+        }
+    };
+
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for line in lines {
+            // the pager was quit early
+            if writeln!(stdin, "{line}").is_err() {
+                break;
+            }
+        }
+    }
+
+    child.wait()?;
+
+    Ok(())
+}
+
+fn print_lines(lines: &[String]) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for line in lines {
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
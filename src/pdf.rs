@@ -0,0 +1,590 @@
+use std::fmt::Write as _;
+use std::io::Write;
+
+use color_eyre::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, ImageOutputFormat};
+
+use crate::artifacts::escape_xml;
+
+/// A page image ready to be put in a PDF
+pub struct Image {
+    width: u32,
+    height: u32,
+    /// Whether the pixels are grayscale rather than RGB
+    gray: bool,
+    data: ImageData,
+}
+
+enum ImageData {
+    Jpeg(Vec<u8>),
+    /// 8 bit pixels, deflated
+    Deflated(Vec<u8>),
+}
+
+impl Image {
+    /// A page's pixels as they are, deflated
+    pub fn rgb(image: &DynamicImage) -> Result<Self> {
+        let rgb = image.to_rgb8();
+
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(rgb.as_raw())?;
+
+        Ok(Self {
+            width: rgb.width(),
+            height: rgb.height(),
+            gray: false,
+            data: ImageData::Deflated(encoder.finish()?),
+        })
+    }
+
+    /// A page converted to grayscale, deflated
+    pub fn gray(image: &DynamicImage) -> Result<Self> {
+        let luma = image.to_luma8();
+
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(luma.as_raw())?;
+
+        Ok(Self {
+            width: luma.width(),
+            height: luma.height(),
+            gray: true,
+            data: ImageData::Deflated(encoder.finish()?),
+        })
+    }
+
+    /// A page re-encoded as a JPEG of the given quality, which is embedded in
+    /// the PDF as it is rather than as raw pixels
+    pub fn jpeg(image: &DynamicImage, quality: u8) -> Result<Self> {
+        let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+
+        let mut jpeg = vec![];
+        rgb.write_to(&mut jpeg, ImageOutputFormat::Jpeg(quality))?;
+
+        Ok(Self {
+            width: rgb.width(),
+            height: rgb.height(),
+            gray: false,
+            data: ImageData::Jpeg(jpeg),
+        })
+    }
+
+    /// A page converted to grayscale and re-encoded as a JPEG of the given
+    /// quality
+    pub fn gray_jpeg(image: &DynamicImage, quality: u8) -> Result<Self> {
+        let luma = DynamicImage::ImageLuma8(image.to_luma8());
+
+        let mut jpeg = vec![];
+        luma.write_to(&mut jpeg, ImageOutputFormat::Jpeg(quality))?;
+
+        Ok(Self {
+            width: luma.width(),
+            height: luma.height(),
+            gray: true,
+            data: ImageData::Jpeg(jpeg),
+        })
+    }
+
+    /// How many bytes the image takes up in the PDF
+    pub fn size(&self) -> u64 {
+        match &self.data {
+            ImageData::Jpeg(data) | ImageData::Deflated(data) => data.len() as u64,
+        }
+    }
+}
+
+/// An image written to the PDF, which any number of pages can show
+#[derive(Clone, Copy)]
+pub struct ImageRef {
+    id: u32,
+    width: u32,
+    height: u32,
+}
+
+impl ImageRef {
+    /// Content stream operators drawing the image scaled to fit and centred
+    /// on a page of the given size
+    fn draw(self, width: f64, height: f64) -> String {
+        self.draw_in(0, (0.0, 0.0), (width, height))
+    }
+
+    /// Content stream operators drawing the image, as `/Im<index>` of the
+    /// page's images, scaled to fit and centred in the box with its bottom
+    /// left corner at `(x, y)`
+    pub fn draw_in(self, index: usize, (x, y): (f64, f64), (width, height): (f64, f64)) -> String {
+        let scale = (width / self.width as f64).min(height / self.height as f64);
+        let (drawn_width, drawn_height) = (self.width as f64 * scale, self.height as f64 * scale);
+        let (x, y) = (
+            x + (width - drawn_width) / 2.0,
+            y + (height - drawn_height) / 2.0,
+        );
+        format!("q {drawn_width:.3} 0 0 {drawn_height:.3} {x:.3} {y:.3} cm /Im{index} Do Q")
+    }
+
+    fn resources(self) -> String {
+        format!("/XObject << /Im0 {} 0 R >>", self.id)
+    }
+}
+
+/// Helvetica is one of the fonts every reader has, so it isn't embedded, but
+/// it can only show Latin-1
+const FONT: &str =
+    "/Font << /F1 << /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >> >>";
+
+/// Helvetica glyphs are about half as wide as they're tall, which is close
+/// enough to lay text out by
+const GLYPH_WIDTH: f64 = 0.5;
+
+/// What PDF readers show as the document's properties
+pub struct Info {
+    pub title: String,
+    pub author: String,
+    pub subject: String,
+    pub keywords: Vec<String>,
+    /// Where the document came from, i.e. the archive's url
+    pub identifier: String,
+}
+
+/// Counts what's been written, as the PDF's cross reference table needs the
+/// offset of every object
+struct Counted<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes a PDF of one image per page, writing each page out as soon as it's
+/// added so only one page is ever held in memory
+pub struct PdfWriter<W: Write> {
+    out: Counted<W>,
+    /// Offsets of each object, by id - 1, objects are given ids before being
+    /// written so they can be referred to
+    offsets: Vec<Option<u64>>,
+    pages_id: u32,
+    pages: Vec<u32>,
+    bookmarks: Vec<(String, u32)>,
+}
+
+impl<W: Write> PdfWriter<W> {
+    pub fn new(out: W) -> Result<Self> {
+        let mut out = Counted {
+            inner: out,
+            written: 0,
+        };
+        // the binary comment tells transfer tools the file isn't text
+        out.write_all(b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n")?;
+
+        let mut writer = Self {
+            out,
+            offsets: vec![],
+            pages_id: 0,
+            pages: vec![],
+            bookmarks: vec![],
+        };
+        writer.pages_id = writer.reserve();
+
+        Ok(writer)
+    }
+
+    /// How many bytes of the PDF have been written so far
+    pub fn written(&self) -> u64 {
+        self.out.written
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn reserve(&mut self) -> u32 {
+        self.offsets.push(None);
+        self.offsets.len() as u32
+    }
+
+    fn object(&mut self, id: u32, body: &str) -> Result<()> {
+        self.offsets[id as usize - 1] = Some(self.out.written);
+        write!(self.out, "{id} 0 obj\n{body}\nendobj\n")?;
+        Ok(())
+    }
+
+    fn stream(&mut self, id: u32, dict: &str, data: &[u8]) -> Result<()> {
+        self.offsets[id as usize - 1] = Some(self.out.written);
+        write!(
+            self.out,
+            "{id} 0 obj\n<< {dict} /Length {} >>\nstream\n",
+            data.len()
+        )?;
+        self.out.write_all(data)?;
+        self.out.write_all(b"\nendstream\nendobj\n")?;
+        Ok(())
+    }
+
+    /// Write out an image for pages to show
+    pub fn add_image(&mut self, image: &Image) -> Result<ImageRef> {
+        let image_id = self.reserve();
+
+        let (filter, data) = match &image.data {
+            ImageData::Jpeg(data) => ("DCTDecode", data),
+            ImageData::Deflated(data) => ("FlateDecode", data),
+        };
+        self.stream(
+            image_id,
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} \
+                 /BitsPerComponent 8 /Interpolate true /Filter /{filter}",
+                image.width,
+                image.height,
+                if image.gray {
+                    "DeviceGray"
+                } else {
+                    "DeviceRGB"
+                }
+            ),
+            data,
+        )?;
+
+        Ok(ImageRef {
+            id: image_id,
+            width: image.width,
+            height: image.height,
+        })
+    }
+
+    /// Add a page of the given size in points with the image scaled to fit
+    /// and centred on it, and `bookmark` in the outline
+    pub fn add_page(
+        &mut self,
+        image: ImageRef,
+        width: f64,
+        height: f64,
+        bookmark: Option<String>,
+    ) -> Result<()> {
+        let contents_id = self.reserve();
+        let page_id = self.reserve();
+
+        let contents = image.draw(width, height);
+        self.stream(contents_id, "", contents.as_bytes())?;
+
+        self.page(
+            page_id,
+            (width, height),
+            &image.resources(),
+            contents_id,
+            bookmark,
+        )
+    }
+
+    /// Add a cover page of the given size in points, showing the image like
+    /// [`PdfWriter::add_page`] with `lines` of text on a band across the
+    /// bottom, and `bookmark` in the outline
+    pub fn add_cover_page(
+        &mut self,
+        image: ImageRef,
+        width: f64,
+        height: f64,
+        lines: &[String],
+        bookmark: Option<String>,
+    ) -> Result<()> {
+        let contents_id = self.reserve();
+        let page_id = self.reserve();
+
+        let size = width / 32.0;
+        let leading = size * 1.3;
+        let margin = size;
+        let max_chars = chars_in(width - 2.0 * margin, size);
+        let lines = lines
+            .iter()
+            .flat_map(|line| wrap(line, max_chars))
+            .collect::<Vec<_>>();
+        let band = lines.len() as f64 * leading + margin;
+
+        let mut contents = image.draw(width, height);
+        let _ = write!(
+            contents,
+            " q 1 g 0 0 {width:.3} {band:.3} re f Q BT 0 g /F1 {size:.3} Tf {leading:.3} TL {margin:.3} {:.3} Td",
+            band - margin / 2.0 - size
+        );
+        for line in &lines {
+            let _ = write!(contents, " {} Tj T*", literal(line));
+        }
+        contents.push_str(" ET");
+        self.stream(contents_id, "", contents.as_bytes())?;
+
+        self.page(
+            page_id,
+            (width, height),
+            &format!("{} {FONT}", image.resources()),
+            contents_id,
+            bookmark,
+        )
+    }
+
+    /// Add a blank page of the given size in points with a line of text in
+    /// the middle, and `bookmark` in the outline
+    pub fn add_placeholder_page(
+        &mut self,
+        width: f64,
+        height: f64,
+        message: &str,
+        bookmark: Option<String>,
+    ) -> Result<()> {
+        let contents_id = self.reserve();
+        let page_id = self.reserve();
+
+        let size = 18.0;
+        let x = (width - message.chars().count() as f64 * size * GLYPH_WIDTH).max(0.0) / 2.0;
+        let contents = format!(
+            "BT /F1 {size} Tf {x:.3} {:.3} Td {} Tj ET",
+            height / 2.0,
+            literal(message)
+        );
+        self.stream(contents_id, "", contents.as_bytes())?;
+
+        self.page(page_id, (width, height), FONT, contents_id, bookmark)
+    }
+
+    /// Add a page of the given size in points drawn by `contents`, which can
+    /// draw the images with [`ImageRef::draw_in`] and text with [`text`], and
+    /// `bookmark` in the outline
+    pub fn add_drawn_page(
+        &mut self,
+        width: f64,
+        height: f64,
+        images: &[ImageRef],
+        contents: &str,
+        bookmark: Option<String>,
+    ) -> Result<()> {
+        let contents_id = self.reserve();
+        let page_id = self.reserve();
+
+        self.stream(contents_id, "", contents.as_bytes())?;
+
+        let images = images
+            .iter()
+            .enumerate()
+            .map(|(i, image)| format!("/Im{i} {} 0 R", image.id))
+            .collect::<Vec<_>>();
+        self.page(
+            page_id,
+            (width, height),
+            &format!("/XObject << {} >> {FONT}", images.join(" ")),
+            contents_id,
+            bookmark,
+        )
+    }
+
+    fn page(
+        &mut self,
+        page_id: u32,
+        (width, height): (f64, f64),
+        resources: &str,
+        contents_id: u32,
+        bookmark: Option<String>,
+    ) -> Result<()> {
+        self.object(
+            page_id,
+            &format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {width:.3} {height:.3}] \
+                 /Resources << {resources} >> /Contents {contents_id} 0 R >>",
+                self.pages_id
+            ),
+        )?;
+
+        self.pages.push(page_id);
+        if let Some(bookmark) = bookmark {
+            self.bookmarks.push((bookmark, page_id));
+        }
+
+        Ok(())
+    }
+
+    /// Write out everything that refers to the pages, returning the writer
+    /// to be flushed
+    pub fn finish(mut self, info: &Info, rtl: bool) -> Result<W> {
+        let kids = self
+            .pages
+            .iter()
+            .map(|id| format!("{id} 0 R"))
+            .collect::<Vec<_>>();
+        self.object(
+            self.pages_id,
+            &format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                kids.join(" "),
+                kids.len()
+            ),
+        )?;
+
+        let outline_id = self.reserve();
+        let bookmark_ids = (0..self.bookmarks.len())
+            .map(|_| self.reserve())
+            .collect::<Vec<_>>();
+        for (i, (title, page_id)) in std::mem::take(&mut self.bookmarks).iter().enumerate() {
+            let mut item = format!(
+                "<< /Title {} /Parent {outline_id} 0 R /Dest [{page_id} 0 R /Fit]",
+                text_string(title)
+            );
+            if let Some(prev) = i.checked_sub(1).map(|i| bookmark_ids[i]) {
+                let _ = write!(item, " /Prev {prev} 0 R");
+            }
+            if let Some(next) = bookmark_ids.get(i + 1) {
+                let _ = write!(item, " /Next {next} 0 R");
+            }
+            item.push_str(" >>");
+            self.object(bookmark_ids[i], &item)?;
+        }
+        let mut outline = format!("<< /Type /Outlines /Count {}", bookmark_ids.len());
+        if let (Some(first), Some(last)) = (bookmark_ids.first(), bookmark_ids.last()) {
+            let _ = write!(outline, " /First {first} 0 R /Last {last} 0 R");
+        }
+        outline.push_str(" >>");
+        self.object(outline_id, &outline)?;
+
+        // the info dictionary has no place for an identifier, so it goes in
+        // XMP metadata instead
+        let metadata_id = self.reserve();
+        let xmp = format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+             <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+             <dc:identifier>{}</dc:identifier>\n\
+             </rdf:Description>\n\
+             </rdf:RDF>\n\
+             </x:xmpmeta>\n\
+             <?xpacket end=\"w\"?>",
+            escape_xml(&info.identifier)
+        );
+        self.stream(metadata_id, "/Type /Metadata /Subtype /XML", xmp.as_bytes())?;
+
+        let info_id = self.reserve();
+        self.object(
+            info_id,
+            &format!(
+                "<< /Title {} /Author {} /Subject {} /Keywords {} /Creator (kscrpr) /Producer (kscrpr) >>",
+                text_string(&info.title),
+                text_string(&info.author),
+                text_string(&info.subject),
+                text_string(&info.keywords.join(", "))
+            ),
+        )?;
+
+        let catalog_id = self.reserve();
+        let direction = if rtl {
+            " /ViewerPreferences << /Direction /R2L >>"
+        } else {
+            ""
+        };
+        self.object(
+            catalog_id,
+            &format!(
+                "<< /Type /Catalog /Pages {} 0 R /Outlines {outline_id} 0 R /Metadata {metadata_id} 0 R{direction} >>",
+                self.pages_id
+            ),
+        )?;
+
+        let xref = self.out.written;
+        write!(
+            self.out,
+            "xref\n0 {}\n0000000000 65535 f \n",
+            self.offsets.len() + 1
+        )?;
+        for offset in &self.offsets {
+            // every reserved id has been written by now
+            write!(self.out, "{:010} 00000 n \n", offset.unwrap_or_default())?;
+        }
+        write!(
+            self.out,
+            "trailer\n<< /Size {} /Root {catalog_id} 0 R /Info {info_id} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            self.offsets.len() + 1
+        )?;
+
+        Ok(self.out.inner)
+    }
+}
+
+/// Content stream operators drawing a line of text with its baseline
+/// starting at `(x, y)`
+pub fn text((x, y): (f64, f64), size: f64, line: &str) -> String {
+    format!(
+        "BT 0 g /F1 {size:.3} Tf {x:.3} {y:.3} Td {} Tj ET",
+        literal(line)
+    )
+}
+
+/// Roughly how many characters of text of the given size fit in `width`
+pub fn chars_in(width: f64, size: f64) -> usize {
+    (width / (size * GLYPH_WIDTH)).max(1.0) as usize
+}
+
+/// A PDF literal string for text drawn in [`FONT`], anything it can't show
+/// becomes `?`
+fn literal(s: &str) -> String {
+    let mut out = "(".to_owned();
+    for c in s.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            // WinAnsiEncoding agrees with Latin-1 from here on
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// Split text into lines of at most `max_chars`, between words where it can
+pub fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word = word.chars().collect::<Vec<_>>();
+
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > max_chars {
+            lines.push(std::mem::take(&mut line));
+        }
+        // words too long for a line of their own are just broken up
+        while word.len() > max_chars {
+            let rest = word.split_off(max_chars);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// A PDF text string, as UTF-16 so any title survives
+fn text_string(s: &str) -> String {
+    let mut out = "<FEFF".to_owned();
+    for unit in s.encode_utf16() {
+        let _ = write!(out, "{unit:04X}");
+    }
+    out.push('>');
+    out
+}
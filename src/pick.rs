@@ -1,33 +1,48 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
 use std::time::{Duration, Instant};
 
-use color_eyre::Result;
-use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode};
-use crossterm::execute;
+use clap::ArgEnum;
+use color_eyre::{Report, Result};
+use crossterm::cursor::MoveTo;
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    MouseButton,
+};
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use indicatif::ProgressBar;
 use itertools::Itertools;
 use tui::backend::{Backend, CrosstermBackend};
-use tui::layout::{Constraint, Layout, Margin};
-use tui::style::{Color, Modifier, Style};
+use tui::layout::{Constraint, Layout, Margin, Rect};
+use tui::style::{Modifier, Style};
 use tui::text::{Span, Spans, Text};
-use tui::widgets::{List, ListItem, Paragraph};
+use tui::widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap};
 use tui::{Frame, Terminal};
 
 use crate::archive::Archive;
+use crate::artifacts;
+use crate::filesystem::FileSystem;
+use crate::keymap::{keymap, Action, Keys};
+use crate::opts::{opts, RenderProfile, SortKey};
+use crate::preview::Graphics;
+use crate::search::PageRange;
+use crate::theme::theme;
+use crate::utils::{fuck_error, fuzzy_score};
 
 use self::statefullist::StatefulList;
 
-fn render_archive<'a>(archive: &'a Archive) -> Text<'a> {
+pub fn render_archive<'a>(archive: &'a Archive) -> Text<'a> {
     let title = Span::styled(
         &archive.name,
         Style::default()
-            .fg(Color::Rgb(73, 159, 147))
+            .fg(theme().label.tui())
             .add_modifier(Modifier::BOLD),
     );
-    let sep = Span::styled(": ", Style::default().fg(Color::Rgb(73, 159, 147)));
-    let artist = Span::styled(
-        &archive.artist,
-        Style::default().fg(Color::Rgb(73, 159, 147)),
-    );
+    let sep = Span::styled(": ", Style::default().fg(theme().label.tui()));
+    let artist = Span::styled(archive.artist(), Style::default().fg(theme().label.tui()));
     let a = Spans::from(vec![title, sep, artist]);
     let b = Spans::from(
         Itertools::intersperse(
@@ -35,7 +50,7 @@ fn render_archive<'a>(archive: &'a Archive) -> Text<'a> {
                 Span::styled(
                     &t.name,
                     Style::default()
-                        .fg(Color::Rgb(32, 178, 170))
+                        .fg(theme().value.tui())
                         .add_modifier(Modifier::DIM),
                 )
             }),
@@ -47,7 +62,171 @@ fn render_archive<'a>(archive: &'a Archive) -> Text<'a> {
     Text { lines: vec![a, b] }
 }
 
-pub fn do_pick<'a>(query: &str, inputs: &'a [Archive]) -> Result<Option<&'a Archive>> {
+/// How well an archive's name, artists, or tags match every word of `filter`,
+/// or `None` if a word matches none of them
+fn filter_score(archive: &Archive, filter: &str) -> Option<u32> {
+    filter
+        .split_whitespace()
+        .map(|word| {
+            std::iter::once(archive.name.as_str())
+                .chain(archive.artists.iter().map(String::as_str))
+                .chain(archive.tags.iter().map(|t| t.name.as_str()))
+                .filter_map(|field| fuzzy_score(word, field))
+                .max()
+        })
+        .sum()
+}
+
+/// The indices of the archives matching `filter` that haven't been removed,
+/// in order of `sort` and otherwise in the order given. Relevance puts the
+/// best matches first
+fn filtered(inputs: &[Archive], removed: &[usize], filter: &str, sort: SortKey) -> Vec<usize> {
+    inputs
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !removed.contains(i))
+        .filter_map(|(i, archive)| Some((filter_score(archive, filter)?, i, archive)))
+        .sorted_by(|(s0, i0, a0), (s1, i1, a1)| match sort {
+            SortKey::Relevance => s1.cmp(s0).then(i0.cmp(i1)),
+            sort => sort.compare(a0, a1).then(i0.cmp(i1)),
+        })
+        .map(|(_, i, _)| i)
+        .collect()
+}
+
+/// Refilter the list, keeping the same archive highlighted if it's still in it
+fn refilter(
+    list: &mut StatefulList<usize>,
+    inputs: &[Archive],
+    removed: &[usize],
+    filter: &str,
+    sort: SortKey,
+) {
+    let highlighted = list.selected().map(|i| list.items()[i]);
+    list.set_items(filtered(inputs, removed, filter, sort));
+
+    let moved = highlighted.and_then(|h| list.items().iter().position(|i| *i == h));
+    if let Some(i) = moved {
+        list.select(i);
+    }
+}
+
+/// How many archives are read from sled or the search index at a time
+const FEED_PAGE: usize = 200;
+
+/// The indexes searched by terms that don't name one, the same as `get
+/// search` uses
+pub const SEARCH_INDEXES: [&str; 4] = ["name", "artist", "parody", "tag"];
+
+/// The rest of the library or of a search's results, read a page at a time
+/// as the highlight gets near the bottom of the list
+enum Feed {
+    Library { after: Option<u32> },
+    Search { query: String, offset: usize },
+}
+
+impl Feed {
+    /// The whole library for an empty query, otherwise what it finds
+    fn new(query: &str) -> Self {
+        if query.is_empty() {
+            Feed::Library { after: None }
+        } else {
+            Feed::Search {
+                query: query.to_owned(),
+                offset: 0,
+            }
+        }
+    }
+
+    async fn next_page(&mut self, fs: &FileSystem) -> Result<Vec<Archive>> {
+        match self {
+            Feed::Library { after } => {
+                let page = fs.fetch_page(*after, FEED_PAGE)?;
+                *after = page.last().map(|a| a.id).or(*after);
+                Ok(page)
+            }
+            Feed::Search { query, offset } => {
+                let page = fs
+                    .search(
+                        query,
+                        &SEARCH_INDEXES,
+                        Some(FEED_PAGE),
+                        *offset,
+                        PageRange::default(),
+                    )
+                    .await?;
+                *offset += page.len();
+                Ok(page)
+            }
+        }
+    }
+}
+
+/// What the picker lists
+enum Source {
+    /// The archives given, which were found by the query
+    Given(String),
+    /// The whole library, with a search box to list what a search finds
+    /// instead
+    Library,
+}
+
+/// The orders the sort action cycles through
+const SORTS: [SortKey; 6] = [
+    SortKey::Relevance,
+    SortKey::Name,
+    SortKey::Artist,
+    SortKey::Id,
+    SortKey::Pages,
+    SortKey::Added,
+];
+
+/// What the lines above and below the list say
+struct Header<'a> {
+    query: &'a str,
+    /// Whether the query is being typed into the search box, or `None` if
+    /// there's no search box
+    searching: Option<bool>,
+    filter: &'a str,
+    /// Whether the filter is being typed into
+    editing: bool,
+    /// How many archives there are before filtering
+    total: usize,
+    /// Whether there are more archives still to read
+    more: bool,
+    sort: SortKey,
+    /// What the status bar says, and whether it's an error
+    status: Option<(&'a str, bool)>,
+}
+
+/// Let the user pick archives from `inputs`, returning those marked or else
+/// the one highlighted, or nothing if they quit
+pub async fn do_pick<'a>(
+    query: &str,
+    inputs: &'a [Archive],
+    fs: &FileSystem,
+) -> Result<Vec<&'a Archive>> {
+    let mut archives = inputs.to_vec();
+    let selection = pick(&mut archives, Source::Given(query.to_owned()), fs).await?;
+
+    Ok(selection.into_iter().map(|i| &inputs[i]).collect())
+}
+
+/// Let the user search the whole library and pick archives from it, without
+/// a query to start from
+pub async fn do_ui(fs: &FileSystem) -> Result<Vec<Archive>> {
+    let mut archives = vec![];
+    let selection = pick(&mut archives, Source::Library, fs).await?;
+
+    Ok(selection.into_iter().map(|i| archives[i].clone()).collect())
+}
+
+/// Take over the terminal to let the user pick from `source`, returning the
+/// indices of the archives picked out of those read into `inputs`
+async fn pick(inputs: &mut Vec<Archive>, source: Source, fs: &FileSystem) -> Result<Vec<usize>> {
+    // read before taking over the terminal, so mistakes in it are shown
+    keymap()?;
+
     crossterm::terminal::enable_raw_mode()?;
     let mut stdout = std::io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -56,10 +235,12 @@ pub fn do_pick<'a>(query: &str, inputs: &'a [Archive]) -> Result<Option<&'a Arch
     terminal.clear()?;
 
     let tick_rate = Duration::from_millis(200);
-    let mut list = StatefulList::with_items(inputs.iter().map(render_archive).collect_vec());
-    list.next();
-    let selection = run_app(&mut terminal, query, list, tick_rate)?;
+    let graphics = Graphics::detect(opts().picker_preview);
+    let picked = run_app(&mut terminal, inputs, source, fs, graphics, tick_rate).await?;
 
+    if let Some(graphics) = graphics {
+        graphics.clear(terminal.backend_mut())?;
+    }
     crossterm::terminal::disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
@@ -68,46 +249,894 @@ pub fn do_pick<'a>(query: &str, inputs: &'a [Archive]) -> Result<Option<&'a Arch
     )?;
     terminal.show_cursor()?;
 
-    Ok(selection.map(|i| &inputs[i]))
+    match picked {
+        Picked::Archives(selection) => Ok(selection),
+        Picked::Paths(paths) => {
+            for i in paths {
+                println!("{}", fs.rendered_file_of_id(inputs[i].id).display());
+            }
+            Ok(vec![])
+        }
+    }
+}
+
+/// A dialog shown over the list, which captures all key presses
+enum Modal {
+    ConfirmDelete,
+    Blacklist {
+        selected: usize,
+    },
+    /// The menu of what can be done to all the marked archives at once
+    Batch {
+        selected: usize,
+    },
+    ConfirmBatchDelete,
+    /// Typing the directory to export the marked archives into
+    ExportTo {
+        dir: String,
+    },
+}
+
+/// Something done to all the marked archives at once from the batch menu
+#[derive(Clone, Copy)]
+enum BatchAction {
+    Render,
+    Delete,
+    Favorite,
+    Export,
+    PrintPaths,
+}
+
+/// The batch menu, in order
+const BATCH_ACTIONS: [BatchAction; 5] = [
+    BatchAction::Render,
+    BatchAction::Delete,
+    BatchAction::Favorite,
+    BatchAction::Export,
+    BatchAction::PrintPaths,
+];
+
+impl BatchAction {
+    fn label(self) -> &'static str {
+        match self {
+            BatchAction::Render => "Render",
+            BatchAction::Delete => "Delete",
+            BatchAction::Favorite => "Add to favorites",
+            BatchAction::Export => "Export to a directory",
+            BatchAction::PrintPaths => "Print their paths and quit",
+        }
+    }
+}
+
+/// How the picker was left
+enum Picked {
+    /// With these archives picked, or none if the user quit
+    Archives(Vec<usize>),
+    /// To print the rendered paths of these archives rather than pick them
+    Paths(Vec<usize>),
+}
+
+/// What the status bar says for a few seconds after something's done
+struct Message {
+    text: String,
+    error: bool,
+    at: Instant,
+}
+
+impl Message {
+    fn info(text: impl Into<String>) -> Self {
+        Message {
+            text: text.into(),
+            error: false,
+            at: Instant::now(),
+        }
+    }
+
+    /// An error shown in the status bar rather than leaving the picker over it
+    fn error(what: &str, e: &Report) -> Self {
+        Message {
+            text: format!("{what}: {e}"),
+            error: true,
+            at: Instant::now(),
+        }
+    }
+}
+
+/// How long messages stay in the status bar
+const MESSAGE_TIME: Duration = Duration::from_secs(4);
+
+enum TaskKind {
+    Render,
+    Export(PathBuf),
+}
+
+/// Batch work on the marked archives, done an archive at a time between
+/// drawing the picker so the status bar can show how far along it is
+struct Task {
+    kind: TaskKind,
+    /// The archives still to do
+    pending: VecDeque<usize>,
+    total: usize,
+    /// How many archives failed, and why the last of them did
+    failed: usize,
+    error: Option<Report>,
+}
+
+impl Task {
+    fn new(kind: TaskKind, targets: Vec<usize>) -> Self {
+        Task {
+            kind,
+            total: targets.len(),
+            pending: targets.into(),
+            failed: 0,
+            error: None,
+        }
+    }
+
+    /// What the status bar says while the task is going
+    fn describe(&self, inputs: &[Archive]) -> String {
+        let verb = match self.kind {
+            TaskKind::Render => "Rendering",
+            TaskKind::Export(_) => "Exporting",
+        };
+        let next = self
+            .pending
+            .front()
+            .map_or("", |i| inputs[*i].name.as_str());
+
+        format!(
+            "{verb} {}/{}: {next}",
+            self.total - self.pending.len() + 1,
+            self.total
+        )
+    }
+
+    /// Do the next archive, returning what to say once they're all done
+    fn step(&mut self, fs: &FileSystem, inputs: &[Archive]) -> Option<Message> {
+        if let Some(i) = self.pending.pop_front() {
+            let archive = &inputs[i];
+            let hidden = ProgressBar::hidden();
+            let done = match &self.kind {
+                // there's nothing to render them from
+                TaskKind::Render if fs.is_evicted(archive.id) => Ok(()),
+                TaskKind::Render => fs.render_archive(archive, &hidden, &hidden),
+                TaskKind::Export(dir) => fs
+                    .export_archive(
+                        archive,
+                        dir,
+                        RenderProfile::Standard,
+                        false,
+                        &hidden,
+                        &hidden,
+                    )
+                    .map(|_| ()),
+            };
+
+            if let Err(e) = done {
+                self.failed += 1;
+                self.error = Some(e);
+            }
+        }
+
+        if !self.pending.is_empty() {
+            return None;
+        }
+
+        let (verb, done) = match &self.kind {
+            TaskKind::Render => ("render", "Rendered".to_owned()),
+            TaskKind::Export(dir) => ("export", format!("Exported to {}", dir.display())),
+        };
+        Some(match &self.error {
+            Some(e) => Message::error(
+                &format!("Couldn't {verb} {} of {} archives", self.failed, self.total),
+                e,
+            ),
+            None => Message::info(format!("{done}: {} archives", self.total)),
+        })
+    }
 }
 
-fn run_app<'a, B: Backend>(
+/// Delete archives, committing the search index once they're all gone
+async fn delete_archives<'a>(
+    fs: &FileSystem,
+    archives: impl IntoIterator<Item = &'a Archive>,
+) -> Result<()> {
+    for archive in archives {
+        fs.remove_archive(archive).await?;
+    }
+    fs.searcher.commit().await
+}
+
+/// How many archives fit in the list at once, each taking two lines between
+/// the search and filter lines and the status bar
+fn page_size(area: Rect) -> usize {
+    (area.height.saturating_sub(5) / 2).max(1) as usize
+}
+
+/// Whether a key press edits the filter while it's being typed into, rather
+/// than going through the keymap
+fn is_typing(key: &KeyEvent) -> bool {
+    matches!(
+        key.code,
+        KeyCode::Char(_) | KeyCode::Backspace | KeyCode::Enter | KeyCode::Esc
+    ) && !key
+        .modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+}
+
+/// The commands that put what they read on the system clipboard, for the
+/// platform and the display servers running
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        return vec![("pbcopy", &[][..])];
+    }
+    if cfg!(windows) {
+        return vec![("clip", &[][..])];
+    }
+
+    let mut commands = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(("wl-copy", &[][..]));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        commands.push(("xclip", &["-selection", "clipboard"][..]));
+        commands.push(("xsel", &["--clipboard", "--input"][..]));
+    }
+    commands
+}
+
+/// Put `text` on the system clipboard with the first clipboard command that
+/// works, or else the OSC 52 escape, which the terminal passes on to the
+/// system clipboard, even over ssh
+fn copy_to_clipboard(out: &mut impl Write, text: &str) -> Result<()> {
+    for (command, args) in clipboard_commands() {
+        let child = std::process::Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+
+    write!(out, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// The sort after `sort` in the order the sort action cycles through
+fn next_sort(sort: SortKey) -> SortKey {
+    let next = SORTS.iter().position(|s| *s == sort).map_or(0, |i| i + 1);
+    SORTS[next % SORTS.len()]
+}
+
+/// Open an archive's rendered file from the picker, saying how it went
+fn open_archive<B: Backend>(
     terminal: &mut Terminal<B>,
-    query: &str,
-    mut list: StatefulList<Text<'a>>,
+    fs: &FileSystem,
+    archive: &Archive,
+) -> Result<Message> {
+    let opened = fs.open_archive(archive.id);
+    // archives that aren't rendered yet are rendered first, which writes over
+    // the screen
+    terminal.clear()?;
+
+    Ok(match opened {
+        Ok(()) => Message::info(format!("Opened {}", archive.name)),
+        Err(e) => Message::error("Couldn't open the archive", &e),
+    })
+}
+
+/// How long after clicking on an archive clicking on it again opens it
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Where the things that can be clicked on were drawn
+#[derive(Default)]
+struct ClickAreas {
+    list: Rect,
+    /// The index of the first archive shown in the list
+    offset: usize,
+    sort: Rect,
+    preview: Rect,
+}
+
+impl ClickAreas {
+    /// The index into the list of the archive drawn at a cell, which may be
+    /// past the end of the list
+    fn archive_at(&self, column: u16, row: u16) -> Option<usize> {
+        if !contains(self.list, column, row) {
+            return None;
+        }
+        // each archive takes two lines
+        Some(self.offset + (row - self.list.y) as usize / 2)
+    }
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    (area.left()..area.right()).contains(&column) && (area.top()..area.bottom()).contains(&row)
+}
+
+/// Remember the archive highlighted while picking from `query`, so picking
+/// from it again starts there
+fn remember_position(fs: &FileSystem, query: &str, highlighted: Option<&Archive>) {
+    if let Some(archive) = highlighted {
+        if let Err(e) = fs.set_picker_position(query, archive.id) {
+            tracing::error!(
+                error = fuck_error(&e),
+                query,
+                "Failed to remember the picker's position, treating this as a non-fatal error though"
+            );
+        }
+    }
+}
+
+/// Draw an archive's thumbnail at the top left of `area`, scaled to fit in it,
+/// generating the thumbnail first if it's missing
+fn draw_cover(
+    out: &mut impl Write,
+    graphics: Graphics,
+    fs: &FileSystem,
+    archive: &Archive,
+    area: Rect,
+) -> Result<()> {
+    let path = fs.thumb_file_of_id(archive.id);
+    if !path.exists() {
+        artifacts::generate_thumbnail(fs, archive)?;
+    }
+    let cover = image::open(path)?;
+
+    queue!(out, MoveTo(area.x, area.y))?;
+    graphics.draw(out, &cover, (area.width, area.height))?;
+    out.flush()?;
+
+    Ok(())
+}
+
+async fn run_app<B: Backend + Write>(
+    terminal: &mut Terminal<B>,
+    inputs: &mut Vec<Archive>,
+    source: Source,
+    fs: &FileSystem,
+    graphics: Option<Graphics>,
     tick_rate: Duration,
-) -> Result<Option<usize>> {
+) -> Result<Picked> {
     let mut last_tick = Instant::now();
+    let mut modal = None;
+    let searchable = matches!(source, Source::Library);
+    let (mut query, mut feed) = match source {
+        Source::Given(query) => (query, None),
+        Source::Library => (String::new(), Some(Feed::new(""))),
+    };
+    // typed into the search box, which only searches once enter is pressed
+    let mut search = String::new();
+    let mut searching = false;
+    // earlier searches, oldest first, and which of them up and down have put
+    // in the search box along with what was typed there before
+    let mut history = if searchable {
+        fs.recent_searches()?
+    } else {
+        vec![]
+    };
+    let mut recalled: Option<(usize, String)> = None;
+    let mut list = StatefulList::with_items(filtered(inputs, &[], "", SortKey::Relevance));
+    list.next();
+    // the archive highlighted when this was last picked from, which the
+    // highlight goes back to once it's been read
+    let mut restore = fs.picker_position(&query);
+    // typed to narrow down the list, which is refiltered on every key press
+    let mut filter = String::new();
+    // whether typing goes to the filter rather than the keymap
+    let mut editing = false;
+    let mut sort = SortKey::Relevance;
+    // archives deleted from the picker, which stay out of the list
+    let mut removed = vec![];
+    // archives marked to be picked together, which survive refiltering
+    let mut marked: Vec<usize> = vec![];
+    let mut favorites = (0..inputs.len())
+        .filter(|i| fs.is_favorite(inputs[*i].id))
+        .collect_vec();
+    let mut preview = graphics.is_some();
+    let mut keys = Keys::new()?;
+    // where the cover on screen was drawn and whose it is
+    let mut shown = None;
+    // when an archive was last clicked on, to tell double clicks
+    let mut last_click = None;
+    let mut message: Option<Message> = None;
+    let mut task: Option<Task> = None;
     loop {
-        terminal.draw(|f| ui(f, query, &mut list))?;
+        // read more once the highlight gets within a page of the bottom
+        while let Some(current) = &mut feed {
+            let wanted = list.selected().map_or(0, |i| i + 1) + page_size(terminal.size()?);
+            let waiting = restore.map_or(false, |id| inputs.iter().all(|a| a.id != id));
+            if list.items().len() >= wanted && !waiting {
+                break;
+            }
+
+            let page = match current.next_page(fs).await {
+                Ok(page) => page,
+                Err(e) => {
+                    message = Some(Message::error("Couldn't read more archives", &e));
+                    feed = None;
+                    break;
+                }
+            };
+            if page.len() < FEED_PAGE {
+                feed = None;
+            }
+
+            let start = inputs.len();
+            inputs.extend(page);
+            favorites.extend((start..inputs.len()).filter(|i| fs.is_favorite(inputs[*i].id)));
+            refilter(&mut list, inputs, &removed, &filter, sort);
+        }
+
+        if let Some(id) = restore {
+            if let Some(i) = list.items().iter().position(|i| inputs[*i].id == id) {
+                list.select(i);
+            }
+            if feed.is_none() || inputs.iter().any(|a| a.id == id) {
+                restore = None;
+            }
+        }
+
+        let highlighted = list.selected().map(|i| &inputs[list.items()[i]]);
+
+        let total = inputs.len() - removed.len();
+        let more = feed.is_some();
+        let status = match (&task, &message) {
+            (Some(task), _) => Some((task.describe(inputs), false)),
+            (None, Some(m)) if m.at.elapsed() < MESSAGE_TIME => Some((m.text.clone(), m.error)),
+            _ => None,
+        };
+        let mut draw = |terminal: &mut Terminal<B>| -> Result<(Option<Rect>, ClickAreas)> {
+            let mut drawn = (None, ClickAreas::default());
+            terminal.draw(|f| {
+                drawn = ui(
+                    f,
+                    Header {
+                        query: if searching { &search } else { &query },
+                        searching: searchable.then(|| searching),
+                        filter: &filter,
+                        editing,
+                        total,
+                        more,
+                        sort,
+                        status: status.as_ref().map(|(text, error)| (text.as_str(), *error)),
+                    },
+                    inputs,
+                    &mut list,
+                    (&marked, &favorites),
+                    graphics.map(|_| preview),
+                    modal.as_ref(),
+                )
+            })?;
+            Ok(drawn)
+        };
+        let (cover_area, clicks) = draw(terminal)?;
+
+        // covers are drawn behind tui's back, so they're only drawn again
+        // when the highlight or the layout changes
+        let wanted = cover_area
+            .filter(|_| modal.is_none())
+            .zip(highlighted.map(|a| a.id));
+        if let (Some(graphics), true) = (graphics, wanted != shown) {
+            graphics.clear(terminal.backend_mut())?;
+            // redrawing everything is the only way to get rid of the last one
+            terminal.clear()?;
+            draw(terminal)?;
+
+            if let (Some((area, _)), Some(archive)) = (wanted, highlighted) {
+                if let Err(e) = draw_cover(terminal.backend_mut(), graphics, fs, archive, area) {
+                    tracing::debug!(
+                        error = fuck_error(&e),
+                        id = archive.id,
+                        "Couldn't show the cover"
+                    );
+                }
+            }
+            shown = wanted;
+        }
+
+        // batch work goes an archive at a time, checking for key presses
+        // between them
+        if let Some(current) = &mut task {
+            if let Some(done) = current.step(fs, inputs) {
+                message = Some(done);
+                task = None;
+            }
+        }
 
         let timeout = tick_rate
             .checked_sub(last_tick.elapsed())
+            .filter(|_| task.is_none())
             .unwrap_or_else(|| Duration::from_secs(0));
 
         if crossterm::event::poll(timeout)? {
-            match event::read()? {
-                Event::Key(key) => match key.code {
-                    KeyCode::Char('q') => return Ok(None),
-                    KeyCode::Esc => return Ok(None),
-                    KeyCode::Down => list.next(),
-                    KeyCode::Up => list.previous(),
-                    KeyCode::PageDown => {
-                        for _ in 0..10 {
-                            list.next();
+            let event = event::read()?;
+
+            if let Some(current) = &mut modal {
+                let key = match event {
+                    Event::Key(key) => key.code,
+                    _ => continue,
+                };
+                // what batch actions are done to, the same as what's picked
+                let targets = if marked.is_empty() {
+                    list.selected()
+                        .map(|i| list.items()[i])
+                        .into_iter()
+                        .collect_vec()
+                } else {
+                    marked.clone()
+                };
+
+                match (current, key, highlighted) {
+                    (Modal::ExportTo { dir }, KeyCode::Char(c), _) => dir.push(c),
+                    (Modal::ExportTo { dir }, KeyCode::Backspace, _) => {
+                        dir.pop();
+                    }
+                    (Modal::ExportTo { dir }, KeyCode::Enter, _) => {
+                        let dir = match dir.trim() {
+                            "" => PathBuf::from("."),
+                            dir => PathBuf::from(dir),
+                        };
+                        task = Some(Task::new(TaskKind::Export(dir), targets));
+                        modal = None;
+                    }
+                    (_, KeyCode::Esc | KeyCode::Char('n') | KeyCode::Char('q'), _) => modal = None,
+                    (Modal::ConfirmDelete, KeyCode::Char('y'), Some(archive)) => {
+                        modal = None;
+                        if let Err(e) = delete_archives(fs, [archive]).await {
+                            message = Some(Message::error("Couldn't delete the archive", &e));
+                            continue;
+                        }
+
+                        message = Some(Message::info(format!("Deleted {}", archive.name)));
+                        if let Some(i) = list.selected() {
+                            let input = list.items()[i];
+                            removed.push(input);
+                            marked.retain(|m| *m != input);
+                        }
+                        list.remove_selected();
+
+                        if removed.len() == inputs.len() && !searchable {
+                            return Ok(Picked::Archives(vec![]));
                         }
                     }
-                    KeyCode::PageUp => {
-                        for _ in 0..10 {
-                            list.previous();
+                    (Modal::ConfirmBatchDelete, KeyCode::Char('y'), _) => {
+                        modal = None;
+                        let archives = targets.iter().map(|i| &inputs[*i]);
+                        if let Err(e) = delete_archives(fs, archives).await {
+                            message = Some(Message::error("Couldn't delete the archives", &e));
+                            continue;
                         }
+
+                        message =
+                            Some(Message::info(format!("Deleted {} archives", targets.len())));
+                        removed.extend(&targets);
+                        marked.clear();
+                        refilter(&mut list, inputs, &removed, &filter, sort);
+
+                        if removed.len() == inputs.len() && !searchable {
+                            return Ok(Picked::Archives(vec![]));
+                        }
+                    }
+                    (Modal::Blacklist { selected }, KeyCode::Left, _) => {
+                        *selected = selected.saturating_sub(1);
+                    }
+                    (Modal::Blacklist { selected }, KeyCode::Right, Some(archive)) => {
+                        *selected = (*selected + 1).min(archive.tags.len().saturating_sub(1));
+                    }
+                    (Modal::Blacklist { selected }, KeyCode::Enter, Some(archive)) => {
+                        if let Some(tag) = archive.tags.get(*selected) {
+                            message = Some(match fs.blacklist_tag(&tag.name) {
+                                Ok(()) => Message::info(format!("Blacklisted {}", tag.name)),
+                                Err(e) => Message::error("Couldn't blacklist the tag", &e),
+                            });
+                        }
+                        modal = None;
+                    }
+                    (Modal::Batch { selected }, KeyCode::Up, _) => {
+                        *selected = selected.saturating_sub(1);
+                    }
+                    (Modal::Batch { selected }, KeyCode::Down, _) => {
+                        *selected = (*selected + 1).min(BATCH_ACTIONS.len() - 1);
+                    }
+                    (Modal::Batch { selected }, KeyCode::Enter, _) => {
+                        modal = match BATCH_ACTIONS[*selected] {
+                            BatchAction::Render => {
+                                task = Some(Task::new(TaskKind::Render, targets));
+                                None
+                            }
+                            BatchAction::Delete => Some(Modal::ConfirmBatchDelete),
+                            BatchAction::Favorite => {
+                                let favorited = targets
+                                    .iter()
+                                    .try_for_each(|i| fs.set_favorite(inputs[*i].id, true));
+                                message = Some(match favorited {
+                                    Ok(()) => {
+                                        for i in &targets {
+                                            if !favorites.contains(i) {
+                                                favorites.push(*i);
+                                            }
+                                        }
+                                        Message::info(format!(
+                                            "Added {} archives to favorites",
+                                            targets.len()
+                                        ))
+                                    }
+                                    Err(e) => {
+                                        Message::error("Couldn't add the archives to favorites", &e)
+                                    }
+                                });
+                                None
+                            }
+                            BatchAction::Export => Some(Modal::ExportTo { dir: String::new() }),
+                            BatchAction::PrintPaths => return Ok(Picked::Paths(targets)),
+                        };
                     }
-                    KeyCode::Enter => return Ok(list.selected()),
                     _ => {}
+                }
+
+                continue;
+            }
+
+            match event {
+                Event::Key(key)
+                    if searching
+                        && (is_typing(&key) || matches!(key.code, KeyCode::Up | KeyCode::Down)) =>
+                {
+                    match key.code {
+                        KeyCode::Char(c) => {
+                            search.push(c);
+                            recalled = None;
+                        }
+                        KeyCode::Backspace => {
+                            search.pop();
+                            recalled = None;
+                        }
+                        KeyCode::Up => {
+                            let i = recalled.as_ref().map_or(history.len(), |(i, _)| *i);
+                            if i > 0 {
+                                let typed = recalled
+                                    .take()
+                                    .map_or_else(|| search.clone(), |(_, typed)| typed);
+                                search = history[i - 1].clone();
+                                recalled = Some((i - 1, typed));
+                            }
+                        }
+                        KeyCode::Down => {
+                            if let Some((i, typed)) = recalled.take() {
+                                if i + 1 < history.len() {
+                                    search = history[i + 1].clone();
+                                    recalled = Some((i + 1, typed));
+                                } else {
+                                    search = typed;
+                                }
+                            }
+                        }
+                        KeyCode::Enter => {
+                            remember_position(fs, &query, highlighted);
+                            query = search.trim().to_owned();
+                            if !query.is_empty() {
+                                if let Err(e) = fs.push_search_history(&query) {
+                                    message =
+                                        Some(Message::error("Couldn't remember the search", &e));
+                                }
+                                history.retain(|h| *h != query);
+                                history.push(query.clone());
+                            }
+
+                            // marks and the like are indices into what's
+                            // been read, so go with it
+                            feed = Some(Feed::new(&query));
+                            restore = fs.picker_position(&query);
+                            task = None;
+                            inputs.clear();
+                            removed.clear();
+                            marked.clear();
+                            favorites.clear();
+                            list.set_items(vec![]);
+                        }
+                        _ => {}
+                    }
+                    // escape goes back to what was searched for before
+                    searching = !matches!(key.code, KeyCode::Enter | KeyCode::Esc);
+                    if !searching {
+                        recalled = None;
+                    }
+                }
+                Event::Key(key) if editing && is_typing(&key) => {
+                    match key.code {
+                        KeyCode::Char(c) => filter.push(c),
+                        KeyCode::Backspace => {
+                            filter.pop();
+                        }
+                        KeyCode::Esc => filter.clear(),
+                        _ => {}
+                    }
+                    // enter keeps the filter and esc throws it away
+                    editing = matches!(key.code, KeyCode::Char(_) | KeyCode::Backspace);
+                    if key.code != KeyCode::Enter {
+                        list.set_items(filtered(inputs, &removed, &filter, sort));
+                    }
+                }
+                Event::Key(key) => match keys.press(key) {
+                    Some(Action::Quit) => {
+                        remember_position(fs, &query, highlighted);
+                        return Ok(Picked::Archives(vec![]));
+                    }
+                    Some(Action::Filter) => editing = true,
+                    Some(Action::Search) => {
+                        if searchable {
+                            search = query.clone();
+                            searching = true;
+                        }
+                    }
+                    Some(Action::Sort) => {
+                        sort = next_sort(sort);
+                        // keep the same archive highlighted where it's moved to
+                        refilter(&mut list, inputs, &removed, &filter, sort);
+                    }
+                    Some(Action::Open) => {
+                        if let Some(archive) = highlighted {
+                            message = Some(open_archive(terminal, fs, archive)?);
+                            shown = None;
+                        }
+                    }
+                    Some(Action::OpenData) => {
+                        if let Some(archive) = highlighted {
+                            message = Some(match opener::open(fs.data_dir_of_id(archive.id)) {
+                                Ok(()) => Message::info("Opened the image directory"),
+                                Err(e) => Message::error(
+                                    "Couldn't open the image directory",
+                                    &Report::from(e),
+                                ),
+                            });
+                        }
+                    }
+                    Some(
+                        action @ (Action::CopyPath
+                        | Action::CopyDataPath
+                        | Action::CopyId
+                        | Action::CopyUrl),
+                    ) => {
+                        if let Some(archive) = highlighted {
+                            let text = match action {
+                                Action::CopyPath => {
+                                    fs.rendered_file_of_id(archive.id).display().to_string()
+                                }
+                                Action::CopyDataPath => {
+                                    fs.data_dir_of_id(archive.id).display().to_string()
+                                }
+                                Action::CopyId => archive.id.to_string(),
+                                _ => archive.base_url.to_string(),
+                            };
+                            let copied = copy_to_clipboard(terminal.backend_mut(), &text);
+                            message = Some(match copied {
+                                Ok(()) => Message::info(format!("Copied {text}")),
+                                Err(e) => Message::error("Couldn't copy to the clipboard", &e),
+                            });
+                        }
+                    }
+                    Some(Action::Preview) => preview = graphics.is_some() && !preview,
+                    Some(Action::Delete) => {
+                        if highlighted.is_some() {
+                            modal = Some(Modal::ConfirmDelete);
+                        }
+                    }
+                    Some(Action::Blacklist) => {
+                        if highlighted.map_or(false, |a| !a.tags.is_empty()) {
+                            modal = Some(Modal::Blacklist { selected: 0 });
+                        }
+                    }
+                    Some(Action::Batch) => {
+                        if !marked.is_empty() || highlighted.is_some() {
+                            modal = Some(Modal::Batch { selected: 0 });
+                        }
+                    }
+                    Some(Action::Favorite) => {
+                        if let Some(i) = list.selected() {
+                            let input = list.items()[i];
+                            let favorite = !favorites.contains(&input);
+                            if let Err(e) = fs.set_favorite(inputs[input].id, favorite) {
+                                message = Some(Message::error("Couldn't change the favorite", &e));
+                            } else if favorite {
+                                favorites.push(input);
+                                message = Some(Message::info("Added to favorites"));
+                            } else {
+                                favorites.retain(|f| *f != input);
+                                message = Some(Message::info("Removed from favorites"));
+                            }
+                        }
+                    }
+                    Some(Action::Mark) => {
+                        if let Some(i) = list.selected() {
+                            let input = list.items()[i];
+                            match marked.iter().position(|m| *m == input) {
+                                Some(pos) => {
+                                    marked.remove(pos);
+                                }
+                                None => marked.push(input),
+                            }
+                            list.next();
+                        }
+                    }
+                    Some(Action::Down) => list.next(),
+                    Some(Action::Up) => list.previous(),
+                    Some(Action::PageDown) => {
+                        let page = page_size(terminal.size()?);
+                        list.select(list.selected().map_or(0, |i| i + page));
+                    }
+                    Some(Action::PageUp) => {
+                        let page = page_size(terminal.size()?);
+                        list.select(list.selected().map_or(0, |i| i.saturating_sub(page)));
+                    }
+                    Some(Action::HalfPageDown) => {
+                        let half = (page_size(terminal.size()?) / 2).max(1);
+                        list.select(list.selected().map_or(0, |i| i + half));
+                    }
+                    Some(Action::HalfPageUp) => {
+                        let half = (page_size(terminal.size()?) / 2).max(1);
+                        list.select(list.selected().map_or(0, |i| i.saturating_sub(half)));
+                    }
+                    Some(Action::Top) => list.select(0),
+                    Some(Action::Bottom) => list.select(usize::MAX),
+                    Some(Action::Select) => {
+                        remember_position(fs, &query, highlighted);
+                        if marked.is_empty() {
+                            marked.extend(list.selected().map(|i| list.items()[i]));
+                        }
+                        marked.sort_unstable();
+                        return Ok(Picked::Archives(marked));
+                    }
+                    None => {}
                 },
                 Event::Mouse(evt) => match evt.kind {
                     event::MouseEventKind::ScrollDown => list.next(),
                     event::MouseEventKind::ScrollUp => list.previous(),
+                    event::MouseEventKind::Down(button) => {
+                        let (column, row) = (evt.column, evt.row);
+                        let clicked = clicks
+                            .archive_at(column, row)
+                            .filter(|i| *i < list.items().len());
+
+                        if button == MouseButton::Left && contains(clicks.sort, column, row) {
+                            sort = next_sort(sort);
+                            refilter(&mut list, inputs, &removed, &filter, sort);
+                        } else if button == MouseButton::Left
+                            && contains(clicks.preview, column, row)
+                        {
+                            preview = graphics.is_some() && !preview;
+                        } else if let Some(i) = clicked {
+                            // a second click on the same archive soon after
+                            // the first opens it, like a middle click does
+                            let double = last_click.map_or(false, |(at, j): (Instant, usize)| {
+                                j == i && at.elapsed() < DOUBLE_CLICK
+                            });
+                            list.select(i);
+                            last_click = Some((Instant::now(), i));
+
+                            if button == MouseButton::Middle
+                                || (button == MouseButton::Left && double)
+                            {
+                                last_click = None;
+                                message =
+                                    Some(open_archive(terminal, fs, &inputs[list.items()[i]])?);
+                                shown = None;
+                            }
+                        }
+                    }
                     _ => {}
                 },
                 _ => {}
@@ -120,7 +1149,145 @@ fn run_app<'a, B: Backend>(
     }
 }
 
-fn ui<'a, B: Backend>(f: &mut Frame<B>, query: &str, list: &mut StatefulList<Text<'a>>) {
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Draw a dialog over the list, about the highlighted archive or the
+/// `targets` archives a batch action is done to
+fn render_modal<B: Backend>(
+    f: &mut Frame<B>,
+    modal: &Modal,
+    highlighted: Option<&Archive>,
+    targets: usize,
+) {
+    let (title, body) = match (modal, highlighted) {
+        (Modal::ConfirmDelete, Some(archive)) => (
+            "Delete archive",
+            Text::from(vec![
+                Spans::from(Span::styled(
+                    archive.pretty_single_line(),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Spans::from(""),
+                Spans::from("Delete this archive? (y/n)"),
+            ]),
+        ),
+        (Modal::Blacklist { selected }, Some(archive)) => {
+            let tags = Itertools::intersperse(
+                archive.tags.iter().enumerate().map(|(i, t)| {
+                    let style = if i == *selected {
+                        Style::default()
+                            .fg(theme().label.tui())
+                            .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+                    } else {
+                        Style::default().fg(theme().value.tui())
+                    };
+                    Span::styled(t.name.as_str(), style)
+                }),
+                Span::raw(" "),
+            )
+            .collect_vec();
+
+            (
+                "Blacklist tag",
+                Text::from(vec![
+                    Spans::from(tags),
+                    Spans::from(""),
+                    Spans::from("Choose with left/right, enter to blacklist, esc to cancel"),
+                ]),
+            )
+        }
+        (Modal::Batch { selected }, _) => {
+            let mut lines = vec![
+                Spans::from(Span::styled(
+                    format!("{targets} archives"),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Spans::from(""),
+            ];
+            lines.extend(BATCH_ACTIONS.iter().enumerate().map(|(i, action)| {
+                let style = if i == *selected {
+                    Style::default()
+                        .fg(theme().label.tui())
+                        .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+                } else {
+                    Style::default().fg(theme().value.tui())
+                };
+                Spans::from(Span::styled(action.label(), style))
+            }));
+            lines.push(Spans::from(""));
+            lines.push(Spans::from(
+                "Choose with up/down, enter to do it, esc to cancel",
+            ));
+
+            ("Batch actions", Text::from(lines))
+        }
+        (Modal::ConfirmBatchDelete, _) => (
+            "Delete archives",
+            Text::from(vec![
+                Spans::from(Span::styled(
+                    format!("{targets} archives"),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Spans::from(""),
+                Spans::from("Delete all of them? (y/n)"),
+            ]),
+        ),
+        (Modal::ExportTo { dir }, _) => (
+            "Export archives",
+            Text::from(vec![
+                Spans::from(vec![
+                    Span::raw(format!("Export {targets} archives to: ")),
+                    Span::styled(dir.as_str(), Style::default().fg(theme().label.tui())),
+                    Span::styled("▏", Style::default().fg(theme().accent.tui())),
+                ]),
+                Spans::from(""),
+                Spans::from("Enter to export, into the current directory if left empty"),
+            ]),
+        ),
+        _ => return,
+    };
+
+    let height = (body.lines.len() as u16 + 2).max(7);
+    let area = centered_rect(f.size().width * 3 / 4, height, f.size());
+    let dialog = Paragraph::new(body)
+        .wrap(Wrap { trim: true })
+        .block(Block::default().title(title).borders(Borders::ALL));
+
+    f.render_widget(Clear, area);
+    f.render_widget(dialog, area);
+}
+
+fn ui<B: Backend>(
+    f: &mut Frame<B>,
+    header: Header,
+    inputs: &[Archive],
+    list: &mut StatefulList<usize>,
+    (marked, favorites): (&[usize], &[usize]),
+    preview: Option<bool>,
+    modal: Option<&Modal>,
+) -> (Option<Rect>, ClickAreas) {
+    let Header {
+        query,
+        searching,
+        filter,
+        editing,
+        total,
+        more,
+        sort,
+        status,
+    } = header;
+    let highlighted = list.selected().map(|i| &inputs[list.items()[i]]);
+
     let chunks = Layout::default()
         .margin(1)
         .direction(tui::layout::Direction::Vertical)
@@ -128,18 +1295,41 @@ fn ui<'a, B: Backend>(f: &mut Frame<B>, query: &str, list: &mut StatefulList<Tex
             Constraint::Length(1),
             Constraint::Length(1),
             Constraint::Min(1),
+            Constraint::Length(1),
         ])
         .split(f.size());
 
     let items = list
         .items()
         .iter()
-        .map(|i| ListItem::new(i.clone()))
+        .map(|input| {
+            let mut text = render_archive(&inputs[*input]);
+            let marker = format!(
+                "{}{} ",
+                if marked.contains(input) { "●" } else { " " },
+                if favorites.contains(input) {
+                    "★"
+                } else {
+                    " "
+                },
+            );
+            if let Some(first) = text.lines.first_mut() {
+                first.0.insert(
+                    0,
+                    Span::styled(marker, Style::default().fg(theme().accent.tui())),
+                );
+            }
+            ListItem::new(text)
+        })
         .collect_vec();
 
     let items = List::new(items)
         // .block(Block::default())
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+        .highlight_style(
+            Style::default()
+                .fg(theme().highlight.tui())
+                .add_modifier(Modifier::BOLD),
+        )
         .highlight_symbol("｜")
         .repeat_highlight_symbol(true);
 
@@ -147,15 +1337,25 @@ fn ui<'a, B: Backend>(f: &mut Frame<B>, query: &str, list: &mut StatefulList<Tex
         Span::styled(
             "Search: ",
             Style::default()
-                .fg(Color::Rgb(32, 178, 170))
+                .fg(theme().dim.tui())
                 .add_modifier(Modifier::DIM),
         ),
         Span::styled(
             query,
             Style::default()
-                .fg(Color::Rgb(73, 159, 147))
+                .fg(theme().label.tui())
                 .add_modifier(Modifier::BOLD),
         ),
+        match searching {
+            Some(true) => Span::styled("▏", Style::default().fg(theme().accent.tui())),
+            Some(false) if query.is_empty() => Span::styled(
+                "ctrl-f to search the library",
+                Style::default()
+                    .fg(theme().dim.tui())
+                    .add_modifier(Modifier::DIM),
+            ),
+            _ => Span::raw(""),
+        },
     ])));
 
     f.render_widget(
@@ -166,15 +1366,137 @@ fn ui<'a, B: Backend>(f: &mut Frame<B>, query: &str, list: &mut StatefulList<Tex
         }),
     );
 
-    f.render_stateful_widget(items, chunks[2], list.state());
+    let mut spans = vec![
+        Span::styled(
+            "Filter: ",
+            Style::default()
+                .fg(theme().dim.tui())
+                .add_modifier(Modifier::DIM),
+        ),
+        Span::styled(filter, Style::default().fg(theme().label.tui())),
+        if editing {
+            Span::styled("▏", Style::default().fg(theme().accent.tui()))
+        } else if filter.is_empty() {
+            Span::styled(
+                "/ to filter",
+                Style::default()
+                    .fg(theme().dim.tui())
+                    .add_modifier(Modifier::DIM),
+            )
+        } else {
+            Span::raw("")
+        },
+        Span::styled(
+            format!(
+                "  {}/{}{} of {}{} ({} marked, by ",
+                list.selected().map_or(0, |i| i + 1),
+                list.items().len(),
+                if more { "+" } else { "" },
+                total,
+                if more { "+" } else { "" },
+                marked.len(),
+            ),
+            Style::default()
+                .fg(theme().dim.tui())
+                .add_modifier(Modifier::DIM),
+        ),
+        Span::styled(
+            sort.to_possible_value().map_or("", |v| v.get_name()),
+            Style::default().fg(theme().accent.tui()),
+        ),
+        Span::styled(
+            ")",
+            Style::default()
+                .fg(theme().dim.tui())
+                .add_modifier(Modifier::DIM),
+        ),
+    ];
+    if let Some(preview) = preview {
+        spans.push(Span::raw("  "));
+        spans.push(Span::styled(
+            if preview { "cover on" } else { "cover off" },
+            Style::default().fg(theme().accent.tui()),
+        ));
+    }
+
+    let line = chunks[1].inner(&Margin {
+        vertical: 0,
+        horizontal: 2,
+    });
+    // where a span ended up on the line, to click on
+    let span_area = |i: usize| {
+        let x = spans[..i].iter().map(Span::width).sum::<usize>() as u16;
+        let x = line.x.saturating_add(x).min(line.right());
+        let width = (spans[i].width() as u16).min(line.right() - x);
+        Rect::new(x, line.y, width, 1)
+    };
+    let mut clicks = ClickAreas {
+        sort: span_area(4),
+        preview: if preview.is_some() {
+            span_area(7)
+        } else {
+            Rect::default()
+        },
+        ..ClickAreas::default()
+    };
+
+    f.render_widget(Paragraph::new(Text::from(Spans::from(spans))), line);
+
+    let (list_area, cover_area) = if preview == Some(true) {
+        let columns = Layout::default()
+            .direction(tui::layout::Direction::Horizontal)
+            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+            .split(chunks[2]);
+        (columns[0], Some(columns[1]))
+    } else {
+        (chunks[2], None)
+    };
+
+    // each archive takes two lines
+    clicks.list = list_area;
+    clicks.offset = list.offset((list_area.height / 2) as usize);
+    f.render_stateful_widget(items, list_area, list.state());
+
+    // the cover itself is drawn over the block once tui is done
+    let cover_area = cover_area.map(|area| {
+        let block = Block::default().title("Cover").borders(Borders::ALL);
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        inner
+    });
+
+    if let Some((text, error)) = status {
+        let style = if error {
+            Style::default()
+                .fg(theme().error.tui())
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(theme().accent.tui())
+        };
+        f.render_widget(
+            Paragraph::new(Span::styled(text, style)),
+            chunks[3].inner(&Margin {
+                vertical: 0,
+                horizontal: 2,
+            }),
+        );
+    }
+
+    if let Some(modal) = modal {
+        render_modal(f, modal, highlighted, marked.len().max(1));
+    }
+
+    (cover_area, clicks)
 }
 
-mod statefullist {
+pub mod statefullist {
     use tui::widgets::ListState;
 
     pub struct StatefulList<T> {
         state: ListState,
         items: Vec<T>,
+        /// The first item shown, which tui keeps to itself
+        offset: usize,
     }
 
     impl<T> StatefulList<T> {
@@ -182,9 +1504,32 @@ mod statefullist {
             StatefulList {
                 state: ListState::default(),
                 items,
+                offset: 0,
             }
         }
 
+        /// The first item shown when `rows` items fit, scrolled as little as
+        /// needed to show the highlighted item, the same as tui scrolls lists
+        /// of items that are all the same height
+        pub fn offset(&mut self, rows: usize) -> usize {
+            // tui leaves empty lists as they were
+            if self.items.is_empty() {
+                return self.offset;
+            }
+            let rows = rows.max(1);
+            let selected = self.state.selected().unwrap_or(0);
+
+            self.offset = self.offset.min(self.items.len().saturating_sub(1));
+            if selected >= self.offset + rows {
+                self.offset = selected + 1 - rows;
+            }
+            if selected < self.offset {
+                self.offset = selected;
+            }
+
+            self.offset
+        }
+
         pub fn items(&self) -> &[T] {
             &self.items
         }
@@ -193,7 +1538,24 @@ mod statefullist {
             self.state.selected()
         }
 
+        /// Highlight the `i`th item, or the last if there aren't that many
+        pub fn select(&mut self, i: usize) {
+            if !self.items.is_empty() {
+                self.state.select(Some(i.min(self.items.len() - 1)));
+            }
+        }
+
+        /// Replace the items, highlighting the first
+        pub fn set_items(&mut self, items: Vec<T>) {
+            self.items = items;
+            self.state
+                .select(if self.items.is_empty() { None } else { Some(0) });
+        }
+
         pub fn next(&mut self) {
+            if self.items.is_empty() {
+                return;
+            }
             let i = match self.state.selected() {
                 Some(i) => {
                     if i >= self.items.len() - 1 {
@@ -208,6 +1570,9 @@ mod statefullist {
         }
 
         pub fn previous(&mut self) {
+            if self.items.is_empty() {
+                return;
+            }
             let i = match self.state.selected() {
                 Some(i) => {
                     if i == 0 {
@@ -221,6 +1586,18 @@ mod statefullist {
             self.state.select(Some(i));
         }
 
+        /// Remove the highlighted item, keeping the highlight in place
+        pub fn remove_selected(&mut self) {
+            if let Some(i) = self.state.selected() {
+                self.items.remove(i);
+                if self.items.is_empty() {
+                    self.state.select(None);
+                } else {
+                    self.state.select(Some(i.min(self.items.len() - 1)));
+                }
+            }
+        }
+
         pub fn state(&mut self) -> &mut ListState {
             &mut self.state
         }
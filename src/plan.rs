@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use color_eyre::Result;
+
+use crate::archive::Archive;
+use crate::filesystem::FileSystem;
+use crate::scrape::DownloadSize;
+
+/// What fetching a set of archives would add to the library
+pub struct FetchPlan {
+    pub archives: Vec<(Archive, DownloadSize)>,
+    /// Archives that would be skipped because of a blacklisted tag
+    pub blacklisted: usize,
+    /// Total size of the zips that would be downloaded
+    pub download_size: u64,
+    pub pages: u64,
+    /// How long downloading would take at recent download speeds
+    pub download_time: Option<Duration>,
+    /// Estimated size on disk of the images and rendered files
+    pub disk_size: u64,
+    /// Current size of the library on disk
+    pub library_size: u64,
+}
+
+/// The size of the library on disk, and how many pages that size is spread
+/// over, counting both the extracted images and the rendered files
+fn library_usage(fs: &FileSystem) -> Result<(u64, u64)> {
+    let mut bytes = 0;
+    let mut pages = 0;
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+
+        if fs.is_evicted(archive.id) {
+            continue;
+        }
+
+        bytes += fs.content_size_of(archive.id);
+        pages += archive.num_pages as u64;
+    }
+
+    Ok((bytes, pages))
+}
+
+pub fn plan(fs: &FileSystem, candidates: Vec<(Archive, DownloadSize)>) -> Result<FetchPlan> {
+    let (blacklisted, archives): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|(archive, _)| fs.blacklisted_tag_of(archive).is_some());
+
+    let download_size: u64 = archives.iter().map(|(_, size)| size.0 as u64).sum();
+    let pages: u64 = archives.iter().map(|(a, _)| a.num_pages as u64).sum();
+
+    let download_time = fs
+        .download_rate()?
+        .map(|rate| Duration::from_secs_f64(download_size as f64 / rate));
+
+    let (library_size, library_pages) = library_usage(fs)?;
+
+    // with nothing local to go off, assume the images take as much space as
+    // the zip and the rendered file as much again
+    let disk_size = match library_size.checked_div(library_pages) {
+        Some(per_page) => per_page * pages,
+        None => download_size * 2,
+    };
+
+    Ok(FetchPlan {
+        archives,
+        blacklisted: blacklisted.len(),
+        download_size,
+        pages,
+        download_time,
+        disk_size,
+        library_size,
+    })
+}
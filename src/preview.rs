@@ -0,0 +1,187 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use color_eyre::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbImage};
+
+use crate::opts::PreviewProtocol;
+
+/// A guess at the size of a terminal cell in pixels, as there's no asking the
+/// terminal through crossterm
+const CELL_SIZE: (u32, u32) = (8, 16);
+
+/// How many base64 bytes go in each kitty graphics escape, its maximum
+const KITTY_CHUNK: usize = 4096;
+
+/// A way of drawing images in the terminal
+#[derive(Clone, Copy)]
+pub enum Graphics {
+    Kitty,
+    Iterm,
+    Sixel,
+}
+
+impl Graphics {
+    /// The protocol to draw images with, guessing from the environment with
+    /// `auto`. Sixel support can only be found out by asking the terminal, so
+    /// it's only used when it's asked for
+    pub fn detect(protocol: PreviewProtocol) -> Option<Self> {
+        match protocol {
+            PreviewProtocol::None => None,
+            PreviewProtocol::Kitty => Some(Graphics::Kitty),
+            PreviewProtocol::Iterm => Some(Graphics::Iterm),
+            PreviewProtocol::Sixel => Some(Graphics::Sixel),
+            PreviewProtocol::Auto => {
+                let term = std::env::var("TERM").unwrap_or_default();
+                let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+
+                if term == "xterm-kitty" || std::env::var_os("KITTY_WINDOW_ID").is_some() {
+                    Some(Graphics::Kitty)
+                } else if matches!(term_program.as_str(), "iTerm.app" | "WezTerm") {
+                    Some(Graphics::Iterm)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Draw `image` at the cursor, scaled to fit in `cols` by `rows` cells
+    pub fn draw(
+        self,
+        out: &mut impl Write,
+        image: &DynamicImage,
+        (cols, rows): (u16, u16),
+    ) -> Result<()> {
+        let (width, height) = fit(image, (cols, rows));
+        let image = image.resize_exact(width, height, FilterType::Triangle);
+        let (cols, rows) = (
+            (width + CELL_SIZE.0 - 1) / CELL_SIZE.0,
+            (height + CELL_SIZE.1 - 1) / CELL_SIZE.1,
+        );
+
+        match self {
+            Graphics::Kitty => {
+                let encoded = base64::encode(png(&image)?);
+                let chunks = encoded.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = (i + 1 < chunks.len()) as u8;
+                    if i == 0 {
+                        write!(out, "\x1b_Gf=100,a=T,q=2,C=1,c={cols},r={rows},m={more};")?;
+                    } else {
+                        write!(out, "\x1b_Gm={more};")?;
+                    }
+                    out.write_all(chunk)?;
+                    write!(out, "\x1b\\")?;
+                }
+            }
+            Graphics::Iterm => {
+                let png = png(&image)?;
+                write!(
+                    out,
+                    "\x1b]1337;File=inline=1;size={};width={cols};height={rows};preserveAspectRatio=1:{}\x07",
+                    png.len(),
+                    base64::encode(&png)
+                )?;
+            }
+            Graphics::Sixel => out.write_all(sixel(&image.to_rgb8()).as_bytes())?,
+        }
+
+        Ok(())
+    }
+
+    /// Remove every image drawn, for terminals that keep them above text
+    /// rather than drawing them into the cells
+    pub fn clear(self, out: &mut impl Write) -> Result<()> {
+        if let Graphics::Kitty = self {
+            write!(out, "\x1b_Ga=d,q=2\x1b\\")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The biggest size in pixels `image` can be scaled to in `cols` by `rows`
+/// cells, keeping its aspect ratio
+fn fit(image: &DynamicImage, (cols, rows): (u16, u16)) -> (u32, u32) {
+    let (max_width, max_height) = (cols as u32 * CELL_SIZE.0, rows as u32 * CELL_SIZE.1);
+    let (width, height) = image.dimensions();
+    let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+
+    (
+        ((width as f64 * scale) as u32).max(1),
+        ((height as f64 * scale) as u32).max(1),
+    )
+}
+
+fn png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut png = vec![];
+    image.write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Encode an image as sixels, with its colors reduced to 6 levels of each of
+/// red, green, and blue
+fn sixel(image: &RgbImage) -> String {
+    let level = |v: u8| (v as u32 * 5 + 127) / 255;
+    let color = |x, y| {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        level(r) * 36 + level(g) * 6 + level(b)
+    };
+    let (width, height) = image.dimensions();
+
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for i in 0..216 {
+        let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+        let _ = write!(out, "#{i};2;{};{};{}", r * 20, g * 20, b * 20);
+    }
+
+    // each row of sixels is a band 6 pixels tall, drawn over once per color
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+        let colors = rows
+            .clone()
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| color(x, y))
+            .collect::<BTreeSet<_>>();
+
+        for (n, c) in colors.into_iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{c}");
+
+            let mut run = ('?', 0);
+            for x in 0..width {
+                let bits = rows
+                    .clone()
+                    .filter(|y| color(x, *y) == c)
+                    .fold(0, |bits, y| bits | 1 << (y - top));
+                let sixel = (63 + bits) as u8 as char;
+
+                if sixel != run.0 {
+                    push_run(&mut out, run);
+                    run = (sixel, 0);
+                }
+                run.1 += 1;
+            }
+            push_run(&mut out, run);
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Add `count` of a sixel, run length encoded once that's shorter
+fn push_run(out: &mut String, (sixel, count): (char, usize)) {
+    if count > 3 {
+        let _ = write!(out, "!{count}{sixel}");
+    } else {
+        out.extend(std::iter::repeat(sixel).take(count));
+    }
+}
@@ -1,4 +1,4 @@
-use color_eyre::{eyre::eyre, Result};
+use color_eyre::Result;
 use indicatif::ProgressBar;
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
@@ -56,16 +56,11 @@ pub struct ArchiveMeta {
 }
 
 impl ArchiveMeta {
-    pub fn as_archive(&self, base_url: Url, download_url: Url) -> Archive {
+    pub fn as_archive(&self, base_url: Url, download_url: Url, raw: String) -> Archive {
         Archive {
             id: self.id,
             name: self.title.clone(),
-            artist: self
-                .artists
-                .first()
-                .map(|a| a.name.clone())
-                .ok_or_else(|| eyre!("Archive had no artist?"))
-                .unwrap(),
+            artists: self.artists.iter().map(|a| a.name.clone()).collect_vec(),
             parody: self
                 .parodies
                 .first()
@@ -82,6 +77,14 @@ impl ArchiveMeta {
             num_pages: self.pages,
             base_url,
             download_url,
+            final_download_url: None,
+            verified: false,
+            added_at: None,
+            last_fetched_at: None,
+            source_site: Some(opts().base_url.clone()),
+            site: opts().base_url.host_str().unwrap_or_default().to_owned(),
+            custom: Default::default(),
+            raw_metadata: Some(raw),
         }
     }
 }
@@ -89,7 +92,8 @@ impl ArchiveMeta {
 async fn fetch_archive(client: &Client, url: &Url) -> Result<(Archive, DownloadSize)> {
     tracing::debug!(%url, "Fetching archive");
 
-    let meta: ArchiveMeta = client.get(url.join(".json")?).send().await?.json().await?;
+    let raw_meta = client.get(url.join(".json")?).send().await?.text().await?;
+    let meta: ArchiveMeta = serde_json::from_str(&raw_meta)?;
 
     let page = client.get(url.as_str()).send().await?.text().await?;
     let doc = Html::parse_document(&page);
@@ -104,7 +108,9 @@ async fn fetch_archive(client: &Client, url: &Url) -> Result<(Archive, DownloadS
 
     let download_url = Url::parse(download_url)?;
 
-    Ok((meta.as_archive(url.clone(), download_url), meta.size))
+    let size = meta.size;
+
+    Ok((meta.as_archive(url.clone(), download_url, raw_meta), size))
 }
 
 pub async fn fetch_tag_page(
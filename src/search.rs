@@ -1,21 +1,141 @@
+use std::collections::HashMap;
 use std::path::Path;
 
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Help, Result};
+use itertools::Itertools;
 use tantivy::collector::{DocSetCollector, TopDocs};
-use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
-use tantivy::schema::{Schema, INDEXED, STORED, TEXT};
-use tantivy::{doc, Index, IndexWriter, Term};
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, Occur, PhraseQuery, Query, QueryParser, RangeQuery,
+    TermQuery,
+};
+use tantivy::schema::{
+    Field, FieldType, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED,
+    STORED, STRING, TEXT,
+};
+use tantivy::tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer, TokenStream};
+use tantivy::SnippetGenerator;
+use tantivy::{doc, DocAddress, DocSet, Document, Index, IndexWriter, Term, TERMINATED};
 use tokio::sync::Mutex;
 
-use crate::archive::Archive;
+use crate::archive::{normalize_tag, Archive};
+use crate::opts::{opts, SearchTokenizer};
+
+/// Inclusive bounds on the page count of archives to match
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageRange {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl PageRange {
+    fn query(&self, pages_field: Field) -> Option<Box<dyn Query>> {
+        if self.min.is_none() && self.max.is_none() {
+            return None;
+        }
+
+        let range = self.min.unwrap_or(0)..self.max.map_or(u64::MAX, |max| max.saturating_add(1));
+
+        Some(Box::new(RangeQuery::new_u64(pages_field, range)))
+    }
+
+    /// Restrict `query` to archives within the range
+    fn restrict(&self, pages_field: Field, query: Box<dyn Query>) -> Box<dyn Query> {
+        match self.query(pages_field) {
+            Some(range) => Box::new(BooleanQuery::new(vec![
+                (Occur::Must, query),
+                (Occur::Must, range),
+            ])),
+            None => query,
+        }
+    }
+}
+
+/// Explains which parts of archives matched a search
+pub struct Why {
+    generators: Vec<(&'static str, SnippetGenerator)>,
+}
+
+impl Why {
+    /// The fields of `archive` that matched, with the matching terms in brackets
+    pub fn explain(&self, archive: &Archive) -> Vec<(&'static str, String)> {
+        let mut out = vec![];
+
+        for (field, generator) in &self.generators {
+            let values = match *field {
+                "name" => vec![archive.name.clone()],
+                "artist" => archive.artists.clone(),
+                "parody" => vec![archive.parody.clone()],
+                "tag" => archive
+                    .tags
+                    .iter()
+                    .map(|t| normalize_tag(&t.name))
+                    .collect(),
+                _ => unreachable!(),
+            };
+
+            for value in values {
+                let snippet = generator.snippet(&value);
+                if snippet.highlighted().is_empty() {
+                    continue;
+                }
+
+                let fragment = snippet.fragment();
+                let mut marked = String::new();
+                let mut last = 0;
+                for range in snippet.highlighted() {
+                    marked.push_str(&fragment[last..range.start]);
+                    marked.push('[');
+                    marked.push_str(&fragment[range.clone()]);
+                    marked.push(']');
+                    last = range.end;
+                }
+                marked.push_str(&fragment[last..]);
+
+                out.push((*field, marked));
+            }
+        }
+
+        out
+    }
+}
+
+/// Bump this whenever the fields below change, indexes built with any other
+/// version are recreated empty when opened and refilled from sled
+const SCHEMA_VERSION: u32 = 3;
+
+/// File in the index directory holding the version it was built with
+const SCHEMA_VERSION_FILE: &str = "schema_version";
+
+/// File in the index directory holding the `--tag-normalization` the tags
+/// were indexed (and the tag trees built) with
+const TAG_NORMALIZATION_FILE: &str = "tag_normalization";
 
 pub struct Searcher {
     index: Index,
     writer: Mutex<IndexWriter>,
+    /// Recent query results, cleared on every commit
+    cache: sled::Tree,
+    /// The index was just recreated because it was built with another schema
+    migrated: bool,
 }
 
 impl Searcher {
-    pub fn new(base_dir: &Path) -> Result<Self> {
+    pub fn new(base_dir: &Path, cache: sled::Tree, tokenizer: SearchTokenizer) -> Result<Self> {
+        let version_file = base_dir.join(SCHEMA_VERSION_FILE);
+        let built_with = std::fs::read_to_string(&version_file)
+            .ok()
+            .and_then(|v| v.trim().parse::<u32>().ok());
+
+        let migrated = base_dir.join("meta.json").exists() && built_with != Some(SCHEMA_VERSION);
+        if migrated {
+            tracing::info!(
+                ?built_with,
+                current = SCHEMA_VERSION,
+                "Search index schema is out of date, recreating it"
+            );
+            std::fs::remove_dir_all(base_dir)?;
+        }
+
         std::fs::create_dir_all(base_dir)?;
         let mmap_directory = tantivy::directory::MmapDirectory::open(base_dir)?;
         let index = if Index::exists(&mmap_directory)? {
@@ -23,96 +143,577 @@ impl Searcher {
         } else {
             let mut schema_builder = Schema::builder();
             schema_builder.add_u64_field("id", INDEXED | STORED);
-            schema_builder.add_text_field("name", TEXT);
-            schema_builder.add_text_field("artist", TEXT);
+            let split_text = TextOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(tokenizer.name())
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            );
+            schema_builder.add_text_field("name", split_text.clone());
+            schema_builder.add_text_field("artist", split_text);
             schema_builder.add_text_field("parody", TEXT);
-            schema_builder.add_text_field("tag", TEXT);
+            // tags are indexed whole (after normalization) so multi-word tags can be matched exactly
+            schema_builder.add_text_field("tag", STRING);
+            schema_builder.add_text_field("site", STRING | STORED);
+            schema_builder.add_u64_field("pages", INDEXED | FAST);
+            // custom metadata, searchable as `meta.key:value`
+            schema_builder.add_json_field("meta", TEXT);
+            // the whole record, so results don't need looking up in sled
+            schema_builder.add_bytes_field("archive", STORED);
 
             let schema = schema_builder.build();
 
-            Index::create_in_dir(base_dir, schema)?
+            let index = Index::create_in_dir(base_dir, schema)?;
+            std::fs::write(&version_file, SCHEMA_VERSION.to_string())?;
+            index
         };
 
+        let schema = index.schema();
+        let name_field = schema.get_field("name").unwrap();
+        let indexed_with = match schema.get_field_entry(name_field).field_type() {
+            FieldType::Str(options) => options
+                .get_indexing_options()
+                .map(|indexing| indexing.tokenizer().to_owned()),
+            _ => None,
+        };
+        if indexed_with.as_deref() != Some(tokenizer.name()) {
+            return Err(eyre!(
+                "The search index was built with the {:?} tokenizer, not {:?}",
+                indexed_with.unwrap_or_default(),
+                tokenizer.name()
+            ))
+            .suggestion("Run `kscrpr reindex` to rebuild it with the new --search-tokenizer");
+        }
+
+        // indexes from before this was recorded are taken to match
+        let normalization = opts().tag_normalization.name();
+        let normalization_file = base_dir.join(TAG_NORMALIZATION_FILE);
+        match std::fs::read_to_string(&normalization_file) {
+            Ok(built_with) if built_with.trim() != normalization => {
+                return Err(eyre!(
+                    "The search index and tag directories were built with the {:?} tag normalization, not {:?}",
+                    built_with.trim(),
+                    normalization
+                ))
+                .suggestion("Run `kscrpr reindex` to rebuild them with the new --tag-normalization");
+            }
+            Ok(_) => (),
+            Err(_) => std::fs::write(&normalization_file, normalization)?,
+        }
+
+        index.tokenizers().register(
+            SearchTokenizer::Ngram.name(),
+            TextAnalyzer::from(NgramTokenizer::new(2, 3, false)).filter(LowerCaser),
+        );
+
         let writer = Mutex::new(index.writer(3000000)?);
 
-        Ok(Self { index, writer })
+        Ok(Self {
+            index,
+            writer,
+            cache,
+            migrated,
+        })
     }
 
-    pub async fn add_archive(&self, archive: &Archive) -> Result<()> {
+    /// Whether the index was recreated on opening because it was built with
+    /// an older schema, and so needs refilling with [`Searcher::refill`]
+    pub fn migrated(&self) -> bool {
+        self.migrated
+    }
+
+    /// Index every archive and commit, to fill a freshly migrated index
+    pub fn refill(&mut self, archives: impl Iterator<Item = Result<Archive>>) -> Result<u64> {
+        let mut count = 0;
+
+        for archive in archives {
+            let doc = self.document(&archive?)?;
+            self.writer.get_mut().add_document(doc)?;
+            count += 1;
+        }
+
+        self.writer.get_mut().commit()?;
+        self.cache.clear()?;
+        self.migrated = false;
+
+        Ok(count)
+    }
+
+    fn document(&self, archive: &Archive) -> Result<Document> {
         let schema = self.index.schema();
         let id = schema.get_field("id").unwrap();
         let name = schema.get_field("name").unwrap();
         let artist = schema.get_field("artist").unwrap();
         let parody = schema.get_field("parody").unwrap();
         let tag = schema.get_field("tag").unwrap();
-
-        let writer = self.writer.lock().await;
+        let site = schema.get_field("site").unwrap();
+        let pages = schema.get_field("pages").unwrap();
+        let meta = schema.get_field("meta").unwrap();
+        let stored = schema.get_field("archive").unwrap();
 
         let mut doc = doc!(
             id => archive.id as u64,
             name => archive.name.clone(),
-            artist => archive.artist.clone(),
             parody => archive.parody.clone(),
+            site => archive.site().to_owned(),
+            pages => archive.num_pages as u64,
+            stored => serde_cbor::to_vec(archive)?,
         );
 
+        for artist_v in &archive.artists {
+            doc.add_text(artist, artist_v);
+        }
+
         for tag_v in &archive.tags {
-            doc.add_text(tag, &tag_v.name);
+            doc.add_text(tag, &normalize_tag(&tag_v.name));
         }
 
+        if !archive.custom.is_empty() {
+            let custom = archive
+                .custom
+                .iter()
+                .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                .collect();
+            doc.add_json_object(meta, custom);
+        }
+
+        Ok(doc)
+    }
+
+    /// Index an archive, replacing any document already indexed for its id
+    pub async fn add_archive(&self, archive: &Archive) -> Result<()> {
+        let id = self.index.schema().get_field("id").unwrap();
+        let doc = self.document(archive)?;
+
+        let writer = self.writer.lock().await;
+        writer.delete_term(Term::from_field_u64(id, archive.id as u64));
         writer.add_document(doc)?;
 
         Ok(())
     }
 
+    pub async fn delete_archive(&self, id: u32) -> Result<()> {
+        let id_field = self.index.schema().get_field("id").unwrap();
+
+        let writer = self.writer.lock().await;
+        writer.delete_term(Term::from_field_u64(id_field, id as u64));
+
+        Ok(())
+    }
+
+    /// Remove every document, to rebuild the index from scratch
+    pub async fn clear(&self) -> Result<()> {
+        let writer = self.writer.lock().await;
+        writer.delete_all_documents()?;
+
+        Ok(())
+    }
+
+    /// Number of documents in the last committed version of the index
+    pub fn num_docs(&self) -> Result<u64> {
+        Ok(self.index.reader()?.searcher().num_docs())
+    }
+
+    /// Every tag in the index along with how many archives have it
+    pub fn tag_counts(&self) -> Result<HashMap<String, u64>> {
+        let reader = self.index.reader()?;
+        let searcher = reader.searcher();
+        let tag_field = self.index.schema().get_field("tag").unwrap();
+
+        let mut counts = HashMap::<String, u64>::new();
+
+        for segment in searcher.segment_readers() {
+            let inverted_index = segment.inverted_index(tag_field)?;
+            let alive = segment.alive_bitset();
+
+            let mut terms = inverted_index.terms().stream()?;
+            while terms.advance() {
+                let mut postings = inverted_index
+                    .read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)?;
+
+                // doc frequencies include deleted documents, so count the live ones
+                let mut count = 0;
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    if alive.map_or(true, |alive| alive.is_alive(doc)) {
+                        count += 1;
+                    }
+                    doc = postings.advance();
+                }
+
+                if count > 0 {
+                    let tag = String::from_utf8_lossy(terms.key()).into_owned();
+                    *counts.entry(tag).or_default() += count;
+                }
+            }
+        }
+
+        Ok(counts)
+    }
+
     pub async fn commit(&self) -> Result<()> {
         let mut writer = self.writer.lock().await;
         writer.prepare_commit()?.commit_future().await?;
 
+        self.cache.clear()?;
+
+        Ok(())
+    }
+
+    pub async fn with_all_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        self.with_tags(tags, Occur::Must, excluded, pages).await
+    }
+
+    pub async fn with_any_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        self.with_tags(tags, Occur::Should, excluded, pages).await
+    }
+
+    pub async fn with_no_tags(
+        &self,
+        tags: &[String],
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        self.with_tags(tags, Occur::MustNot, excluded, pages).await
+    }
+
+    /// Key for the query cache, which includes the index's last commit opstamp
+    /// so results from before a commit are never returned
+    fn cache_key(&self, parts: &[&str]) -> Result<Vec<u8>> {
+        let opstamp = self.index.load_metas()?.opstamp;
+        Ok(format!("{opstamp}\0{}", parts.join("\0")).into_bytes())
+    }
+
+    fn cache_get(&self, key: &[u8]) -> Option<Vec<Archive>> {
+        let v = self.cache.get(key).ok()??;
+        serde_cbor::from_slice(&v).ok()
+    }
+
+    fn cache_put(&self, key: &[u8], archives: &[Archive]) -> Result<()> {
+        self.cache.insert(key, serde_cbor::to_vec(archives)?)?;
         Ok(())
     }
 
-    pub async fn with_all_tags(&self, tags: &[String]) -> Result<Vec<u32>> {
+    async fn with_tags(
+        &self,
+        tags: &[String],
+        occur: Occur,
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let occur_s = format!("{occur:?}");
+        let pages_s = format!("{pages:?}");
+        let excluded_s = excluded.iter().map(|t| format!("!{t}")).collect::<Vec<_>>();
+        let parts = ["tags", occur_s.as_str(), pages_s.as_str()]
+            .into_iter()
+            .chain(tags.iter().map(String::as_str))
+            .chain(excluded_s.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        let key = self.cache_key(&parts)?;
+
+        if let Some(archives) = self.cache_get(&key) {
+            tracing::debug!(?tags, ?excluded, "Using cached tag query results");
+            return Ok(archives);
+        }
+
+        let archives = self
+            .with_tags_uncached(tags, occur, excluded, pages)
+            .await?;
+        self.cache_put(&key, &archives)?;
+
+        Ok(archives)
+    }
+
+    async fn with_tags_uncached(
+        &self,
+        tags: &[String],
+        occur: Occur,
+        excluded: &[String],
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let schema = self.index.schema();
+        let tag_field = schema.get_field("tag").unwrap();
+        let pages_field = schema.get_field("pages").unwrap();
+
+        let mut query_terms = tags
+            .iter()
+            .map(|tag| (occur, tag))
+            .chain(excluded.iter().map(|tag| (Occur::MustNot, tag)))
+            .map(|(occur, tag)| {
+                (
+                    occur,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(tag_field, &normalize_tag(tag)),
+                        IndexRecordOption::Basic,
+                    )) as Box<dyn Query>,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // a query of only exclusions matches nothing, so exclude from everything
+        if query_terms
+            .iter()
+            .all(|(occur, _)| *occur == Occur::MustNot)
+        {
+            query_terms.push((Occur::Must, Box::new(AllQuery)));
+        }
+
+        let query = pages.restrict(pages_field, Box::new(BooleanQuery::new(query_terms)));
+
+        self.archives_matching(query.as_ref()).await
+    }
+
+    /// Every archive matching a query
+    async fn archives_matching(&self, query: &dyn Query) -> Result<Vec<Archive>> {
         let reader = self.index.reader()?;
         let searcher = reader.searcher();
 
+        let all_docs = searcher.search(query, &DocSetCollector)?;
+
+        self.load_archives(&searcher, all_docs).await
+    }
+
+    /// Read the archives stored with the given documents
+    async fn load_archives(
+        &self,
+        searcher: &tantivy::Searcher,
+        doc_addresses: impl IntoIterator<Item = DocAddress>,
+    ) -> Result<Vec<Archive>> {
+        let archive_field = self.index.schema().get_field("archive").unwrap();
+
+        let mut archives = vec![];
+
+        for doc_address in doc_addresses {
+            let doc = searcher.doc_async(doc_address).await?;
+            let stored = doc
+                .get_first(archive_field)
+                .and_then(|v| v.as_bytes())
+                .ok_or_else(|| eyre!("An indexed document has no stored archive"))
+                .suggestion("Run `kscrpr reindex` to rebuild the search index")?;
+
+            archives.push(serde_cbor::from_slice::<Archive>(stored)?);
+        }
+
+        // local archives are keyed by id alone, so an id indexed from more
+        // than one site still only refers to one archive
+        Ok(archives.into_iter().unique_by(|a| a.id).collect())
+    }
+
+    /// Archives by an artist that also have all of the given tags
+    ///
+    /// The artist name is matched as a phrase, so `get artist "foo bar"`
+    /// doesn't also return archives by "bar foo".
+    pub async fn by_artist(&self, artist: &str, tags: &[String]) -> Result<Vec<Archive>> {
+        self.by_phrase("artist", artist, tags).await
+    }
+
+    /// Archives of a parody, matched as a phrase like [`Self::by_artist`]
+    pub async fn by_parody(&self, parody: &str) -> Result<Vec<Archive>> {
+        self.by_phrase("parody", parody, &[]).await
+    }
+
+    /// A query matching `value` as a phrase in a tokenized field, or `None` if
+    /// nothing in `value` is indexable
+    fn phrase_query(&self, field: Field, value: &str) -> Result<Option<Box<dyn Query>>> {
+        let tokenizer = self.index.tokenizer_for_field(field)?;
+        let mut stream = tokenizer.token_stream(value);
+        let mut terms = vec![];
+        while stream.advance() {
+            // ngrams all share one position, so keep the positions the
+            // tokenizer gives rather than numbering terms in order
+            let token = stream.token();
+            terms.push((token.position, Term::from_field_text(field, &token.text)));
+        }
+
+        Ok(match terms.len() {
+            0 => None,
+            1 => Some(Box::new(TermQuery::new(
+                terms.pop().unwrap().1,
+                IndexRecordOption::Basic,
+            ))),
+            _ => Some(Box::new(PhraseQuery::new_with_offset(terms))),
+        })
+    }
+
+    /// The archives most like `archive`, scored by shared tags, with sharing
+    /// an artist or parody counting for more
+    pub async fn similar(&self, archive: &Archive, limit: usize) -> Result<Vec<Archive>> {
+        let id_s = archive.id.to_string();
+        let limit_s = limit.to_string();
+        let key = self.cache_key(&["similar", &id_s, &limit_s])?;
+
+        if let Some(archives) = self.cache_get(&key) {
+            tracing::debug!(id = archive.id, "Using cached similar archives");
+            return Ok(archives);
+        }
+
         let schema = self.index.schema();
         let id_field = schema.get_field("id").unwrap();
         let tag_field = schema.get_field("tag").unwrap();
 
-        let query_terms = tags
+        let mut query_terms: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::MustNot,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(id_field, archive.id as u64),
+                IndexRecordOption::Basic,
+            )),
+        )];
+
+        for tag in &archive.tags {
+            query_terms.push((
+                Occur::Should,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(tag_field, &normalize_tag(&tag.name)),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let mut phrases = archive
+            .artists
             .iter()
-            .map(|tag| {
+            .map(|artist| ("artist", artist.as_str(), 3.0))
+            .collect_vec();
+        // "original" is what archives without a parody are given
+        if archive.parody != "original" {
+            phrases.push(("parody", archive.parody.as_str(), 2.0));
+        }
+
+        for (field, value, boost) in phrases {
+            if let Some(query) = self.phrase_query(schema.get_field(field).unwrap(), value)? {
+                query_terms.push((Occur::Should, Box::new(BoostQuery::new(query, boost))));
+            }
+        }
+
+        let reader = self.index.reader()?;
+        let searcher = reader.searcher();
+
+        let top_docs = searcher.search(
+            &BooleanQuery::new(query_terms),
+            &TopDocs::with_limit(limit.max(1)),
+        )?;
+
+        let archives = self
+            .load_archives(&searcher, top_docs.into_iter().map(|(_score, doc)| doc))
+            .await?;
+        self.cache_put(&key, &archives)?;
+
+        Ok(archives)
+    }
+
+    /// Archives whose `field` contains `value` as a phrase and that have all
+    /// of the given tags
+    async fn by_phrase(&self, field: &str, value: &str, tags: &[String]) -> Result<Vec<Archive>> {
+        let parts = [field, value]
+            .into_iter()
+            .chain(tags.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        let key = self.cache_key(&parts)?;
+
+        if let Some(archives) = self.cache_get(&key) {
+            tracing::debug!(field, value, ?tags, "Using cached phrase query results");
+            return Ok(archives);
+        }
+
+        let schema = self.index.schema();
+        let tag_field = schema.get_field("tag").unwrap();
+
+        let phrase_query = self
+            .phrase_query(schema.get_field(field).unwrap(), value)?
+            .ok_or_else(|| eyre!("{:?} isn't a searchable {} name", value, field))?;
+
+        let query_terms = std::iter::once((Occur::Must, phrase_query))
+            .chain(tags.iter().map(|tag| {
                 (
                     Occur::Must,
                     Box::new(TermQuery::new(
-                        Term::from_field_text(tag_field, tag),
-                        tantivy::schema::IndexRecordOption::Basic,
+                        Term::from_field_text(tag_field, &normalize_tag(tag)),
+                        IndexRecordOption::Basic,
                     )) as Box<dyn Query>,
                 )
-            })
+            }))
             .collect();
         let query = BooleanQuery::new(query_terms);
 
-        let all_docs = searcher.search(&query, &DocSetCollector)?;
+        let archives = self.archives_matching(&query).await?;
+        self.cache_put(&key, &archives)?;
+
+        Ok(archives)
+    }
 
-        let mut matched_ids = Vec::with_capacity(all_docs.len());
+    /// Prepare to explain why archives matched a query given to [`Self::search`]
+    pub fn why(&self, query: &str, default_indexes: &[&str]) -> Result<Why> {
+        let reader = self.index.reader()?;
+        let searcher = reader.searcher();
 
-        for doc_address in all_docs {
-            let doc = searcher.doc_async(doc_address).await?;
-            let doc_id = doc.get_first(id_field).unwrap().as_u64().unwrap();
+        let schema = self.index.schema();
+        let default_indexes = default_indexes
+            .iter()
+            .map(|name| schema.get_field(name).unwrap())
+            .collect::<Vec<_>>();
 
-            matched_ids.push(doc_id as u32);
+        let query = QueryParser::for_index(&self.index, default_indexes).parse_query(query)?;
+
+        let mut generators = vec![];
+        for field in ["name", "artist", "parody", "tag"] {
+            let generator = SnippetGenerator::create(
+                &searcher,
+                query.as_ref(),
+                schema.get_field(field).unwrap(),
+            )?;
+            generators.push((field, generator));
         }
 
-        Ok(matched_ids)
+        Ok(Why { generators })
     }
 
     pub async fn search(
         &self,
         query: &str,
         default_indexes: &[&str],
-        max: Option<usize>,
-    ) -> Result<Vec<u32>> {
+        limit: Option<usize>,
+        offset: usize,
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        let indexes_s = default_indexes.join(",");
+        let limit_s = format!("{limit:?}+{offset}");
+        let pages_s = format!("{pages:?}");
+        let key = self.cache_key(&["search", query, &indexes_s, &limit_s, &pages_s])?;
+
+        if let Some(archives) = self.cache_get(&key) {
+            tracing::debug!(query, "Using cached search results");
+            return Ok(archives);
+        }
+
+        let archives = self
+            .search_uncached(query, default_indexes, limit, offset, pages)
+            .await?;
+        self.cache_put(&key, &archives)?;
+
+        Ok(archives)
+    }
+
+    async fn search_uncached(
+        &self,
+        query: &str,
+        default_indexes: &[&str],
+        limit: Option<usize>,
+        offset: usize,
+        pages: PageRange,
+    ) -> Result<Vec<Archive>> {
+        if limit == Some(0) {
+            return Ok(vec![]);
+        }
+
         let reader = self.index.reader()?;
         let searcher = reader.searcher();
 
@@ -125,37 +726,24 @@ impl Searcher {
         let query_parser = QueryParser::for_index(&self.index, default_indexes);
 
         let query = query_parser.parse_query(query)?;
+        let query = pages.restrict(schema.get_field("pages").unwrap(), query);
 
-        if let Some(max) = max {
-            let top_docs = searcher.search(&query, &TopDocs::with_limit(max))?;
-
-            let id_field = schema.get_field("id").unwrap();
-
-            let mut matched_ids = Vec::with_capacity(top_docs.len());
-
-            for (_score, doc_address) in top_docs {
-                let doc = searcher.doc_async(doc_address).await?;
-                let doc_id = doc.get_first(id_field).unwrap().as_u64().unwrap();
+        // skipping results needs them ranked, so rank everything if there's no limit
+        let limit = match limit {
+            None if offset > 0 => Some(searcher.num_docs().max(1) as usize),
+            limit => limit,
+        };
 
-                matched_ids.push(doc_id as u32);
-            }
+        if let Some(limit) = limit {
+            let top_docs =
+                searcher.search(&query, &TopDocs::with_limit(limit).and_offset(offset))?;
 
-            Ok(matched_ids)
+            self.load_archives(&searcher, top_docs.into_iter().map(|(_score, doc)| doc))
+                .await
         } else {
             let all_docs = searcher.search(&query, &DocSetCollector)?;
 
-            let id_field = schema.get_field("id").unwrap();
-
-            let mut matched_ids = Vec::with_capacity(all_docs.len());
-
-            for doc_address in all_docs {
-                let doc = searcher.doc_async(doc_address).await?;
-                let doc_id = doc.get_first(id_field).unwrap().as_u64().unwrap();
-
-                matched_ids.push(doc_id as u32);
-            }
-
-            Ok(matched_ids)
+            self.load_archives(&searcher, all_docs).await
         }
     }
 }
@@ -0,0 +1,354 @@
+use std::convert::Infallible;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use color_eyre::{eyre::eyre, Help, Result};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use ipnet::IpNet;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+use crate::config::config;
+use crate::filesystem::FileSystem;
+
+/// The settings the `[serve]` section of the config file may have
+pub const SETTINGS: [&str; 5] = ["users", "allow", "deny", "tls_cert", "tls_key"];
+
+/// The hash of a password as kept in `users`, salted with the user's name so
+/// users sharing a password don't share a hash
+pub fn hash_password(user: &str, password: &str) -> String {
+    Sha256::new()
+        .chain_update(user)
+        .chain_update(":")
+        .chain_update(password)
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Compare without stopping at the first difference, so how long a check
+/// takes doesn't give away how much of a guessed hash was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Who may use the server and how it's reached, from the `[serve]` section
+/// of the config file
+struct Settings {
+    /// Users and the hashes of their passwords, anyone may connect if empty
+    users: Vec<(String, String)>,
+    /// Addresses clients must connect from, any if empty
+    allow: Vec<IpNet>,
+    /// Addresses clients are turned away from, even if they're allowed
+    deny: Vec<IpNet>,
+    /// The certificate chain and private key to serve HTTPS with
+    tls: Option<(PathBuf, PathBuf)>,
+}
+
+fn parse_net(value: &str) -> Result<IpNet> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| {
+            eyre!("`{value}` under `[serve]` isn't an address or a range like `192.168.1.0/24`")
+        })
+}
+
+impl Settings {
+    fn load() -> Result<Self> {
+        let mut settings = Settings {
+            users: vec![],
+            allow: vec![],
+            deny: vec![],
+            tls: None,
+        };
+        let (mut cert, mut key) = (None, None);
+
+        for (name, values) in config().section("serve") {
+            match name {
+                "users" => {
+                    for value in values {
+                        let (user, hash) = value.split_once(':').ok_or_else(|| {
+                            eyre!("`{value}` under `[serve]` isn't a `user:hash` pair")
+                                .suggestion("Make one with `kscrpr config hash-password <user>`")
+                        })?;
+                        settings
+                            .users
+                            .push((user.to_owned(), hash.to_ascii_lowercase()));
+                    }
+                }
+                "allow" => {
+                    settings.allow = values.iter().map(|v| parse_net(v)).collect::<Result<_>>()?
+                }
+                "deny" => {
+                    settings.deny = values.iter().map(|v| parse_net(v)).collect::<Result<_>>()?
+                }
+                "tls_cert" => cert = values.first().map(PathBuf::from),
+                "tls_key" => key = values.first().map(PathBuf::from),
+                _ => {}
+            }
+        }
+
+        settings.tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                return Err(eyre!("Serving with TLS needs both a certificate and a key"))
+                    .suggestion("Set both `tls_cert` and `tls_key` under `[serve]`")
+            }
+        };
+
+        Ok(settings)
+    }
+
+    fn allows(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack socket show up as mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+
+    fn authorized(&self, req: &Request<Body>) -> bool {
+        if self.users.is_empty() {
+            return true;
+        }
+
+        let credentials = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| base64::decode(v.trim()).ok())
+            .and_then(|v| String::from_utf8(v).ok());
+        let (user, password) = match credentials.as_deref().and_then(|c| c.split_once(':')) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+
+        let hash = hash_password(user, password);
+        self.users
+            .iter()
+            .any(|(u, h)| u == user && constant_time_eq(h.as_bytes(), hash.as_bytes()))
+    }
+}
+
+fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let open = |path: &Path| -> Result<_> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| eyre!("Couldn't open {}: {e}", path.display()))?;
+        Ok(BufReader::new(file))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert)?)?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(eyre!("There are no certificates in {}", cert.display()));
+    }
+
+    let private_key = rustls_pemfile::read_all(&mut open(key)?)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| eyre!("There's no private key in {}", key.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match ext.as_deref() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("xml") => "application/atom+xml;profile=opds-catalog",
+        Some("pdf") => "application/pdf",
+        Some("epub") => "application/epub+zip",
+        Some("cbz") => "application/vnd.comicbook+zip",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::from(status.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Serves the gallery, OPDS catalog, thumbnails, and rendered files, and
+/// nothing else from the library, like its database
+pub struct Server {
+    settings: Settings,
+    /// `meta/`, which request paths are relative to as the gallery's links are
+    meta: PathBuf,
+    /// The files and directories requests may reach
+    served: Vec<PathBuf>,
+}
+
+impl Server {
+    pub fn new(fs: &FileSystem) -> Result<Self> {
+        let served = [
+            fs.gallery_file(),
+            fs.opds_file(),
+            fs.thumbs_dir(),
+            fs.rendered_dir(),
+        ]
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+
+        Ok(Server {
+            settings: Settings::load()?,
+            meta: fs.meta_dir().canonicalize()?,
+            served,
+        })
+    }
+
+    /// The file a request's path is for, if it's one that's served
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = match path.trim_start_matches('/') {
+            "" => "gallery.html",
+            path => path,
+        };
+
+        let file = Url::from_directory_path(&self.meta)
+            .ok()?
+            .join(path)
+            .ok()?
+            .to_file_path()
+            .ok()?
+            .canonicalize()
+            .ok()?;
+
+        let served = self.served.iter().any(|p| file.starts_with(p));
+        (served && file.is_file()).then(|| file)
+    }
+
+    fn respond(&self, req: &Request<Body>) -> Response<Body> {
+        if !self.settings.authorized(req) {
+            let mut response = status(StatusCode::UNAUTHORIZED);
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                "Basic realm=\"kscrpr\", charset=\"UTF-8\"".parse().unwrap(),
+            );
+            return response;
+        }
+
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let file = match self.resolve(req.uri().path()) {
+            Some(file) => file,
+            None => return status(StatusCode::NOT_FOUND),
+        };
+
+        match std::fs::read(&file) {
+            Ok(bytes) => {
+                let mut response = Response::new(Body::from(bytes));
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, content_type(&file).parse().unwrap());
+                response
+            }
+            Err(e) => {
+                tracing::warn!(file = %file.display(), error = %e, "Couldn't read a served file");
+                status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    /// Accept connections on `bind` until the process is stopped
+    pub async fn run(self, bind: SocketAddr) -> Result<()> {
+        // anyone who can reach the port could read the library otherwise
+        if self.settings.users.is_empty() && !bind.ip().is_loopback() {
+            return Err(eyre!("Refusing to serve on {bind} without a login")).suggestion(
+                "Add `users` under `[serve]` in the config file with \
+                 `kscrpr config hash-password <user>`, or bind to 127.0.0.1",
+            );
+        }
+
+        let tls = match &self.settings.tls {
+            Some((cert, key)) => Some(tls_acceptor(cert, key)?),
+            None if !self.settings.users.is_empty() && !bind.ip().is_loopback() => {
+                tracing::warn!(
+                    "Passwords are sent in the clear without TLS, set `tls_cert` and `tls_key` under `[serve]`"
+                );
+                None
+            }
+            None => None,
+        };
+
+        let listener = TcpListener::bind(bind)
+            .await
+            .map_err(|e| eyre!("Couldn't listen on {bind}: {e}"))?;
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        eprintln!("Serving the gallery at {scheme}://{bind}/");
+
+        let server = Arc::new(self);
+
+        loop {
+            let (stream, peer) = listener.accept().await?;
+
+            // dropping the connection closes it before anything is read
+            if !server.settings.allows(peer.ip()) {
+                tracing::debug!(%peer, "Turned away a connection");
+                continue;
+            }
+
+            let server = server.clone();
+            let tls = tls.clone();
+
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let response = server.respond(&req);
+                    async move { Ok::<_, Infallible>(response) }
+                });
+
+                let result = match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => Http::new().serve_connection(stream, service).await,
+                        Err(e) => {
+                            tracing::debug!(%peer, error = %e, "TLS handshake failed");
+                            return;
+                        }
+                    },
+                    None => Http::new().serve_connection(stream, service).await,
+                };
+
+                if let Err(e) = result {
+                    tracing::debug!(%peer, error = %e, "Connection failed");
+                }
+            });
+        }
+    }
+}
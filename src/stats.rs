@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use itertools::Itertools;
+
+use crate::filesystem::FileSystem;
+use crate::utils;
+
+/// Upper bounds of the page count histogram buckets, the last bucket holds
+/// everything bigger
+const PAGE_BUCKETS: [u64; 6] = [10, 25, 50, 100, 200, 500];
+
+#[derive(Default, serde::Serialize)]
+pub struct Stats {
+    pub archives: u64,
+    pub pages: u64,
+    pub artists: BTreeMap<String, u64>,
+    pub tags: BTreeMap<String, u64>,
+    pub parodies: BTreeMap<String, u64>,
+    /// Archive counts by page count range, i.e. `11-25`
+    pub page_histogram: Vec<(String, u64)>,
+    /// Archives added per month, as `YYYY-MM`
+    pub added_per_month: BTreeMap<String, u64>,
+    /// Archives added before timestamps were recorded
+    pub added_unknown: u64,
+}
+
+fn bucket_label(i: usize) -> String {
+    let low = if i == 0 { 1 } else { PAGE_BUCKETS[i - 1] + 1 };
+    match PAGE_BUCKETS.get(i) {
+        Some(high) => format!("{low}-{high}"),
+        None => format!("{low}+"),
+    }
+}
+
+pub fn collect(fs: &FileSystem) -> Result<Stats> {
+    let mut stats = Stats::default();
+    let mut buckets = [0; PAGE_BUCKETS.len() + 1];
+
+    for archive in fs.fetch_all() {
+        let archive = archive?;
+
+        stats.archives += 1;
+        stats.pages += archive.num_pages as u64;
+
+        for artist in archive.artists {
+            *stats.artists.entry(artist).or_default() += 1;
+        }
+        *stats.parodies.entry(archive.parody).or_default() += 1;
+        for tag in archive.tags {
+            *stats.tags.entry(tag.name).or_default() += 1;
+        }
+
+        let bucket = PAGE_BUCKETS
+            .iter()
+            .position(|high| archive.num_pages as u64 <= *high)
+            .unwrap_or(PAGE_BUCKETS.len());
+        buckets[bucket] += 1;
+
+        match archive.added_at {
+            Some(added_at) => {
+                let (year, month, _) = utils::civil_date(added_at);
+                *stats
+                    .added_per_month
+                    .entry(format!("{year:04}-{month:02}"))
+                    .or_default() += 1;
+            }
+            None => stats.added_unknown += 1,
+        }
+    }
+
+    stats.page_histogram = buckets
+        .iter()
+        .enumerate()
+        .map(|(i, count)| (bucket_label(i), *count))
+        .collect();
+
+    Ok(stats)
+}
+
+impl Stats {
+    pub fn lines(&self) -> Vec<String> {
+        fn by_count(counts: &BTreeMap<String, u64>) -> impl Iterator<Item = String> + '_ {
+            counts
+                .iter()
+                .sorted_by(|(n0, c0), (n1, c1)| c1.cmp(c0).then_with(|| n0.cmp(n1)))
+                .map(|(name, count)| format!("  {name}: {count}"))
+        }
+
+        let mut lines = vec![
+            format!("Total: {}", self.archives),
+            format!("Pages: {}", self.pages),
+            "Pages per archive:".to_owned(),
+        ];
+
+        lines.extend(
+            self.page_histogram
+                .iter()
+                .map(|(range, count)| format!("  {range}: {count}")),
+        );
+
+        lines.push("Added per month:".to_owned());
+        lines.extend(
+            self.added_per_month
+                .iter()
+                .map(|(month, count)| format!("  {month}: {count}")),
+        );
+        if self.added_unknown > 0 {
+            lines.push(format!("  unknown: {}", self.added_unknown));
+        }
+
+        lines.push("Artists:".to_owned());
+        lines.extend(by_count(&self.artists));
+        lines.push("Parodies:".to_owned());
+        lines.extend(by_count(&self.parodies));
+        lines.push("Tags:".to_owned());
+        lines.extend(by_count(&self.tags));
+
+        lines
+    }
+}
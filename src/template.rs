@@ -0,0 +1,248 @@
+use std::str::FromStr;
+
+use clap::ArgEnum;
+use itertools::Itertools;
+
+use crate::archive::Archive;
+use crate::filesystem::{FileSystem, RENDERED_EXTENSIONS};
+use crate::opts::OutputAsType;
+
+/// A `--format` template that `get` prints each archive with, i.e.
+/// `{id}\t{artist}\t{name}`
+///
+/// Placeholders are any of the `--output-as` kinds, like `{id}`, `{name}`,
+/// `{url}`, `{path}`, or `{data_path}`, along with `{artist}`, `{parody}`,
+/// `{tags}`, `{pages}`, `{added_at}` in unix seconds, and `{meta.<key>}` for
+/// custom metadata, which is empty if the archive doesn't have it. `{{` and
+/// `}}` are literal braces, and `\t` and `\n` are a tab and a newline as
+/// they're awkward to pass from a shell.
+#[derive(Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone)]
+enum Field {
+    Artist,
+    Parody,
+    Tags,
+    Pages,
+    AddedAt,
+    Meta(String),
+    Output(OutputAsType),
+}
+
+/// The placeholders that aren't `--output-as` kinds
+const FIELDS: [&str; 6] = [
+    "artist",
+    "parody",
+    "tags",
+    "pages",
+    "added_at",
+    "meta.<key>",
+];
+
+/// The placeholders a `--rendered-name-template` can use
+const NAME_FIELDS: [&str; 9] = [
+    "id",
+    "name",
+    "artist",
+    "parody",
+    "tags",
+    "pages",
+    "added_at",
+    "site",
+    "meta.<key>",
+];
+
+/// A piece of a template filled in for an archive, see [`Template::pieces`]
+pub enum Piece {
+    /// Literal text from the template
+    Text(String),
+    /// A placeholder that's always short, like `{id}` or `{pages}`
+    Short(String),
+    /// A placeholder that can be any length, like `{name}` or `{tags}`
+    Long(String),
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let field = match s.trim() {
+            "artist" => Field::Artist,
+            "parody" => Field::Parody,
+            "tags" => Field::Tags,
+            "pages" => Field::Pages,
+            "added_at" => Field::AddedAt,
+            other if other.starts_with("meta.") => Field::Meta(other["meta.".len()..].to_owned()),
+            other => match <OutputAsType as ArgEnum>::from_str(other, false) {
+                Ok(output_as) => Field::Output(output_as),
+                Err(_) => {
+                    let outputs = OutputAsType::value_variants()
+                        .iter()
+                        .filter_map(|o| o.to_possible_value())
+                        .map(|v| v.get_name());
+                    return Err(format!(
+                        "Unknown placeholder `{{{other}}}`, the placeholders are {}",
+                        FIELDS.into_iter().chain(outputs).format(", ")
+                    ));
+                }
+            },
+        };
+
+        Ok(field)
+    }
+}
+
+impl Field {
+    /// Whether the field can be filled in without knowing where files are,
+    /// so it can be used in a `--rendered-name-template`
+    fn in_names(&self) -> bool {
+        match self {
+            Field::Output(output_as) => matches!(
+                output_as,
+                OutputAsType::Id | OutputAsType::Name | OutputAsType::Site
+            ),
+            _ => true,
+        }
+    }
+
+    /// The value of the field for an archive, only the fields allowed by
+    /// `in_names` can be filled in without a [`FileSystem`]
+    fn value(&self, doc: &Archive, fs: Option<&FileSystem>) -> String {
+        match self {
+            Field::Artist => doc.artist(),
+            Field::Parody => doc.parody.clone(),
+            Field::Tags => doc.tags.iter().map(|t| &t.name).join(", "),
+            Field::Pages => doc.num_pages.to_string(),
+            Field::AddedAt => doc.added_at.map(|t| t.to_string()).unwrap_or_default(),
+            Field::Meta(key) => doc.custom.get(key).cloned().unwrap_or_default(),
+            Field::Output(OutputAsType::Id) => doc.id.to_string(),
+            Field::Output(OutputAsType::Name) => doc.name.clone(),
+            Field::Output(OutputAsType::Site) => doc.site().to_owned(),
+            Field::Output(output_as) => fs.map(|fs| output_as.format(doc, fs)).unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse_with(s, false)
+    }
+}
+
+impl Template {
+    /// Parse a `--rendered-name-template`, which can only use placeholders
+    /// that don't depend on where files are. The extension is added
+    /// according to `--render-format`, so any on the end is dropped.
+    pub fn parse_name(s: &str) -> Result<Self, String> {
+        let s = RENDERED_EXTENSIONS
+            .iter()
+            .find_map(|known| s.strip_suffix(&format!(".{known}")))
+            .unwrap_or(s);
+
+        Template::parse_with(s, true)
+    }
+
+    fn parse_with(s: &str, names_only: bool) -> Result<Self, String> {
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("`{{` without a closing `}}` in `{s}`"))?;
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    let field = rest[..end].parse::<Field>()?;
+                    if names_only && !field.in_names() {
+                        return Err(format!(
+                            "`{{{}}}` can't be used in file names, the placeholders are {}",
+                            rest[..end].trim(),
+                            NAME_FIELDS.iter().format(", ")
+                        ));
+                    }
+                    parts.push(Part::Field(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => {
+                    // `}}` is a brace, and so is one on its own
+                    if chars.as_str().starts_with('}') {
+                        chars.next();
+                    }
+                    text.push('}');
+                }
+                '\\' => match chars.next() {
+                    Some('t') => text.push('\t'),
+                    Some('n') => text.push('\n'),
+                    Some('\\') => text.push('\\'),
+                    other => {
+                        text.push('\\');
+                        text.extend(other);
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Fill in the placeholders for an archive
+    pub fn render(&self, doc: &Archive, fs: &FileSystem) -> String {
+        let mut out = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Field(field) => out.push_str(&field.value(doc, Some(fs))),
+            }
+        }
+
+        out
+    }
+
+    /// Fill in the placeholders of a template from [`Template::parse_name`]
+    /// for an archive, keeping the pieces apart so each can be sanitized and
+    /// truncated on its own
+    pub fn pieces(&self, doc: &Archive) -> Vec<Piece> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => Piece::Text(text.clone()),
+                Part::Field(field) => {
+                    let value = field.value(doc, None);
+                    match field {
+                        Field::Pages
+                        | Field::AddedAt
+                        | Field::Output(OutputAsType::Id)
+                        | Field::Output(OutputAsType::Site) => Piece::Short(value),
+                        _ => Piece::Long(value),
+                    }
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,297 @@
+use std::str::FromStr;
+
+use clap::ArgEnum;
+use color_eyre::{eyre::eyre, Help, Result};
+use crossterm::tty::IsTty;
+use indicatif::ProgressStyle;
+use once_cell::sync::OnceCell;
+
+use crate::config::config;
+use crate::opts::opts;
+
+/// A color in the picker and progress bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeColor {
+    /// Whatever the terminal's default color is
+    Reset,
+    /// One of the 256 ANSI colors, the first 16 of which the terminal picks
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl ThemeColor {
+    pub fn tui(self) -> tui::style::Color {
+        match self {
+            ThemeColor::Reset => tui::style::Color::Reset,
+            ThemeColor::Ansi(n) => tui::style::Color::Indexed(n),
+            ThemeColor::Rgb(r, g, b) => tui::style::Color::Rgb(r, g, b),
+        }
+    }
+
+    /// The escape code that sets the color as the foreground color
+    fn ansi_escape(self) -> Option<String> {
+        match self {
+            ThemeColor::Reset => None,
+            ThemeColor::Ansi(n) => Some(format!("\x1b[38;5;{n}m")),
+            ThemeColor::Rgb(r, g, b) => Some(format!("\x1b[38;2;{r};{g};{b}m")),
+        }
+    }
+
+    /// The color as an indicatif template style, progress bars only support
+    /// 256 colors so RGB colors are approximated
+    fn progress_spec(self) -> Option<u8> {
+        match self {
+            ThemeColor::Reset => None,
+            ThemeColor::Ansi(n) => Some(n),
+            ThemeColor::Rgb(r, g, b) => {
+                let level = |c: u8| (c as u16 * 5 / 255) as u8;
+                Some(16 + 36 * level(r) + 6 * level(g) + level(b))
+            }
+        }
+    }
+}
+
+impl FromStr for ThemeColor {
+    type Err = String;
+
+    /// Parse a color name like `cyan` or `bright-red`, an ANSI color number,
+    /// `#rrggbb`, or `none` for the terminal's default color
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const NAMES: [&str; 8] = [
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+
+        let s = s.trim().to_ascii_lowercase();
+
+        if s == "none" || s == "default" {
+            return Ok(ThemeColor::Reset);
+        }
+
+        if let Some(hex) = s.strip_prefix('#') {
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    .ok_or_else(|| format!("`#{hex}` isn't of the form #rrggbb"))
+            };
+            if hex.len() != 6 {
+                return Err(format!("`#{hex}` isn't of the form #rrggbb"));
+            }
+            return Ok(ThemeColor::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+
+        if let Ok(n) = s.parse() {
+            return Ok(ThemeColor::Ansi(n));
+        }
+
+        let (bright, name) = match s.strip_prefix("bright-") {
+            Some(name) => (8, name),
+            None => (0, s.as_str()),
+        };
+
+        NAMES
+            .iter()
+            .position(|n| *n == name)
+            .map(|n| ThemeColor::Ansi(n as u8 + bright))
+            .ok_or_else(|| format!("Unknown color `{s}`"))
+    }
+}
+
+/// The built in color palettes
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq)]
+#[clap(rename_all = "snake_case")]
+pub enum ThemeName {
+    /// Teal on dark terminals
+    Dark,
+    /// Darker colors that stay readable on light terminals
+    Light,
+    /// No colors at all
+    Mono,
+}
+
+/// The parts of the output a theme colors
+#[derive(ArgEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[clap(rename_all = "snake_case")]
+pub enum ThemeRole {
+    /// Titles, artists, and progress bar prefixes
+    #[clap(alias = "title")]
+    Label,
+    /// Tags and other secondary text
+    #[clap(alias = "tag")]
+    Value,
+    /// Spinners and elapsed times
+    Accent,
+    /// The marker beside the highlighted item in the picker and browser
+    Highlight,
+    /// Headings and counts in the picker and browser, and counts in lists
+    Dim,
+    /// Errors shown in the picker's status bar
+    Error,
+}
+
+/// Parse a `role=color` override of a theme color
+pub fn parse_override(s: &str) -> Result<(ThemeRole, ThemeColor), String> {
+    let (role, color) = s
+        .split_once('=')
+        .ok_or_else(|| format!("`{s}` isn't of the form role=color"))?;
+
+    Ok((<ThemeRole as ArgEnum>::from_str(role.trim(), true)?, color.parse()?))
+}
+
+pub struct Theme {
+    pub label: ThemeColor,
+    pub value: ThemeColor,
+    pub accent: ThemeColor,
+    pub highlight: ThemeColor,
+    pub dim: ThemeColor,
+    pub error: ThemeColor,
+}
+
+impl Theme {
+    pub fn named(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Theme {
+                label: ThemeColor::Rgb(73, 159, 147),
+                value: ThemeColor::Rgb(32, 178, 170),
+                accent: ThemeColor::Ansi(2),
+                highlight: ThemeColor::Reset,
+                dim: ThemeColor::Rgb(32, 178, 170),
+                error: ThemeColor::Ansi(1),
+            },
+            ThemeName::Light => Theme {
+                label: ThemeColor::Rgb(0, 95, 95),
+                value: ThemeColor::Rgb(0, 110, 140),
+                accent: ThemeColor::Ansi(4),
+                highlight: ThemeColor::Reset,
+                dim: ThemeColor::Rgb(0, 110, 140),
+                error: ThemeColor::Ansi(1),
+            },
+            ThemeName::Mono => Theme {
+                label: ThemeColor::Reset,
+                value: ThemeColor::Reset,
+                accent: ThemeColor::Reset,
+                highlight: ThemeColor::Reset,
+                dim: ThemeColor::Reset,
+                error: ThemeColor::Reset,
+            },
+        }
+    }
+
+    fn color(&self, role: ThemeRole) -> ThemeColor {
+        match role {
+            ThemeRole::Label => self.label,
+            ThemeRole::Value => self.value,
+            ThemeRole::Accent => self.accent,
+            ThemeRole::Highlight => self.highlight,
+            ThemeRole::Dim => self.dim,
+            ThemeRole::Error => self.error,
+        }
+    }
+
+    /// `text` in the color of `role` for printing to stdout, left plain if
+    /// stdout isn't a terminal so piped output has no escape codes in it
+    pub fn paint(&self, role: ThemeRole, text: &str) -> String {
+        match self.color(role).ansi_escape() {
+            Some(escape) if std::io::stdout().is_tty() => format!("{escape}{text}\x1b[0m"),
+            _ => text.to_owned(),
+        }
+    }
+
+    fn color_mut(&mut self, role: ThemeRole) -> &mut ThemeColor {
+        match role {
+            ThemeRole::Label => &mut self.label,
+            ThemeRole::Value => &mut self.value,
+            ThemeRole::Accent => &mut self.accent,
+            ThemeRole::Highlight => &mut self.highlight,
+            ThemeRole::Dim => &mut self.dim,
+            ThemeRole::Error => &mut self.error,
+        }
+    }
+
+    /// A progress bar style from a template, with `{label}`, `{value}`, and
+    /// `{accent}` replaced by the theme's colors, i.e. `{spinner:{accent}}`
+    pub fn progress_style(&self, template: &str) -> ProgressStyle {
+        let spec = |color: ThemeColor| {
+            color
+                .progress_spec()
+                .map_or_else(String::new, |n| format!(".{n}"))
+        };
+
+        let template = match (self.label.progress_spec(), self.value.progress_spec()) {
+            (Some(label), Some(value)) => {
+                template.replace("{label/value}", &format!(".{label}/{value}"))
+            }
+            // a bar with only one half colored looks broken, so leave it plain
+            _ => template.replace(":{label/value}", ""),
+        };
+
+        let template = template
+            .replace("{label}", &spec(self.label))
+            .replace("{value}", &spec(self.value))
+            .replace("{accent}", &spec(self.accent))
+            .replace(":}", "}");
+
+        ProgressStyle::with_template(&template).unwrap()
+    }
+}
+
+/// Whether the terminal says it can show RGB colors
+fn supports_truecolor() -> bool {
+    matches!(
+        std::env::var("COLORTERM").as_deref(),
+        Ok("truecolor" | "24bit")
+    )
+}
+
+/// The first of `colors` the terminal can show, or else the last of them
+fn first_supported(colors: &[ThemeColor]) -> Option<ThemeColor> {
+    colors
+        .iter()
+        .find(|c| !matches!(c, ThemeColor::Rgb(..)) || supports_truecolor())
+        .or_else(|| colors.last())
+        .copied()
+}
+
+/// The theme picked with `--theme`, with the colors from the `[theme]` section
+/// of the config file and then any `--theme-color` overrides applied
+fn read() -> Result<Theme> {
+    let opts = opts();
+    let mut theme = Theme::named(opts.theme);
+
+    // each role is given a color or an array of them, where RGB colors are
+    // only used on terminals that support them, i.e. `title = ["#499f93", "30"]`
+    for (role, colors) in config().section("theme") {
+        let parsed = <ThemeRole as ArgEnum>::from_str(role, true)
+            .map_err(|e| eyre!("Unknown theme color `{role}`: {e}"))
+            .suggestion("The colors are title, tag, accent, highlight, dim, and error")?;
+        let colors = colors
+            .iter()
+            .map(|c| c.parse())
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| eyre!("{e} in the theme for `{role}`"))?;
+
+        if let Some(color) = first_supported(&colors) {
+            *theme.color_mut(parsed) = color;
+        }
+    }
+
+    for (role, color) in &opts.theme_colors {
+        *theme.color_mut(*role) = *color;
+    }
+
+    Ok(theme)
+}
+
+static THEME: OnceCell<Theme> = OnceCell::new();
+
+/// Work out the theme, so mistakes in the config file are reported before
+/// anything is drawn
+pub fn load() -> Result<()> {
+    THEME.get_or_try_init(read)?;
+
+    Ok(())
+}
+
+/// The theme, or the plain `--theme` if it hasn't been loaded
+pub fn theme() -> &'static Theme {
+    THEME.get_or_init(|| Theme::named(opts().theme))
+}
@@ -1,6 +1,7 @@
+use std::cmp::Ordering;
 use std::sync::atomic::AtomicBool;
 
-use color_eyre::Report;
+use color_eyre::{Report, Result};
 
 pub static RUNNING: AtomicBool = AtomicBool::new(true);
 
@@ -8,6 +9,199 @@ pub fn fuck_error(report: &Report) -> &(dyn std::error::Error + 'static) {
     report.as_ref()
 }
 
+/// The current time in seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The UTC year, month, and day of a unix timestamp
+pub fn civil_date(secs: u64) -> (i64, u32, u32) {
+    // days to civil date, from Howard Hinnant's date algorithms
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// A unix timestamp as an ISO 8601 UTC date and time, i.e. `2022-07-01T12:00:00Z`
+pub fn iso8601(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let time = secs % 86400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
 pub fn user_has_quit() -> bool {
     !RUNNING.load(std::sync::atomic::Ordering::Relaxed)
 }
+
+/// Ask a yes/no question on stderr, defaulting to no
+pub fn confirm(prompt: &str) -> Result<bool> {
+    use std::io::Write;
+
+    eprint!("{prompt} [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Replace any `-` argument with the non-empty lines read from stdin
+pub fn expand_stdin_args(args: &[String]) -> Result<Vec<String>> {
+    use std::io::BufRead;
+
+    let mut out = Vec::with_capacity(args.len());
+
+    for arg in args {
+        if arg == "-" {
+            for line in std::io::stdin().lock().lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    out.push(line.to_owned());
+                }
+            }
+        } else {
+            out.push(arg.clone());
+        }
+    }
+
+    Ok(out)
+}
+
+/// Compare strings the way people number things, so `page2` comes before
+/// `page10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            // only differing in leading zeros, which still need an order
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x: String =
+                    std::iter::from_fn(|| a_chars.next_if(char::is_ascii_digit)).collect();
+                let y: String =
+                    std::iter::from_fn(|| b_chars.next_if(char::is_ascii_digit)).collect();
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+/// Score how well `pattern` fuzzy matches `text` ignoring case, like fzf: the
+/// pattern's characters have to appear in order, with runs of them and ones
+/// starting words scoring higher. `None` if it doesn't match
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<u32> {
+    let mut pattern = pattern.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut run = 0;
+    let mut prev = ' ';
+
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match pattern.peek() {
+            None => break,
+            Some(&p) if p == c => {
+                run += 1;
+                score += run + if prev.is_alphanumeric() { 0 } else { 2 };
+                pattern.next();
+            }
+            Some(_) => run = 0,
+        }
+        prev = c;
+    }
+
+    pattern.peek().is_none().then(|| score)
+}
+
+/// Parse an image quality, from 1 for the smallest files to 100 for the best
+/// looking
+pub fn parse_quality(s: &str) -> Result<u8, String> {
+    match s.trim().parse::<u8>() {
+        Ok(quality @ 1..=100) => Ok(quality),
+        _ => Err(format!("`{s}` isn't a quality from 1 to 100")),
+    }
+}
+
+/// Parse a DPI, which has to be positive for pages to have a size
+pub fn parse_dpi(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(dpi) if dpi > 0.0 && dpi.is_finite() => Ok(dpi),
+        _ => Err(format!("`{s}` isn't a positive DPI")),
+    }
+}
+
+/// Parse a screen resolution like `1236x1648`, as width by height in pixels
+pub fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let parsed = s
+        .trim()
+        .split_once(|c| c == 'x' || c == 'X')
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("`{s}` isn't a resolution like `1236x1648`")),
+    }
+}
+
+/// Parse a size like `200G`, `512M`, or `1.5T` into bytes, using binary units.
+/// A bare number is rejected rather than taken as bytes, `500` is far more
+/// likely to mean megabytes than half a kilobyte, so bytes need a `B`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+
+    let num = num
+        .parse::<f64>()
+        .map_err(|_| format!("`{s}` doesn't start with a number"))?;
+
+    let unit = unit.trim().to_ascii_uppercase();
+    if unit.is_empty() {
+        return Err(format!(
+            "`{s}` has no unit, give one like `{s}M` or `{s}G`, or `{s}B` for bytes"
+        ));
+    }
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown unit in `{s}`, expected K, M, G, or T")),
+    };
+
+    Ok((num * (1u64 << shift) as f64) as u64)
+}